@@ -5,6 +5,14 @@ use serde::Deserialize;
 #[derive(Deserialize)]
 struct Config {
     landmarks: Vec<Landmark>,
+    #[serde(default)]
+    magnetometer: Magnetometer,
+    #[serde(default)]
+    gps: Gps,
+    #[serde(default)]
+    time: Time,
+    #[serde(default)]
+    track: Track,
 }
 
 impl Config {
@@ -15,13 +23,168 @@ impl Config {
         format!(
             r#"
                 pub const LANDMARKS: [crate::landmark::Landmark; {}] = [{}];
+                {}
+                {}
+                {}
+                {}
             "#,
             size,
             self.landmarks
                 .into_iter()
                 .map(|landmark| landmark.rustify())
                 .collect::<Vec<_>>()
-                .join(",\n")
+                .join(",\n"),
+            self.magnetometer.rustify(),
+            self.gps.rustify(),
+            self.time.rustify(),
+            self.track.rustify()
+        )
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct Magnetometer {
+    /// Samples per second, one of 10, 50, 100 or 200.
+    output_data_rate: u32,
+    /// One of 64, 128, 256 or 512.
+    oversample: u32,
+    /// Added to the temperature sensor reading in °C.
+    temperature_offset: f32,
+    /// Learn how the hard-iron offset drifts with temperature from successive calibrations.
+    thermal_compensation: bool,
+}
+
+impl Default for Magnetometer {
+    fn default() -> Self {
+        Magnetometer {
+            output_data_rate: 50,
+            oversample: 512,
+            temperature_offset: 0.,
+            thermal_compensation: false,
+        }
+    }
+}
+
+impl Magnetometer {
+    fn rustify(self) -> String {
+        let odr = match self.output_data_rate {
+            10 | 50 | 100 | 200 => self.output_data_rate,
+            odr => panic!("Invalid magnetometer output_data_rate {odr}, use 10, 50, 100 or 200"),
+        };
+        let osr = match self.oversample {
+            64 | 128 | 256 | 512 => self.oversample,
+            osr => panic!("Invalid magnetometer oversample {osr}, use 64, 128, 256 or 512"),
+        };
+
+        format!(
+            r#"
+                pub const MAG_OUTPUT_DATA_RATE: crate::qmc5883l::OutputDataRate =
+                    crate::qmc5883l::OutputDataRate::Rate{}Hz;
+                pub const MAG_OVERSAMPLE: crate::qmc5883l::OversampleRate =
+                    crate::qmc5883l::OversampleRate::Rate{};
+                pub const MAG_TEMPERATURE_OFFSET: f32 = {:?};
+                pub const MAG_THERMAL_COMPENSATION: bool = {};
+            "#,
+            odr, osr, self.temperature_offset, self.thermal_compensation
+        )
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Gps {
+    /// The receiver's TIMEPULSE output is wired to GPIO5.
+    timepulse: bool,
+}
+
+impl Gps {
+    fn rustify(self) -> String {
+        format!(
+            r#"
+                pub const GPS_TIMEPULSE: bool = {};
+            "#,
+            self.timepulse
+        )
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct Time {
+    /// POSIX TZ string, e.g. "NZST-12NZDT,M9.5.0,M4.1.0/3".
+    zone: Option<String>,
+    /// Without a zone, use the nautical zone of the current longitude rather than UTC.
+    zone_from_longitude: bool,
+    /// 12 or 24.
+    hour_format: u32,
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Time {
+            zone: None,
+            zone_from_longitude: false,
+            hour_format: 24,
+        }
+    }
+}
+
+impl Time {
+    fn rustify(self) -> String {
+        let hour_format = match self.hour_format {
+            12 => "H12",
+            24 => "H24",
+            format => panic!("Invalid time hour_format {format}, use 12 or 24"),
+        };
+
+        format!(
+            r#"
+                pub const TIME_ZONE: Option<&str> = {:?};
+                pub const TIME_ZONE_FROM_LONGITUDE: bool = {};
+                pub const HOUR_FORMAT: crate::timezone::HourFormat =
+                    crate::timezone::HourFormat::{};
+            "#,
+            self.zone, self.zone_from_longitude, hour_format
+        )
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct Track {
+    /// Log a point at least this many seconds apart.
+    interval: u32,
+    /// Or once moved this many metres from the last one.
+    distance: f64,
+}
+
+impl Default for Track {
+    fn default() -> Self {
+        Track {
+            interval: 10,
+            distance: 10.,
+        }
+    }
+}
+
+impl Track {
+    fn rustify(self) -> String {
+        assert!(
+            self.interval > 0,
+            "Invalid track interval, must be at least 1 s"
+        );
+        assert!(
+            self.distance > 0.,
+            "Invalid track distance, must be more than 0 m"
+        );
+
+        format!(
+            r#"
+                pub const TRACK_INTERVAL: u32 = {};
+                pub const TRACK_DISTANCE: f64 = {:?};
+            "#,
+            self.interval, self.distance
         )
     }
 }
@@ -53,9 +216,12 @@ impl Landmark {
 }
 
 fn main() {
-    linker_be_nice();
-    // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
-    println!("cargo:rustc-link-arg=-Tlinkall.x");
+    // The host tests without the `esp` feature link as usual
+    if std::env::var_os("CARGO_FEATURE_ESP").is_some() {
+        linker_be_nice();
+        // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
+        println!("cargo:rustc-link-arg=-Tlinkall.x");
+    }
 
     let config: Config =
         toml::from_slice(&std::fs::read(std::env::var("COMPASS_CONFIG").unwrap()).unwrap())
@@ -24,9 +24,7 @@ impl App {
             }
 
             // Update ui and display
-            for command in self.ui.process() {
-                self.display.execute(command);
-            }
+            self.display.draw(&mut self.ui);
 
             // Sleep
             ticker.next().await;
@@ -0,0 +1,389 @@
+//! Assistance for faster fixes.
+//!
+//! An AssistNow Offline or almanac file, a sequence of UBX MGA messages, is uploaded over the USB
+//! serial console into the `assist` partition. When the receiver is first found it gets the
+//! time and the last known fix as a rough position (MGA-INI), then the stored messages for the
+//! current day. Time to first fix is timed from boot, with the aiding it had.
+//!
+//! The partition starts with [`MAGIC`] and the length of the file, written only once the whole
+//! file has arrived and framed cleanly, so an interrupted upload leaves no file.
+
+use core::cell::Cell;
+use core::fmt;
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use critical_section::Mutex;
+use embassy_time::{Duration, Instant};
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::persist::{self, LastFix};
+use crate::receiver::NavPvtState;
+use crate::ubx::{Frame, Framer};
+
+/// Offset of the `assist` partition, which must match `partitions.csv`.
+pub const PARTITION_OFFSET: u32 = 0x2c_0000;
+/// Size of the `assist` partition, which must match `partitions.csv`.
+pub const PARTITION_SIZE: u32 = 0x4_0000;
+
+/// Starts the partition when it holds a file.
+pub const MAGIC: [u8; 4] = *b"AST1";
+const HEADER_LEN: u32 = 8;
+
+/// Bytes written to or read from flash at once, a multiple of the flash write size.
+pub const CHUNK_LEN: usize = 256;
+
+pub const CLASS_MGA: u8 = 0x13;
+pub const ID_MGA_INI: u8 = 0x40;
+pub const ID_MGA_ANO: u8 = 0x20;
+
+/// Accuracy given with the position of the last fix, which may have been carried a long way
+/// while asleep. Still close enough to narrow down the satellites in view.
+pub const POSITION_ACCURACY_CM: u32 = 10_000_000;
+
+/// Accuracy given with the time, which free ran from the RTC through deep sleep.
+pub const TIME_ACCURACY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error<E> {
+    /// The file does not fit in the partition.
+    TooLarge,
+    /// The file is not made of whole UBX frames with valid checksums.
+    NotUbx,
+    /// Underlying flash error.
+    Flash(E),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::Flash(e)
+    }
+}
+
+/// The assistance file in flash.
+pub struct AssistStore<F> {
+    flash: F,
+    offset: u32,
+    size: u32,
+}
+
+impl<F: NorFlash> AssistStore<F> {
+    /// The store in the `size` bytes of `flash` from `offset`.
+    pub fn new(flash: F, offset: u32, size: u32) -> Self {
+        assert!(CHUNK_LEN.is_multiple_of(F::WRITE_SIZE) && CHUNK_LEN.is_multiple_of(F::READ_SIZE));
+        assert!((size as usize).is_multiple_of(F::ERASE_SIZE));
+        Self {
+            flash,
+            offset,
+            size,
+        }
+    }
+
+    /// Gives the flash back.
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /// Length of the stored file, None if there is none.
+    pub fn len(&mut self) -> Result<Option<u32>, F::Error> {
+        let mut header = [0u8; HEADER_LEN as usize];
+        self.flash.read(self.offset, &mut header)?;
+        let len = u32::from_le_bytes(header[4..].try_into().unwrap());
+        Ok((header[..4] == MAGIC && len <= self.size - HEADER_LEN).then_some(len))
+    }
+
+    /// Reads the chunk of the stored file from `start`, a multiple of [`CHUNK_LEN`], returning
+    /// the bytes of the file in it.
+    pub fn read<'b>(
+        &mut self,
+        len: u32,
+        start: u32,
+        buf: &'b mut [u8; CHUNK_LEN],
+    ) -> Result<&'b [u8], F::Error> {
+        let end = (start + CHUNK_LEN as u32).min(len);
+        let file_len = end.saturating_sub(start) as usize;
+        // The tail of the last chunk is padding
+        let read_len = file_len.next_multiple_of(F::READ_SIZE);
+        self.flash
+            .read(self.offset + HEADER_LEN + start, &mut buf[..read_len])?;
+        Ok(&buf[..file_len])
+    }
+
+    /// Erases the store for a file of `len` bytes, to be written with [`AssistStore::write`].
+    pub fn begin(&mut self, len: u32) -> Result<Upload, Error<F::Error>> {
+        if len == 0 || len > self.size - HEADER_LEN {
+            return Err(Error::TooLarge);
+        }
+        self.flash.erase(self.offset, self.offset + self.size)?;
+        Ok(Upload {
+            len,
+            received: 0,
+            framed: 0,
+            framer: Framer::new(),
+            chunk: [0; CHUNK_LEN],
+            chunk_len: 0,
+        })
+    }
+
+    /// Adds the next bytes of the file, returning how many of `bytes` it took. Once it is
+    /// complete the rest are left for the caller.
+    pub fn write(&mut self, upload: &mut Upload, bytes: &[u8]) -> Result<usize, F::Error> {
+        let take = bytes.len().min((upload.len - upload.received) as usize);
+        for &byte in &bytes[..take] {
+            if let Some(frame) = upload.framer.push(byte) {
+                upload.framed += frame.as_bytes().len() as u32;
+            }
+            upload.chunk[upload.chunk_len] = byte;
+            upload.chunk_len += 1;
+            upload.received += 1;
+            if upload.chunk_len == CHUNK_LEN {
+                self.write_chunk(upload)?;
+            }
+        }
+        Ok(take)
+    }
+
+    /// Writes the header once the whole file has arrived, making it the stored file. Returns the
+    /// number of messages in it.
+    pub fn finish(&mut self, mut upload: Upload) -> Result<u32, Error<F::Error>> {
+        if !upload.is_complete() || upload.framed != upload.len {
+            return Err(Error::NotUbx);
+        }
+        if upload.chunk_len > 0 {
+            self.write_chunk(&mut upload)?;
+        }
+
+        let mut header = [0u8; HEADER_LEN as usize];
+        header[..4].copy_from_slice(&MAGIC);
+        header[4..].copy_from_slice(&upload.len.to_le_bytes());
+        self.flash.write(self.offset, &header)?;
+        Ok(upload.framer.stats().packets)
+    }
+
+    /// Erases the stored file.
+    pub fn clear(&mut self) -> Result<(), F::Error> {
+        self.flash.erase(self.offset, self.offset + self.size)
+    }
+
+    fn write_chunk(&mut self, upload: &mut Upload) -> Result<(), F::Error> {
+        let start = upload.received - upload.chunk_len as u32;
+        // Erased bytes pad the last write
+        let len = upload.chunk_len.next_multiple_of(F::WRITE_SIZE);
+        upload.chunk[upload.chunk_len..len].fill(0xff);
+        self.flash
+            .write(self.offset + HEADER_LEN + start, &upload.chunk[..len])?;
+        upload.chunk_len = 0;
+        Ok(())
+    }
+}
+
+/// A file being uploaded, see [`AssistStore::begin`].
+pub struct Upload {
+    len: u32,
+    received: u32,
+    /// Bytes in complete frames.
+    framed: u32,
+    framer: Framer,
+    chunk: [u8; CHUNK_LEN],
+    chunk_len: usize,
+}
+
+impl Upload {
+    pub fn is_complete(&self) -> bool {
+        self.received == self.len
+    }
+}
+
+/// Whether `frame` is worth sending on `today`: AssistNow Offline data is for one day each, any
+/// other message always is. Without a date everything is.
+pub fn is_current(frame: &Frame<'_>, today: Option<NaiveDate>) -> bool {
+    let (Some(today), CLASS_MGA, ID_MGA_ANO) = (today, frame.class(), frame.id()) else {
+        return true;
+    };
+    match frame.payload() {
+        [_, _, _, _, year, month, day, ..] => {
+            NaiveDate::from_ymd_opt(2000 + *year as i32, *month as u32, *day as u32) == Some(today)
+        }
+        _ => false,
+    }
+}
+
+/// MGA-INI-POS_LLH payload: a position in 1e-7 degrees and cm above the ellipsoid, with its
+/// accuracy in cm.
+pub fn mga_ini_pos_llh(hint: &PositionHint, accuracy_cm: u32) -> [u8; 20] {
+    let mut payload = [0u8; 20];
+    // Type, then version 0
+    payload[0] = 0x01;
+    payload[4..8].copy_from_slice(&hint.latitude.to_le_bytes());
+    payload[8..12].copy_from_slice(&hint.longitude.to_le_bytes());
+    payload[12..16].copy_from_slice(&hint.height.to_le_bytes());
+    payload[16..20].copy_from_slice(&accuracy_cm.to_le_bytes());
+    payload
+}
+
+/// MGA-INI-TIME_UTC payload: `utc` as of its arrival, good to `accuracy`.
+pub fn mga_ini_time_utc(utc: NaiveDateTime, accuracy: Duration) -> [u8; 24] {
+    let mut payload = [0u8; 24];
+    // Type, then version 0 and no external time reference
+    payload[0] = 0x10;
+    // Leap seconds unknown
+    payload[3] = i8::MIN as u8;
+    payload[4..6].copy_from_slice(&(utc.year() as u16).to_le_bytes());
+    payload[6] = utc.month() as u8;
+    payload[7] = utc.day() as u8;
+    payload[8] = utc.hour() as u8;
+    payload[9] = utc.minute() as u8;
+    payload[10] = utc.second() as u8;
+    payload[12..16].copy_from_slice(&utc.nanosecond().min(999_999_999).to_le_bytes());
+    let accuracy_ms = accuracy.as_millis();
+    payload[16..18]
+        .copy_from_slice(&((accuracy_ms / 1000).min(u16::MAX as u64) as u16).to_le_bytes());
+    payload[20..24].copy_from_slice(&((accuracy_ms % 1000) as u32 * 1_000_000).to_le_bytes());
+    payload
+}
+
+/// A rough position for the receiver, from the last known fix.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PositionHint {
+    /// Latitude in 1e-7 degrees.
+    pub latitude: i32,
+    /// Longitude in 1e-7 degrees.
+    pub longitude: i32,
+    /// Height above the ellipsoid in cm.
+    pub height: i32,
+}
+
+impl PositionHint {
+    pub fn from_fix(fix: &LastFix) -> Self {
+        Self {
+            latitude: libm::round(fix.latitude * 1e7) as i32,
+            longitude: libm::round(fix.longitude * 1e7) as i32,
+            height: libm::round(fix.height * 100.) as i32,
+        }
+    }
+}
+
+/// The last known fix as a hint, restored from before deep sleep until the receiver has a fix.
+pub fn hint() -> Option<PositionHint> {
+    persist::last_fix().map(|fix| PositionHint::from_fix(&fix))
+}
+
+/// Aiding sent to the receiver.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Aiding {
+    pub time: bool,
+    pub position: bool,
+    /// Messages sent from the stored file.
+    pub messages: u32,
+}
+
+/// Time to first fix since boot.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FirstFix {
+    pub started: Option<Instant>,
+    pub aiding: Aiding,
+    /// None until the first fix good enough to navigate by.
+    pub time_to_fix: Option<Duration>,
+}
+
+impl FirstFix {
+    pub const fn new() -> Self {
+        Self {
+            started: None,
+            aiding: Aiding {
+                time: false,
+                position: false,
+                messages: 0,
+            },
+            time_to_fix: None,
+        }
+    }
+
+    /// Starts timing at `at`.
+    pub fn start(&mut self, at: Instant) {
+        *self = Self {
+            started: Some(at),
+            ..Self::new()
+        };
+    }
+
+    /// Records a fix at `at`, returning the time to first fix if it is the first.
+    pub fn fix(&mut self, at: Instant) -> Option<Duration> {
+        if self.time_to_fix.is_some() {
+            return None;
+        }
+        self.time_to_fix = Some(at.checked_duration_since(self.started?)?);
+        self.time_to_fix
+    }
+}
+
+impl fmt::Display for FirstFix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.time_to_fix {
+            Some(time) => write!(
+                f,
+                "First fix after {}.{} s",
+                time.as_secs(),
+                time.as_millis() % 1000 / 100
+            )?,
+            None => f.write_str("No fix yet")?,
+        }
+        let Aiding {
+            time,
+            position,
+            messages,
+        } = self.aiding;
+        write!(
+            f,
+            ", aided with time {} position {} messages {}",
+            if time { "yes" } else { "no" },
+            if position { "yes" } else { "no" },
+            messages
+        )
+    }
+}
+
+impl Default for FirstFix {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub static FIRST_FIX: Mutex<Cell<FirstFix>> = Mutex::new(Cell::new(FirstFix::new()));
+
+/// Starts timing the first fix.
+pub fn start(at: Instant) {
+    critical_section::with(|cs| {
+        let cell = FIRST_FIX.borrow(cs);
+        let mut first_fix = cell.get();
+        first_fix.start(at);
+        cell.set(first_fix);
+    })
+}
+
+/// Records the aiding sent to the receiver.
+pub fn aided(aiding: Aiding) {
+    critical_section::with(|cs| {
+        let cell = FIRST_FIX.borrow(cs);
+        let mut first_fix = cell.get();
+        first_fix.aiding = aiding;
+        cell.set(first_fix);
+    })
+}
+
+/// Records `state` if it has a fix to navigate by, logging the time to first fix if it is the
+/// first.
+pub fn update(state: &NavPvtState) {
+    let Some(received) = state.received.filter(|_| state.position().is_some()) else {
+        return;
+    };
+    let first_fix = critical_section::with(|cs| {
+        let cell = FIRST_FIX.borrow(cs);
+        let mut first_fix = cell.get();
+        let time_to_fix = first_fix.fix(received);
+        cell.set(first_fix);
+        time_to_fix.map(|_| first_fix)
+    });
+    if let Some(first_fix) = first_fix {
+        log::info!("{}", first_fix);
+    }
+}
@@ -8,18 +8,24 @@
 
 use compass::app::App;
 use compass::button::button_task;
+use compass::clock::{self, timepulse_task};
 use compass::compass::compass_task;
 use compass::display::Display;
+use compass::generated;
 use compass::gps::gps_task;
+use compass::persist;
+use compass::power::{self, PowerMode};
+use compass::println;
+use compass::track::track_task;
 use defmt_rtt as _;
 use embassy_executor::Spawner;
+use embassy_time::{Duration, Instant, Timer};
 use esp_hal::clock::CpuClock;
 use esp_hal::gpio::{self, InputConfig, RtcPinWithResistors};
 use esp_hal::peripherals::*;
 use esp_hal::rtc_cntl::sleep::{Ext1WakeupSource, WakeupLevel};
 use esp_hal::rtc_cntl::Rtc;
 use esp_hal::timer::systimer::SystemTimer;
-use esp_println::println;
 
 #[panic_handler]
 fn panic(_: &core::panic::PanicInfo) -> ! {
@@ -41,18 +47,32 @@ async fn main(spawner: Spawner) -> ! {
     esp_hal_embassy::init(timer0.alarm0);
     println!("Embassy initialized!");
 
+    // After deep sleep the clock carries on from RTC memory until the receiver has a time, and
+    // the compass points from the last known fix until it has a new one
+    let mut lpwr = peripherals.LPWR;
+    clock::restore(Rtc::new(lpwr.reborrow()).current_time_us());
+    if persist::restore() {
+        println!("Restored last known fix, landmark and calibration");
+    }
+    spawner.must_spawn(button_deep_sleep_task(peripherals.GPIO4, lpwr));
+
     spawner.must_spawn(gps_task(
         peripherals.UART1,
         peripherals.GPIO17,
         peripherals.GPIO16,
     ));
+    if generated::GPS_TIMEPULSE {
+        spawner.must_spawn(timepulse_task(peripherals.GPIO5));
+    }
     spawner.must_spawn(compass_task(
         peripherals.I2C0,
         peripherals.GPIO22,
         peripherals.GPIO23,
+        peripherals.GPIO3,
     ));
 
     spawner.must_spawn(button_task(peripherals.GPIO2));
+    spawner.must_spawn(track_task(peripherals.USB_DEVICE));
 
     App::new(
         Display::new(
@@ -70,21 +90,59 @@ async fn main(spawner: Spawner) -> ! {
     .await
 }
 
+/// How long to wait for the receiver to enter backup mode before going to sleep regardless.
+const BACKUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long the sleep button must stay pressed to count, and stay released before sleeping.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
 /// Task that awaits button press to shutdown the esp
+///
+/// The button pulls GPIO4 low against the internal pull-up, the same way round as the UI
+/// button, so both the press and the wakeup are a low level.
 #[embassy_executor::task]
-async fn _button_deep_sleep_task(mut pin: GPIO4<'static>, lpwr: LPWR<'static>) -> ! {
+async fn button_deep_sleep_task(mut pin: GPIO4<'static>, lpwr: LPWR<'static>) -> ! {
     println!("Started Button Deep Sleep Task");
 
     let mut rtc = Rtc::new(lpwr);
 
-    let mut button = gpio::Input::new(
-        pin.reborrow(),
-        InputConfig::default().with_pull(gpio::Pull::Down),
-    );
-    button.wait_for_any_edge().await;
-    // button.wait_for_falling_edge().await;
-    core::mem::drop(button);
+    // The input gives the pin back for the wakeup source at the end of this block
+    {
+        let mut button = gpio::Input::new(
+            pin.reborrow(),
+            InputConfig::default().with_pull(gpio::Pull::Up),
+        );
+        // Ignore bounces and glitches shorter than the debounce time
+        loop {
+            button.wait_for_low().await;
+            Timer::after(DEBOUNCE).await;
+            if button.is_low() {
+                break;
+            }
+        }
+        // Sleeping while still held would wake straight away on the low level
+        loop {
+            button.wait_for_high().await;
+            Timer::after(DEBOUNCE).await;
+            if button.is_high() {
+                break;
+            }
+        }
+    }
+
+    // Backup mode keeps the receiver's ephemeris and time for a hot start on waking
+    power::request_sleep();
+    let deadline = Instant::now() + BACKUP_TIMEOUT;
+    while power::mode() != PowerMode::Backup && Instant::now() < deadline {
+        Timer::after(Duration::from_millis(50)).await;
+    }
+
+    clock::save(rtc.current_time_us());
+    persist::save();
 
+    // The pad is held through deep sleep, keep it pulled up until the next press
+    pin.rtcio_pullup(true);
+    pin.rtcio_pulldown(false);
     let wakeup_pins: &mut [(&mut dyn RtcPinWithResistors, WakeupLevel)] =
         &mut [(&mut pin, WakeupLevel::Low)];
     let ext1 = Ext1WakeupSource::new(wakeup_pins);
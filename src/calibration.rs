@@ -0,0 +1,335 @@
+//! Hard-iron and soft-iron magnetometer calibration.
+//!
+//! Samples are collected while the device is rotated through as many orientations as possible and
+//! fitted to a general ellipsoid. The centre of the ellipsoid is the hard-iron offset and the
+//! shape of the ellipsoid gives the soft-iron matrix that maps it back onto a sphere.
+//!
+//! Nothing in here touches hardware so the fit can be exercised with synthetic data.
+
+use libm::sqrt;
+
+/// How long `compass_task` collects samples before attempting a fit, in seconds. Long enough to
+/// turn the device through every orientation.
+pub const CALIBRATION_SECS: u32 = 30;
+
+/// Minimum number of samples required by [`Calibrator::fit`].
+pub const MIN_SAMPLES: usize = 30;
+
+/// Readings are divided by this before being accumulated to keep the normal equations well
+/// conditioned. The earth's field is roughly 25 to 65 µT.
+const SCALE: f64 = 64.0;
+
+/// Two calibrations must be at least this far apart in °C to estimate thermal drift from.
+pub const MIN_DRIFT_SPAN: f32 = 5.;
+
+/// Number of unknowns in the quadric fit.
+const N: usize = 9;
+
+/// Number of samples collected over [`CALIBRATION_SECS`] at `rate_hz`, the magnetometer's output
+/// data rate.
+pub const fn calibration_samples(rate_hz: u32) -> usize {
+    (rate_hz * CALIBRATION_SECS) as usize
+}
+
+/// Calibration Error
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// Fewer than [`MIN_SAMPLES`] samples were collected.
+    NotEnoughSamples(usize),
+    /// Samples do not describe an ellipsoid, usually because the device was not rotated enough.
+    Degenerate,
+}
+
+/// Correction applied to magnetometer readings in µT.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    /// Hard-iron offset in µT.
+    pub offset: (f32, f32, f32),
+    /// Soft-iron correction matrix, applied after removing the offset.
+    pub soft_iron: [[f32; 3]; 3],
+    /// Mean temperature in °C while fitting, None if the magnetometer has no temperature sensor.
+    pub temp: Option<f32>,
+    /// Change in hard-iron offset in µT/°C away from `temp`.
+    pub drift: (f32, f32, f32),
+}
+
+impl Calibration {
+    /// Calibration that leaves readings untouched.
+    pub const fn new() -> Self {
+        Self {
+            offset: (0., 0., 0.),
+            soft_iron: [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]],
+            temp: None,
+            drift: (0., 0., 0.),
+        }
+    }
+
+    /// Returns true if this calibration does not alter readings.
+    pub fn is_identity(&self) -> bool {
+        *self == Self::new()
+    }
+
+    /// Hard-iron offset in µT at `temp` °C, corrected for thermal drift when both are known.
+    pub fn offset_at(&self, temp: Option<f32>) -> (f32, f32, f32) {
+        match (temp, self.temp) {
+            (Some(t), Some(t0)) => (
+                self.offset.0 + self.drift.0 * (t - t0),
+                self.offset.1 + self.drift.1 * (t - t0),
+                self.offset.2 + self.drift.2 * (t - t0),
+            ),
+            _ => self.offset,
+        }
+    }
+
+    /// Applies hard-iron and soft-iron correction to an (x,y,z) reading in µT taken at `temp` °C.
+    pub fn apply(&self, mag: (f32, f32, f32), temp: Option<f32>) -> (f32, f32, f32) {
+        let offset = self.offset_at(temp);
+        let v = [mag.0 - offset.0, mag.1 - offset.1, mag.2 - offset.2];
+        let m = &self.soft_iron;
+        (
+            m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+            m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+            m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+        )
+    }
+
+    /// Estimates thermal drift from the change in offset since `previous`.
+    ///
+    /// The two must have been fitted at least [`MIN_DRIFT_SPAN`] apart, otherwise the drift of
+    /// `previous` is carried over unchanged.
+    pub fn with_drift_from(mut self, previous: &Calibration) -> Self {
+        self.drift = previous.drift;
+        if let (Some(t), Some(t0)) = (self.temp, previous.temp) {
+            let dt = t - t0;
+            if dt.abs() >= MIN_DRIFT_SPAN {
+                self.drift = (
+                    (self.offset.0 - previous.offset.0) / dt,
+                    (self.offset.1 - previous.offset.1) / dt,
+                    (self.offset.2 - previous.offset.2) / dt,
+                );
+            }
+        }
+        self
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Accumulates samples for an ellipsoid fit.
+///
+/// Fits `ax² + by² + cz² + 2dxy + 2exz + 2fyz + 2gx + 2hy + 2iz = 1` by least squares. Only the
+/// normal equations are kept so memory use does not grow with the number of samples.
+pub struct Calibrator {
+    ata: [[f64; N]; N],
+    atb: [f64; N],
+    samples: usize,
+    temp_sum: f32,
+    temp_samples: usize,
+}
+
+impl Calibrator {
+    pub const fn new() -> Self {
+        Self {
+            ata: [[0.; N]; N],
+            atb: [0.; N],
+            samples: 0,
+            temp_sum: 0.,
+            temp_samples: 0,
+        }
+    }
+
+    /// Number of samples added so far.
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// Adds an (x,y,z) reading in µT taken at `temp` °C to the fit.
+    pub fn add_sample(&mut self, mag: (f32, f32, f32), temp: Option<f32>) {
+        if let Some(temp) = temp {
+            self.temp_sum += temp;
+            self.temp_samples += 1;
+        }
+
+        let x = mag.0 as f64 / SCALE;
+        let y = mag.1 as f64 / SCALE;
+        let z = mag.2 as f64 / SCALE;
+        let row = [
+            x * x,
+            y * y,
+            z * z,
+            2. * x * y,
+            2. * x * z,
+            2. * y * z,
+            2. * x,
+            2. * y,
+            2. * z,
+        ];
+
+        for i in 0..N {
+            for j in 0..N {
+                self.ata[i][j] += row[i] * row[j];
+            }
+            self.atb[i] += row[i];
+        }
+        self.samples += 1;
+    }
+
+    /// Fits the collected samples.
+    ///
+    /// The returned calibration maps readings onto a sphere whose radius is the geometric mean of
+    /// the fitted ellipsoid radii, so corrected readings stay in µT.
+    pub fn fit(&self) -> Result<Calibration, Error> {
+        if self.samples < MIN_SAMPLES {
+            return Err(Error::NotEnoughSamples(self.samples));
+        }
+
+        let p = solve(self.ata, self.atb).ok_or(Error::Degenerate)?;
+
+        let m = [[p[0], p[3], p[4]], [p[3], p[1], p[5]], [p[4], p[5], p[2]]];
+        let v = [p[6], p[7], p[8]];
+
+        // Centre of the ellipsoid is where the gradient vanishes: M c = -v
+        let c = solve(m, [-v[0], -v[1], -v[2]]).ok_or(Error::Degenerate)?;
+
+        // (u - c)ᵀ M (u - c) = 1 + cᵀ M c
+        let k = 1. + dot(c, mul3(m, c));
+        if k.is_nan() || k <= 0. {
+            return Err(Error::Degenerate);
+        }
+
+        let (eigenvalues, eigenvectors) = eigen_symmetric(m);
+        let mut sqrt_eigenvalues = [0.; 3];
+        let mut det = 1.;
+        for i in 0..3 {
+            let lambda = eigenvalues[i] / k;
+            if lambda.is_nan() || lambda <= 0. {
+                return Err(Error::Degenerate);
+            }
+            sqrt_eigenvalues[i] = sqrt(lambda);
+            det *= lambda;
+        }
+
+        // Geometric mean radius of the ellipsoid, in scaled units.
+        let radius = 1. / sqrt(libm::cbrt(det));
+
+        // W = V diag(√λ) Vᵀ · r
+        let mut soft_iron = [[0f32; 3]; 3];
+        for (i, row) in soft_iron.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                let mut sum = 0.;
+                for (k, sqrt_lambda) in sqrt_eigenvalues.iter().enumerate() {
+                    sum += eigenvectors[i][k] * sqrt_lambda * eigenvectors[j][k];
+                }
+                *value = (sum * radius) as f32;
+            }
+        }
+
+        Ok(Calibration {
+            offset: (
+                (c[0] * SCALE) as f32,
+                (c[1] * SCALE) as f32,
+                (c[2] * SCALE) as f32,
+            ),
+            soft_iron,
+            temp: (self.temp_samples > 0).then(|| self.temp_sum / self.temp_samples as f32),
+            drift: (0., 0., 0.),
+        })
+    }
+}
+
+impl Default for Calibrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn mul3(m: [[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    [dot(m[0], v), dot(m[1], v), dot(m[2], v)]
+}
+
+/// Solves `a x = b` by Gaussian elimination with partial pivoting.
+fn solve<const S: usize>(mut a: [[f64; S]; S], mut b: [f64; S]) -> Option<[f64; S]> {
+    let scale = a
+        .iter()
+        .flat_map(|row| row.iter())
+        .fold(0f64, |acc, v| acc.max(v.abs()));
+    let epsilon = scale * 1e-12;
+
+    for col in 0..S {
+        let pivot = (col..S).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].is_nan() || a[pivot][col].abs() <= epsilon {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        let pivot_row = a[col];
+        for row in col + 1..S {
+            let factor = a[row][col] / pivot_row[col];
+            for (value, p) in a[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *value -= factor * p;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = [0.; S];
+    for row in (0..S).rev() {
+        let mut sum = b[row];
+        for k in row + 1..S {
+            sum -= a[row][k] * x[k];
+        }
+        x[row] = sum / a[row][row];
+    }
+    Some(x)
+}
+
+/// Eigen decomposition of a symmetric 3x3 matrix using cyclic Jacobi rotations.
+///
+/// Returns the eigenvalues and a matrix whose columns are the matching eigenvectors.
+fn eigen_symmetric(mut a: [[f64; 3]; 3]) -> ([f64; 3], [[f64; 3]; 3]) {
+    let mut v = [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]];
+
+    for _ in 0..32 {
+        let off = a[0][1] * a[0][1] + a[0][2] * a[0][2] + a[1][2] * a[1][2];
+        if off < 1e-30 {
+            break;
+        }
+
+        for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+            if a[p][q] == 0. {
+                continue;
+            }
+            let theta = (a[q][q] - a[p][p]) / (2. * a[p][q]);
+            let t = theta.signum() / (theta.abs() + sqrt(theta * theta + 1.));
+            let c = 1. / sqrt(t * t + 1.);
+            let s = t * c;
+
+            for row in a.iter_mut() {
+                let akp = row[p];
+                let akq = row[q];
+                row[p] = c * akp - s * akq;
+                row[q] = s * akp + c * akq;
+            }
+            let (ap, aq) = (a[p], a[q]);
+            a[p] = core::array::from_fn(|k| c * ap[k] - s * aq[k]);
+            a[q] = core::array::from_fn(|k| s * ap[k] + c * aq[k]);
+            for row in v.iter_mut() {
+                let vkp = row[p];
+                let vkq = row[q];
+                row[p] = c * vkp - s * vkq;
+                row[q] = s * vkp + c * vkq;
+            }
+        }
+    }
+
+    ([a[0][0], a[1][1], a[2][2]], v)
+}
@@ -0,0 +1,227 @@
+//! Wall clock disciplined by the GNSS receiver.
+//!
+//! UTC is latched from each NAV-PVT (or RMC) whose date and time are fully resolved, and when the
+//! receiver's TIMEPULSE output is wired up, from the edge that marks the start of that epoch.
+//! In between the clock free runs from [`Instant`], so it keeps going through a lost fix. Before
+//! deep sleep it is kept in RTC memory together with the RTC timer, which counts while asleep.
+
+use core::cell::Cell;
+
+#[cfg(feature = "esp")]
+use crate::println;
+use chrono::{DurationRound, NaiveDateTime, TimeDelta};
+use critical_section::Mutex;
+use embassy_time::{Duration, Instant};
+#[cfg(feature = "esp")]
+use esp_hal::{
+    gpio::{Input, InputConfig, Pull},
+    peripherals::*,
+    ram,
+};
+
+pub static CLOCK: Mutex<Cell<Clock>> = Mutex::new(Cell::new(Clock::new()));
+
+/// Longest a TIMEPULSE edge can come before the NAV-PVT of its epoch. Under a second, so a
+/// message held up past the next edge is not matched to it.
+pub const PULSE_WINDOW: Duration = Duration::from_millis(500);
+
+/// Where the clock was last set from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    /// TIMEPULSE edge, good to the interrupt latency.
+    Pulse,
+    /// Arrival of the navigation message, late by the receiver's output delay.
+    Message,
+    /// RTC timer after deep sleep, which drifts by up to a few seconds an hour.
+    Rtc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Latch {
+    /// UTC at `at`.
+    pub utc: NaiveDateTime,
+    pub at: Instant,
+    pub source: Source,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Clock {
+    latch: Option<Latch>,
+    /// Last TIMEPULSE edge not yet matched to an epoch.
+    pulse: Option<Instant>,
+}
+
+impl Clock {
+    pub const fn new() -> Self {
+        Self {
+            latch: None,
+            pulse: None,
+        }
+    }
+
+    /// Last time the clock was set, None if it never was.
+    pub fn last_latch(&self) -> Option<Latch> {
+        self.latch
+    }
+
+    /// Records a TIMEPULSE rising edge.
+    pub fn pulse(&mut self, at: Instant) {
+        self.pulse = Some(at);
+    }
+
+    /// Sets the clock to `utc`, the time of a navigation epoch whose message arrived at
+    /// `received`. A TIMEPULSE edge up to [`PULSE_WINDOW`] before marks the top of the second
+    /// the epoch was measured at.
+    pub fn latch(&mut self, utc: NaiveDateTime, received: Instant) {
+        let pulse = self.pulse.take().filter(|pulse| {
+            received
+                .checked_duration_since(*pulse)
+                .is_some_and(|delay| delay <= PULSE_WINDOW)
+        });
+
+        self.latch = Some(match pulse {
+            Some(at) => Latch {
+                // The epoch time is off the second by the receiver's clock bias
+                utc: utc.duration_round(TimeDelta::seconds(1)).unwrap_or(utc),
+                at,
+                source: Source::Pulse,
+            },
+            None => Latch {
+                utc,
+                at: received,
+                source: Source::Message,
+            },
+        });
+    }
+
+    /// UTC as of `now`, None if the clock was never set.
+    pub fn now_at(&self, now: Instant) -> Option<NaiveDateTime> {
+        let latch = self.latch?;
+        let elapsed = now.as_micros() as i64 - latch.at.as_micros() as i64;
+        latch
+            .utc
+            .checked_add_signed(TimeDelta::microseconds(elapsed))
+    }
+
+    /// Sets the clock to `utc` from the RTC at `at`, unless a fix already has.
+    pub fn restore(&mut self, utc: NaiveDateTime, at: Instant) {
+        if self.latch.is_none() {
+            self.latch = Some(Latch {
+                utc,
+                at,
+                source: Source::Rtc,
+            });
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// UTC now, None until the receiver has given a fully resolved time.
+pub fn now_utc() -> Option<NaiveDateTime> {
+    critical_section::with(|cs| CLOCK.borrow(cs).get()).now_at(Instant::now())
+}
+
+/// Latches `utc` received at `received`, see [`Clock::latch`].
+pub fn latch(utc: NaiveDateTime, received: Instant) {
+    critical_section::with(|cs| {
+        let cell = CLOCK.borrow(cs);
+        let mut clock = cell.get();
+        clock.latch(utc, received);
+        cell.set(clock);
+    })
+}
+
+/// Marks a saved clock, RTC fast memory holds garbage after power on.
+const SAVED_MAGIC: u64 = 0x636c_6f63_6b21_5554;
+
+/// The clock as kept through deep sleep, against the RTC timer which keeps counting while asleep.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Saved {
+    pub utc: NaiveDateTime,
+    /// RTC timer in µs at `utc`, see `Rtc::current_time_us`.
+    pub rtc_us: u64,
+}
+
+impl Saved {
+    /// UTC in µs since the Unix epoch, the RTC timer in µs, and a check word.
+    pub fn to_words(&self) -> [u64; 3] {
+        let utc_us = self.utc.and_utc().timestamp_micros() as u64;
+        [utc_us, self.rtc_us, utc_us ^ self.rtc_us ^ SAVED_MAGIC]
+    }
+
+    /// None unless `words` came from [`Saved::to_words`].
+    pub fn from_words(words: [u64; 3]) -> Option<Self> {
+        let [utc_us, rtc_us, check] = words;
+        if check != utc_us ^ rtc_us ^ SAVED_MAGIC {
+            return None;
+        }
+        let utc = chrono::DateTime::from_timestamp_micros(utc_us as i64)?.naive_utc();
+        Some(Self { utc, rtc_us })
+    }
+
+    /// UTC when the RTC timer reads `rtc_us`, None if it went backwards, as after a reset.
+    pub fn utc_at(&self, rtc_us: u64) -> Option<NaiveDateTime> {
+        let slept = rtc_us.checked_sub(self.rtc_us)?;
+        self.utc
+            .checked_add_signed(TimeDelta::microseconds(i64::try_from(slept).ok()?))
+    }
+}
+
+/// See [`Saved::to_words`].
+#[cfg(feature = "esp")]
+#[ram(unstable(rtc_fast, persistent))]
+static mut SAVED: [u64; 3] = [0; 3];
+
+/// Keeps the clock in RTC memory for [`restore`]. `rtc_us` is the RTC timer, see
+/// `Rtc::current_time_us`.
+#[cfg(feature = "esp")]
+pub fn save(rtc_us: u64) {
+    let Some(utc) = now_utc() else {
+        return;
+    };
+    // SAFETY: only touched from here and `restore`, before tasks start and before deep sleep
+    unsafe {
+        core::ptr::addr_of_mut!(SAVED).write_volatile(Saved { utc, rtc_us }.to_words());
+    }
+}
+
+/// Sets the clock from RTC memory after deep sleep, if [`save`] was called before.
+#[cfg(feature = "esp")]
+pub fn restore(rtc_us: u64) {
+    // SAFETY: see `save`
+    let words = unsafe { core::ptr::addr_of!(SAVED).read_volatile() };
+    let Some(utc) = Saved::from_words(words).and_then(|saved| saved.utc_at(rtc_us)) else {
+        return;
+    };
+    critical_section::with(|cs| {
+        let cell = CLOCK.borrow(cs);
+        let mut clock = cell.get();
+        clock.restore(utc, Instant::now());
+        cell.set(clock);
+    });
+    println!("Clock restored to {}", utc);
+}
+
+/// Timestamps the receiver's TIMEPULSE, one rising edge at the top of each UTC second.
+#[cfg(feature = "esp")]
+#[embassy_executor::task]
+pub async fn timepulse_task(pin: GPIO5<'static>) -> ! {
+    println!("Started Timepulse Task");
+
+    let mut timepulse = Input::new(pin, InputConfig::default().with_pull(Pull::Down));
+    loop {
+        timepulse.wait_for_rising_edge().await;
+        let now = Instant::now();
+        critical_section::with(|cs| {
+            let cell = CLOCK.borrow(cs);
+            let mut clock = cell.get();
+            clock.pulse(now);
+            cell.set(clock);
+        });
+    }
+}
@@ -1,34 +1,90 @@
 use core::cell::Cell;
 
-use crate::qmc5883l::QMC5883L;
+use crate::calibration::{calibration_samples, Calibration, Calibrator};
+use crate::gps::NAV_PVT_STATE;
+pub use crate::heading::NavCompassState;
+use crate::heading::{heading, nav_compass_state, tilt_compensated_heading};
+use crate::magnetometer::{self, AutoRange, Error, Magnetometer, Polarity, Sample};
+use crate::mpu6050::MPU6050;
+use crate::persist::LAST_FIX;
+use crate::println;
+use crate::{generated, landmark};
 use critical_section::Mutex;
-use embassy_time::{Duration, Ticker};
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex};
+use embassy_time::{with_timeout, Delay, Duration, Timer};
 use esp_hal::{
+    gpio::{Input, InputConfig, Pull},
     i2c::master::{Config, I2c},
     peripherals::*,
 };
-use esp_println::println;
 
 #[derive(Debug, Clone, Copy)]
 pub struct CompassState {
-    pub temp: i16,
-    pub mag: (i16, i16, i16),
+    /// Temperature in °C, None if the magnetometer has no temperature sensor.
+    pub temp: Option<f32>,
+    /// Raw (x,y,z) counts as read from the magnetometer.
+    pub raw: (i16, i16, i16),
+    /// Full scale in gauss of the field range `raw` was read at.
+    pub range: f32,
+    /// (x,y,z) in µT with the current [`Calibration`] applied.
+    pub mag: (f32, f32, f32),
+    /// (x,y,z) acceleration in g and device axes, None without an accelerometer.
+    pub accel: Option<(f32, f32, f32)>,
+    /// Magnetic heading in radians, tilt compensated when an accelerometer is fitted.
+    pub heading: f32,
 }
 
-pub struct NavCompassState {
-    pub temp: i16,
-    pub north_dir: f32,
-    pub target_dir: f32,
-    pub screen_offset: f32,
+pub static COMPASS_STATE: Mutex<Cell<CompassState>> = Mutex::new(Cell::new(CompassState {
+    temp: None,
+    raw: (0, 0, 0),
+    range: 0.,
+    mag: (0., 0., 0.),
+    accel: None,
+    heading: 0.,
+}));
+
+/// Magnetometer sample counters, for diagnostics.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MagDiagnostics {
+    /// Samples read successfully.
+    pub samples: u32,
+    /// Reads where the data was not ready yet.
+    pub not_ready: u32,
+    /// Reads dropped because an axis overflowed.
+    pub overflow: u32,
+    /// Times DRDY did not signal within a few sample periods.
+    pub drdy_timeouts: u32,
 }
 
-pub static COMPASS_STATE: Mutex<Cell<CompassState>> = Mutex::new(Cell::new(CompassState {
-    temp: 0,
-    mag: (0, 0, 0),
+pub static MAG_DIAGNOSTICS: Mutex<Cell<MagDiagnostics>> = Mutex::new(Cell::new(MagDiagnostics {
+    samples: 0,
+    not_ready: 0,
+    overflow: 0,
+    drdy_timeouts: 0,
 }));
 
+/// None without a compass reading, or without a position fix good enough to navigate by (see
+/// [`crate::gps::FixQuality`]) now or before.
+pub static NAV_COMPASS_STATE: Mutex<Cell<Option<NavCompassState>>> = Mutex::new(Cell::new(None));
+
+pub static CALIBRATION: Mutex<Cell<Calibration>> = Mutex::new(Cell::new(Calibration::new()));
+
+static CALIBRATION_REQUESTED: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// Asks `compass_task` to start collecting samples for a new calibration.
+/// The device should be rotated through as many orientations as possible until it completes.
+pub fn request_calibration() {
+    critical_section::with(|cs| CALIBRATION_REQUESTED.borrow(cs).set(true));
+}
+
 #[embassy_executor::task]
-pub async fn compass_task(i2c: I2C0<'static>, sda: GPIO22<'static>, scl: GPIO23<'static>) -> ! {
+pub async fn compass_task(
+    i2c: I2C0<'static>,
+    sda: GPIO22<'static>,
+    scl: GPIO23<'static>,
+    drdy: GPIO3<'static>,
+) -> ! {
     println!("Started Compass Task");
 
     let config = Config::default();
@@ -38,20 +94,177 @@ pub async fn compass_task(i2c: I2C0<'static>, sda: GPIO22<'static>, scl: GPIO23<
         .with_scl(scl)
         .into_async();
 
-    let mut qmc5883l = QMC5883L::new(i2c).unwrap();
-    qmc5883l.continuous().unwrap();
+    // The magnetometer and accelerometer share the bus
+    let i2c = mutex::Mutex::<NoopRawMutex, _>::new(i2c);
+
+    let mut magnetometer = magnetometer::detect(I2cDevice::new(&i2c)).await.unwrap();
+    println!("Found {} magnetometer", magnetometer.name());
+    magnetometer.start().await.unwrap();
+
+    let polarity = magnetometer.drdy();
+    let pull = match polarity {
+        Some(Polarity::ActiveLow) => Pull::Up,
+        _ => Pull::Down,
+    };
+    let mut drdy = Input::new(drdy, InputConfig::default().with_pull(pull));
+    let sample_period = Duration::from_hz(generated::MAG_OUTPUT_DATA_RATE.hz() as u64);
+    // If DRDY is not wired up fall back to reading once every few sample periods
+    let drdy_timeout = sample_period * 3;
+    let calibration_samples = calibration_samples(generated::MAG_OUTPUT_DATA_RATE.hz());
+
+    let mut mpu6050 = match MPU6050::new_async(I2cDevice::new(&i2c), &mut Delay).await {
+        Ok(mpu6050) => Some(mpu6050),
+        Err(err) => {
+            println!(
+                "No accelerometer, compass must be held level. Err:{:?}",
+                err
+            );
+            None
+        }
+    };
 
-    let mut ticker = Ticker::every(Duration::from_millis(1000));
+    // Nothing has been calibrated yet so start straight away
+    if critical_section::with(|cs| CALIBRATION.borrow(cs).get().is_identity()) {
+        request_calibration();
+    }
+    let mut calibrator: Option<Calibrator> = None;
+    let mut auto_range = AutoRange::new();
 
     loop {
-        if let Ok(mag) = qmc5883l.mag() {
-            if let Ok(temp) = qmc5883l.temp() {
-                critical_section::with(|cs| {
-                    println!("mag:{:?}temp:{}", mag, temp);
-                    COMPASS_STATE.borrow(cs).set(CompassState { temp, mag });
-                });
+        let ready = match polarity {
+            // DRDY stays high until the data is read, so waiting on the level can't miss an edge
+            Some(Polarity::ActiveHigh) => with_timeout(drdy_timeout, drdy.wait_for_high())
+                .await
+                .is_ok(),
+            Some(Polarity::ActiveLow) => with_timeout(drdy_timeout, drdy.wait_for_falling_edge())
+                .await
+                .is_ok(),
+            None => {
+                Timer::after(sample_period).await;
+                true
             }
+        };
+        if !ready {
+            critical_section::with(|cs| {
+                let diagnostics = MAG_DIAGNOSTICS.borrow(cs);
+                let mut d = diagnostics.get();
+                d.drdy_timeouts += 1;
+                diagnostics.set(d);
+            });
+        }
+
+        if critical_section::with(|cs| CALIBRATION_REQUESTED.borrow(cs).replace(false)) {
+            println!("Calibrating compass, rotate the device");
+            calibrator = Some(Calibrator::new());
         }
-        ticker.next().await;
+
+        let sample = magnetometer.sample().await;
+        critical_section::with(|cs| {
+            let diagnostics = MAG_DIAGNOSTICS.borrow(cs);
+            let mut d = diagnostics.get();
+            match sample {
+                Ok(_) => d.samples += 1,
+                Err(Error::NotReady) => d.not_ready += 1,
+                Err(Error::Overflow) => d.overflow += 1,
+                Err(_) => {}
+            }
+            diagnostics.set(d);
+        });
+
+        let range = magnetometer.ranges()[magnetometer.range()];
+        if let Some(new_range) =
+            auto_range.update(magnetometer.ranges(), magnetometer.range(), &sample)
+        {
+            println!(
+                "Switching magnetometer to ±{} gauss",
+                magnetometer.ranges()[new_range]
+            );
+            if let Err(err) = magnetometer.set_range(new_range).await {
+                println!("Failed to switch magnetometer range. Err:{:?}", err);
+            }
+        }
+
+        let Ok(Sample { raw, mag, temp }) = sample else {
+            continue;
+        };
+
+        if let Some(c) = calibrator.as_mut() {
+            c.add_sample(mag, temp);
+            if c.samples() >= calibration_samples {
+                match c.fit() {
+                    Ok(calibration) => {
+                        critical_section::with(|cs| {
+                            let previous = CALIBRATION.borrow(cs).get();
+                            let calibration = if generated::MAG_THERMAL_COMPENSATION {
+                                calibration.with_drift_from(&previous)
+                            } else {
+                                calibration
+                            };
+                            println!("Compass calibrated:{:?}", calibration);
+                            CALIBRATION.borrow(cs).set(calibration);
+                        });
+                    }
+                    Err(err) => println!("Compass calibration failed:{:?}", err),
+                }
+                calibrator = None;
+            }
+        }
+
+        let accel = match mpu6050.as_mut() {
+            Some(mpu6050) => mpu6050.accel_async().await.ok().map(device_axes),
+            None => None,
+        };
+
+        let (mag, nav_pvt_state, last_fix) = critical_section::with(|cs| {
+            (
+                CALIBRATION.borrow(cs).get().apply(mag, temp),
+                NAV_PVT_STATE.borrow(cs).get(),
+                LAST_FIX.borrow(cs).get(),
+            )
+        });
+        let heading = match accel {
+            Some(accel) => tilt_compensated_heading(mag, accel),
+            None => heading(mag),
+        };
+        // Without a fix, the declination where it was last known
+        let declination = nav_pvt_state
+            .declination()
+            .or_else(|| last_fix.map(|fix| fix.declination))
+            .filter(|declination| declination.is_finite())
+            .unwrap_or(0.) as f32;
+
+        critical_section::with(|cs| {
+            COMPASS_STATE.borrow(cs).set(CompassState {
+                temp,
+                raw,
+                range,
+                mag,
+                accel,
+                heading,
+            });
+
+            // Point from where the fix was last known while the receiver reacquires
+            let position = match (nav_pvt_state.position(), last_fix) {
+                (Some(lle), _) => Some(((lle.latitude, lle.longitude), false)),
+                (None, Some(fix)) => Some((fix.position(), true)),
+                (None, None) => None,
+            };
+            let nav = position.map(|(position, last_known)| NavCompassState {
+                last_known,
+                ..nav_compass_state(
+                    heading,
+                    temp,
+                    declination.to_radians(),
+                    position,
+                    landmark::selected(cs),
+                )
+            });
+            NAV_COMPASS_STATE.borrow(cs).set(nav);
+        });
     }
 }
+
+/// The MPU6050 reads +1g on z when level, rotate its axes 180° about x into device axes.
+fn device_axes(accel: (f32, f32, f32)) -> (f32, f32, f32) {
+    (accel.0, -accel.1, -accel.2)
+}
@@ -0,0 +1,48 @@
+//! Log output on the USB serial console.
+//!
+//! The track console streams GPX over the same USB-Serial-JTAG port the logs go to, so it mutes
+//! them for the length of the dump. Anything printed in between would end up inside the file.
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use critical_section::Mutex;
+use log::LevelFilter;
+
+static MUTED: AtomicBool = AtomicBool::new(false);
+
+/// Log level to go back to after [`unmute`].
+static LOG_LEVEL: Mutex<Cell<LevelFilter>> = Mutex::new(Cell::new(LevelFilter::Off));
+
+/// Like `esp_println::println`, but prints nothing while the console is muted.
+#[macro_export]
+macro_rules! println {
+    ($($arg:tt)*) => {
+        if !$crate::console::muted() {
+            $crate::console::esp_println::println!($($arg)*);
+        }
+    };
+}
+
+#[doc(hidden)]
+pub use esp_println;
+
+/// Whether [`println`](crate::println) and the logger are quiet.
+pub fn muted() -> bool {
+    MUTED.load(Ordering::Relaxed)
+}
+
+/// Stops [`println`](crate::println) and the logger until [`unmute`].
+pub fn mute() {
+    if !MUTED.swap(true, Ordering::Relaxed) {
+        critical_section::with(|cs| LOG_LEVEL.borrow(cs).set(log::max_level()));
+        log::set_max_level(LevelFilter::Off);
+    }
+}
+
+/// Lets [`println`](crate::println) and the logger through again.
+pub fn unmute() {
+    if MUTED.swap(false, Ordering::Relaxed) {
+        log::set_max_level(critical_section::with(|cs| LOG_LEVEL.borrow(cs).get()));
+    }
+}
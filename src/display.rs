@@ -4,7 +4,7 @@ use esp_hal::{gpio, peripherals::*, Async};
 use esp_hal::{spi::master::Spi, time::Rate};
 use pcd8544_hal::{Pcd8544, Pcd8544Spi};
 
-use crate::user_interface::sprites::Frame;
+use crate::user_interface::{sprites::Frame, UserInterface};
 
 pub enum DrawCommand {
     Char(u8),
@@ -51,6 +51,11 @@ impl Display {
         Self { display_driver }
     }
 
+    /// Draws the user interface's current screen.
+    pub fn draw(&mut self, ui: &mut UserInterface) {
+        ui.draw(&mut self.display_driver);
+    }
+
     pub fn execute(&mut self, command: &'static DrawCommand) {
         match command {
             DrawCommand::Char(c) => self.display_driver.print_char(*c),
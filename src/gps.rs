@@ -1,226 +1,577 @@
 use core::cell::Cell;
 
+use crate::println;
 use critical_section::Mutex;
-use embassy_time::{Duration, Ticker};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
 use esp_hal::{
     peripherals::*,
     uart::{self, RxError, TxError, Uart},
     Async,
 };
-use esp_println::println;
-use geoconv::{Degrees, Lle, Meters, Wgs84};
+use esp_storage::FlashStorage;
 use ublox::{
-    CfgPrtUartBuilder, FixedLinearBuffer, GnssFixType, InProtoMask, NavPvtFlags2, OutProtoMask,
-    PacketRef, Parser, UartMode,
+    AlignmentToReferenceTime, CfgMsgAllPortsBuilder, CfgNav5Builder, CfgNav5Params,
+    CfgPrtUartBuilder, CfgRateBuilder, InProtoMask, NavDynamicModel, NavPvt, NavSat, OutProtoMask,
+    UartMode, UartPortId,
 };
 
+use crate::assist::{self, Aiding, AssistStore, CHUNK_LEN, CLASS_MGA, ID_MGA_INI};
+use crate::clock;
+use crate::nmea::{self, Sentence};
+use crate::persist;
+use crate::power::{self, Inputs, Policy, PowerMode, CFG_PM2, CFG_RXM, RXM_PMREQ};
+use crate::receiver::{Event, Receiver};
+use crate::satellites::SkyView;
+use crate::trip;
+use crate::ubx::{self, Ack, Framer};
+
+pub use crate::receiver::{FixQuality, NavPvtState, GOOD_HORIZONTAL_ACCURACY, STALE_AFTER};
+
 pub static NAV_PVT_STATE: Mutex<Cell<NavPvtState>> = Mutex::new(Cell::new(NavPvtState::new()));
 
+/// Satellites from the latest NAV-SAT or GSV sentences.
+pub static SKY_VIEW: Mutex<Cell<SkyView>> = Mutex::new(Cell::new(SkyView::new()));
+
+/// UBX framing counters, for diagnostics.
+pub static UBX_STATS: Mutex<Cell<ubx::Stats>> = Mutex::new(Cell::new(ubx::Stats {
+    packets: 0,
+    checksum_failures: 0,
+    dropped_bytes: 0,
+}));
+
+/// Baud rate the receiver is moved to.
+const BAUD_RATE: u32 = 115200;
+
+/// Baud rates tried when looking for the receiver, most likely first. Most modules ship at 9600.
+const BAUD_RATES: [u32; 7] = [BAUD_RATE, 9600, 38400, 57600, 19200, 230400, 4800];
+
+/// How long to listen at each baud rate.
+const DETECT_TIMEOUT: Duration = Duration::from_millis(2500);
+
+/// Valid UBX frames or NMEA sentences needed to accept a baud rate, so noise at the wrong rate
+/// that happens to pass a checksum is not enough.
+const DETECT_FRAMES: u32 = 2;
+
+/// Time for the receiver to apply a new baud rate.
+const BAUD_SWITCH_DELAY: Duration = Duration::from_millis(100);
+
+/// Look for the receiver again when no UBX frame arrives for this long.
+const SILENCE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Look for the receiver again after this long in power save mode, where it may stay powered
+/// down between attempts to acquire satellites.
+const POWER_SAVE_SILENCE_TIMEOUT: Duration =
+    Duration::from_millis(2 * power::SEARCH_PERIOD_MS as u64);
+
+/// How often the power policy is consulted.
+const POLICY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time for the receiver to come out of backup mode after activity on its UART.
+const WAKE_DELAY: Duration = Duration::from_millis(100);
+
+/// Time allowed for the receiver to take in each assistance message.
+const AIDING_PACE: Duration = Duration::from_millis(10);
+
+/// Time between navigation solutions.
+const MEASUREMENT_RATE_MS: u16 = 1000;
+
+/// Times each configuration message is sent before giving up on it.
+const CONFIG_ATTEMPTS: u8 = 3;
+
+/// How long to wait for ACK-ACK or ACK-NAK after each configuration message.
+const ACK_TIMEOUT: Duration = Duration::from_millis(1000);
+
 #[embassy_executor::task]
 pub async fn gps_task(uart: UART1<'static>, rx: GPIO17<'static>, tx: GPIO16<'static>) -> ! {
     println!("Started Gps Task");
 
-    let mut gps = Gps::new(uart, rx, tx).await.unwrap();
-    println!("Gps Ready!");
+    let mut parser_buf = [0u8; ubx::MAX_FRAME_LEN];
+    let mut gps = Gps::new(uart, rx, tx, &mut parser_buf);
 
     // let mut ticker = Ticker::every(Duration::from_millis(100));
 
+    let mut policy = Policy::new();
+
     loop {
-        if let Err(err) = gps.process().await {
-            println!("GPS RX Err:{}", err)
+        gps.connect().await;
+        println!("Gps Ready!");
+
+        // The receiver keeps its power mode through a reconnect or baud change, so it is sent
+        // again rather than assumed. A mode the receiver did not accept is not asked for again
+        // until the policy changes.
+        let mut requested = policy.mode(Inputs::now(speed()), Instant::now());
+        if !gps.set_power_mode(requested).await {
+            println!("GPS did not enter {:?}", requested);
         }
-        // ticker.next().await;
-    }
-}
+        loop {
+            if let Ok(Err(err)) = with_timeout(POLICY_INTERVAL, gps.process()).await {
+                println!("GPS RX Err:{}", err)
+            }
 
-#[derive(Clone, Copy)]
-#[allow(dead_code)]
-pub struct NavPvtState {
-    pub time_tag: f64,
-    pub year: u16,
-    pub month: u8,
-    pub day: u8,
-    pub hour: u8,
-    pub min: u8,
-    pub sec: u8,
-    pub valid: u8,
-    pub time_accuracy: u32,
-    pub nanosecond: i32,
-    pub utc_time_accuracy: u32,
-    /// Latitude, Longitude, Elevation
-    pub lle: Option<Lle<Wgs84, Degrees>>,
-
-    pub msl: f64,
-    pub vel_ned: (f64, f64, f64),
-    pub speed_over_ground: f64,
-    pub heading_motion: f64,
-    pub heading_vehicle: f64,
-    pub magnetic_declination: f64,
-
-    pub pdop: f64,
-    pub satellites_used: u8,
-
-    pub position_fix_type: GnssFixType,
-}
+            let mode = policy.mode(Inputs::now(speed()), Instant::now());
+            if mode != requested {
+                requested = mode;
+                if !gps.set_power_mode(mode).await {
+                    println!("GPS did not enter {:?}", mode);
+                }
+            }
 
-impl NavPvtState {
-    pub const fn new() -> Self {
-        Self {
-            time_tag: f64::NAN,
-            year: 0,
-            month: 0,
-            day: 0,
-            hour: 0,
-            min: 0,
-            sec: 0,
-            valid: 0,
-            time_accuracy: 0,
-            nanosecond: 0,
-            lle: None,
-            msl: f64::NAN,
-            vel_ned: (f64::NAN, f64::NAN, f64::NAN),
-            speed_over_ground: f64::NAN,
-            heading_motion: f64::NAN,
-            heading_vehicle: f64::NAN,
-            magnetic_declination: f64::NAN,
-            pdop: f64::NAN,
-            satellites_used: 0,
-            utc_time_accuracy: 0,
-            position_fix_type: GnssFixType::NoFix,
+            let timeout = match gps.power_mode {
+                PowerMode::Continuous => SILENCE_TIMEOUT,
+                PowerMode::PowerSave => POWER_SAVE_SILENCE_TIMEOUT,
+                // Silent until woken
+                PowerMode::Backup => continue,
+            };
+            if gps.silence() > timeout {
+                println!("GPS stopped responding");
+                break;
+            }
+            // ticker.next().await;
         }
     }
 }
 
-struct Gps {
+/// Speed over ground from the latest navigation solution, None without a fix to navigate by.
+fn speed() -> Option<f64> {
+    let state = critical_section::with(|cs| NAV_PVT_STATE.borrow(cs).get());
+    state
+        .position()
+        .map(|_| state.speed_over_ground)
+        .filter(|speed| speed.is_finite())
+}
+
+/// The UART side of the receiver, decoding is left to [`Receiver`].
+struct Gps<'a> {
     uart_port: Uart<'static, Async>,
+    baud_rate: u32,
+    /// When the receiver was last found, silence is counted from here until the first frame.
+    connected: Instant,
+    receiver: Receiver<'a>,
+    power_mode: PowerMode,
+    /// Whether the assistance data has been sent, which is done once after boot.
+    assisted: bool,
 }
 
-impl Gps {
-    pub async fn new(
+impl<'a> Gps<'a> {
+    pub fn new(
         uart: UART1<'static>,
         rx: GPIO17<'static>,
         tx: GPIO16<'static>,
-    ) -> Result<Self, TxError> {
-        let config = uart::Config::default().with_baudrate(115200);
-        let mut uart_port = Uart::new(uart, config)
+        parser_buf: &'a mut [u8],
+    ) -> Self {
+        let config = uart::Config::default().with_baudrate(BAUD_RATE);
+        assist::start(Instant::now());
+        let uart_port = Uart::new(uart, config)
             .unwrap()
             .with_rx(rx)
             .with_tx(tx)
             .into_async();
 
-        uart_port
-            .write_async(
-                CfgPrtUartBuilder {
-                    portid: ublox::UartPortId::Usb, // Double check this is not supposed to be Uart1
-                    reserved0: 0,
-                    tx_ready: 0,
-                    mode: UartMode::new(
-                        ublox::DataBits::Eight,
-                        ublox::Parity::None,
-                        ublox::StopBits::One,
-                    ),
-                    baud_rate: 115200,
-                    in_proto_mask: InProtoMask::all(),
-                    out_proto_mask: OutProtoMask::UBLOX,
-                    flags: 0,
-                    reserved5: 0,
+        Self {
+            uart_port,
+            baud_rate: BAUD_RATE,
+            connected: Instant::now(),
+            receiver: Receiver::new(parser_buf),
+            power_mode: PowerMode::Continuous,
+            assisted: false,
+        }
+    }
+
+    /// Finds the receiver's baud rate, moves it to [`BAUD_RATE`] and configures it.
+    async fn connect(&mut self) {
+        // The receiver may have been left in backup mode before deep sleep
+        self.wake().await;
+
+        match self.detect_baud_rate().await {
+            Some(baud_rate) if baud_rate != BAUD_RATE => {
+                println!("GPS found at {} baud", baud_rate);
+                if !self.switch_baud_rate().await {
+                    println!("GPS silent at {} baud, staying at {}", BAUD_RATE, baud_rate);
                 }
-                .into_packet_bytes()
-                .as_slice(),
-            )
-            .await?;
+            }
+            Some(_) => {}
+            None => {
+                println!("GPS not found, assuming {} baud", BAUD_RATE);
+                self.set_baud_rate(BAUD_RATE);
+            }
+        }
 
-        Ok(Self { uart_port })
+        match self.configure().await {
+            Some(steps) => {
+                for (step, reply) in steps {
+                    match reply {
+                        Some(Ack::Ack) => {}
+                        Some(Ack::Nak) => println!("GPS config {} rejected", step),
+                        None => println!("GPS config {} not acknowledged", step),
+                    }
+                }
+                if !self.assisted {
+                    self.assisted = true;
+                    self.assist().await;
+                }
+            }
+            None => println!("GPS does not answer UBX, using NMEA"),
+        }
+        self.connected = Instant::now();
     }
 
-    async fn process(&mut self) -> Result<(), RxError> {
-        const MAX_PAYLOAD_LEN: usize = 1280;
+    /// Sends the time, the position saved before deep sleep and the stored assistance messages
+    /// for today, see [`crate::assist`].
+    async fn assist(&mut self) {
+        let mut aiding = Aiding::default();
+        let mut buf = [0; 64];
+        let utc = clock::now_utc();
+        if let Some(utc) = utc {
+            let time = assist::mga_ini_time_utc(utc, assist::TIME_ACCURACY);
+            aiding.time = self
+                .send_aiding(ubx::encode(CLASS_MGA, ID_MGA_INI, &time, &mut buf))
+                .await;
+        }
+        if let Some(hint) = assist::hint() {
+            let position = assist::mga_ini_pos_llh(&hint, assist::POSITION_ACCURACY_CM);
+            aiding.position = self
+                .send_aiding(ubx::encode(CLASS_MGA, ID_MGA_INI, &position, &mut buf))
+                .await;
+        }
 
-        let mut buf = [0u8; MAX_PAYLOAD_LEN];
-        let mut parser = Parser::new(FixedLinearBuffer::new(&mut buf));
+        let mut store = AssistStore::new(
+            FlashStorage::new(),
+            assist::PARTITION_OFFSET,
+            assist::PARTITION_SIZE,
+        );
+        match store.len() {
+            Ok(Some(len)) => {
+                let today = utc.map(|utc| utc.date());
+                let mut framer = Framer::new();
+                let mut chunk = [0; CHUNK_LEN];
+                for start in (0..len).step_by(CHUNK_LEN) {
+                    let bytes = match store.read(len, start, &mut chunk) {
+                        Ok(bytes) => bytes,
+                        Err(error) => {
+                            println!("Assistance data unreadable: {:?}", error);
+                            break;
+                        }
+                    };
+                    for &byte in bytes {
+                        if let Some(frame) = framer.push(byte) {
+                            if assist::is_current(&frame, today)
+                                && self.send_aiding(frame.as_bytes()).await
+                            {
+                                aiding.messages += 1;
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(error) => println!("Assistance data unavailable: {:?}", error),
+        }
 
-        let mut local_buf = [0; MAX_PAYLOAD_LEN];
-        let nbytes = self.read_uart(&mut local_buf).await?;
+        println!(
+            "GPS aided with time {} position {} messages {}",
+            aiding.time, aiding.position, aiding.messages
+        );
+        assist::aided(aiding);
+    }
+
+    /// Sends an MGA message, giving the receiver [`AIDING_PACE`] to take it in while handling
+    /// anything that arrives meanwhile.
+    async fn send_aiding(&mut self, frame: &[u8]) -> bool {
+        if let Err(err) = self.write_uart(frame).await {
+            println!("GPS TX Err:{}", err);
+            return false;
+        }
+        let mut local_buf = [0; 64];
+        if let Ok(Ok(nbytes)) = with_timeout(AIDING_PACE, self.read_uart(&mut local_buf)).await {
+            self.consume(&local_buf[..nbytes], None);
+        }
+        true
+    }
 
-        let mut it = parser.consume_ubx(&local_buf[..nbytes]);
+    /// Moves the receiver to `mode`, returning whether it got there.
+    ///
+    /// Continuous and power save modes are only entered once the receiver acknowledges them.
+    /// Backup mode has no reply, and the receiver stays there until its UART is written to.
+    pub async fn set_power_mode(&mut self, mode: PowerMode) -> bool {
+        if self.power_mode == PowerMode::Backup {
+            self.wake().await;
+            self.connected = Instant::now();
+        }
 
-        loop {
-            match it.next() {
-                Some(Ok(packet)) => {
-                    self.handle_packet(packet);
-                }
-                Some(Err(err)) => {
-                    println!("Bad Packet! Err:{}", err);
-                }
-                None => {
-                    // debug!("Parsed all data in buffer ...");
-                    break;
+        let entered = match mode {
+            PowerMode::Continuous => {
+                self.send_power_config(CFG_RXM, &power::cfg_rxm(false))
+                    .await
+            }
+            PowerMode::PowerSave => {
+                // Cyclic tracking has to be set up before the receiver switches to it
+                self.send_power_config(CFG_PM2, &power::cfg_pm2()).await
+                    && self.send_power_config(CFG_RXM, &power::cfg_rxm(true)).await
+            }
+            PowerMode::Backup => {
+                let mut buf = [0; 32];
+                let (class, id) = RXM_PMREQ;
+                let pmreq = ubx::encode(class, id, &power::rxm_pmreq_backup(), &mut buf);
+                match self.write_uart(pmreq).await {
+                    Ok(()) => self.uart_port.flush_async().await.is_ok(),
+                    Err(err) => {
+                        println!("GPS TX Err:{}", err);
+                        false
+                    }
                 }
             }
+        };
+
+        if entered {
+            self.set_power_mode_state(mode);
         }
-        Ok(())
+        entered
     }
 
-    /// Reads the serial port, converting timeouts into "no data received"
-    async fn read_uart(&mut self, output: &mut [u8]) -> Result<usize, RxError> {
-        self.uart_port.read_async(output).await
+    /// Sends a power management CFG message, returning whether it was acknowledged.
+    async fn send_power_config(&mut self, (class, id): (u8, u8), payload: &[u8]) -> bool {
+        let mut buf = [0; 64];
+        let packet = ubx::encode(class, id, payload, &mut buf);
+        self.send_config(packet).await == Some(Ack::Ack)
     }
 
-    fn handle_packet(&mut self, packet: PacketRef<'_>) {
-        println!("Handle Packet:{:?}", packet);
+    fn set_power_mode_state(&mut self, mode: PowerMode) {
+        self.power_mode = mode;
+        critical_section::with(|cs| power::POWER_MODE.borrow(cs).set(mode));
+    }
 
-        let parsed = match packet {
-            PacketRef::NavPvt(pkg) => {
-                let mut nav_pvt_state = NavPvtState {
-                    time_tag: (pkg.itow() / 1000) as f64,
-                    ..NavPvtState::new()
-                };
+    /// Brings the receiver out of backup mode. The bytes themselves are lost while it wakes.
+    async fn wake(&mut self) {
+        if let Err(err) = self.write_uart(&[0xff; 8]).await {
+            println!("GPS TX Err:{}", err);
+        }
+        Timer::after(WAKE_DELAY).await;
+    }
+
+    /// Time since the last UBX frame or NMEA sentence, or since connecting.
+    fn silence(&self) -> Duration {
+        match self.receiver.last_frame() {
+            Some(last_frame) => last_frame.max(self.connected).elapsed(),
+            None => self.connected.elapsed(),
+        }
+    }
 
-                if pkg.flags2().contains(NavPvtFlags2::CONFIRMED_AVAI) {
-                    nav_pvt_state.day = pkg.day();
-                    nav_pvt_state.month = pkg.month();
-                    nav_pvt_state.year = pkg.year();
-                    nav_pvt_state.hour = pkg.hour();
-                    nav_pvt_state.min = pkg.min();
-                    nav_pvt_state.sec = pkg.sec();
-                    nav_pvt_state.nanosecond = pkg.nanosec();
+    /// Tries each of [`BAUD_RATES`] until valid UBX or NMEA traffic arrives.
+    async fn detect_baud_rate(&mut self) -> Option<u32> {
+        for baud_rate in BAUD_RATES {
+            self.set_baud_rate(baud_rate);
+            if self.listen(DETECT_TIMEOUT).await {
+                return Some(baud_rate);
+            }
+        }
+        None
+    }
 
-                    nav_pvt_state.utc_time_accuracy = pkg.time_accuracy();
+    /// Whether [`DETECT_FRAMES`] valid UBX frames or NMEA sentences arrive within `timeout`.
+    async fn listen(&mut self, timeout: Duration) -> bool {
+        // Separate framers so noise at the wrong baud rate does not count against the link
+        let mut ubx = Framer::new();
+        let mut nmea = nmea::Framer::new();
+        let mut local_buf = [0; 64];
+
+        let deadline = Instant::now() + timeout;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            match with_timeout(remaining, self.read_uart(&mut local_buf)).await {
+                Ok(Ok(nbytes)) => {
+                    for &byte in &local_buf[..nbytes] {
+                        ubx.push(byte);
+                        nmea.push(byte);
+                    }
+                    if ubx.stats().packets + nmea.sentences() >= DETECT_FRAMES {
+                        return true;
+                    }
                 }
+                // Framing errors are expected at the wrong baud rate
+                Ok(Err(_)) => {}
+                Err(_) => break,
+            }
+        }
+        false
+    }
 
-                nav_pvt_state.position_fix_type = pkg.fix_type();
+    /// Moves the receiver from the current baud rate to [`BAUD_RATE`], going back if it cannot be
+    /// heard at the new rate.
+    async fn switch_baud_rate(&mut self) -> bool {
+        let previous = self.baud_rate;
+        let port = port_config(BAUD_RATE).into_packet_bytes();
 
-                nav_pvt_state.lle = Some(Lle::new(
-                    Degrees::new(pkg.latitude()),
-                    Degrees::new(pkg.longitude()),
-                    Meters::new(pkg.height_above_ellipsoid()),
-                ));
-                //nav_pvt_state.lat = pkg.latitude();
-                //nav_pvt_state.lon = pkg.longitude();
-                //nav_pvt_state.height = pkg.height_above_ellipsoid();
-                nav_pvt_state.msl = pkg.height_msl();
+        // Any reply comes at a rate we are about to leave
+        if let Err(err) = self.write_uart(&port).await {
+            println!("GPS TX Err:{}", err);
+            return false;
+        }
+        if let Err(err) = self.uart_port.flush_async().await {
+            println!("GPS TX Err:{}", err);
+            return false;
+        }
+        Timer::after(BAUD_SWITCH_DELAY).await;
+        self.set_baud_rate(BAUD_RATE);
 
-                nav_pvt_state.vel_ned = (pkg.vel_north(), pkg.vel_east(), pkg.vel_down());
+        // Sending it again at the new rate proves the link works both ways
+        if self.send_config(&port).await.is_some() {
+            return true;
+        }
+        self.set_baud_rate(previous);
+        false
+    }
 
-                nav_pvt_state.speed_over_ground = pkg.ground_speed_2d();
-                nav_pvt_state.heading_motion = pkg.heading_motion();
-                nav_pvt_state.heading_vehicle = pkg.heading_vehicle();
+    fn set_baud_rate(&mut self, baud_rate: u32) {
+        let config = uart::Config::default().with_baudrate(baud_rate);
+        self.uart_port.apply_config(&config).unwrap();
+        self.baud_rate = baud_rate;
+    }
 
-                nav_pvt_state.magnetic_declination = pkg.magnetic_declination();
+    /// Sets up the receiver: UBX only on UART1, NAV-PVT and NAV-SAT every solution, the
+    /// measurement rate and the pedestrian dynamic model.
+    ///
+    /// Returns each step with the receiver's reply, None if it never answered. Gives up with None
+    /// when the port configuration goes unanswered, as the receiver most likely does not speak UBX.
+    pub async fn configure(&mut self) -> Option<[(&'static str, Option<Ack>); 5]> {
+        let port = port_config(self.baud_rate).into_packet_bytes();
+        let port_reply = self.send_config(&port).await?;
+
+        // Rates for DDC, UART1, UART2, USB, SPI and reserved
+        let nav_pvt =
+            CfgMsgAllPortsBuilder::set_rate_for::<NavPvt>([0, 1, 0, 0, 0, 0]).into_packet_bytes();
+        let nav_sat =
+            CfgMsgAllPortsBuilder::set_rate_for::<NavSat>([0, 1, 0, 0, 0, 0]).into_packet_bytes();
+
+        let rate = CfgRateBuilder {
+            measure_rate_ms: MEASUREMENT_RATE_MS,
+            nav_rate: 1,
+            time_ref: AlignmentToReferenceTime::Gps,
+        }
+        .into_packet_bytes();
 
-                nav_pvt_state.pdop = pkg.pdop();
+        let dynamic_model = CfgNav5Builder {
+            mask: CfgNav5Params::DYN,
+            dyn_model: NavDynamicModel::Pedestrian,
+            ..Default::default()
+        }
+        .into_packet_bytes();
+
+        Some([
+            ("CFG-PRT", Some(port_reply)),
+            ("CFG-MSG NAV-PVT", self.send_config(&nav_pvt).await),
+            ("CFG-MSG NAV-SAT", self.send_config(&nav_sat).await),
+            ("CFG-RATE", self.send_config(&rate).await),
+            ("CFG-NAV5", self.send_config(&dynamic_model).await),
+        ])
+    }
 
-                nav_pvt_state.satellites_used = pkg.num_satellites();
+    /// Sends a CFG message until it is acknowledged or rejected, handling anything else that
+    /// arrives meanwhile.
+    async fn send_config(&mut self, packet: &[u8]) -> Option<Ack> {
+        let (class, id) = (packet[2], packet[3]);
+        let mut local_buf = [0; 64];
 
-                Some(nav_pvt_state)
+        for _ in 0..CONFIG_ATTEMPTS {
+            if let Err(err) = self.write_uart(packet).await {
+                println!("GPS TX Err:{}", err);
+                continue;
             }
-            _ => None,
-        };
 
-        if let Some(parsed) = parsed {
-            critical_section::with(|cs| {
-                NAV_PVT_STATE.borrow(cs).set(parsed);
-            })
+            let deadline = Instant::now() + ACK_TIMEOUT;
+            while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+                match with_timeout(remaining, self.read_uart(&mut local_buf)).await {
+                    Ok(Ok(nbytes)) => {
+                        if let Some(ack) = self.consume(&local_buf[..nbytes], Some((class, id))) {
+                            return Some(ack);
+                        }
+                    }
+                    Ok(Err(err)) => println!("GPS RX Err:{}", err),
+                    Err(_) => break,
+                }
+            }
+        }
+        None
+    }
+
+    async fn process(&mut self) -> Result<(), RxError> {
+        let mut local_buf = [0; ubx::MAX_PAYLOAD_LEN];
+        let nbytes = self.read_uart(&mut local_buf).await?;
+        self.consume(&local_buf[..nbytes], None);
+        Ok(())
+    }
+
+    /// Frames and handles received bytes. Returns the reply to the CFG message with `cfg`'s class
+    /// and id if it is among them.
+    fn consume(&mut self, bytes: &[u8], cfg: Option<(u8, u8)>) -> Option<Ack> {
+        let ack = self.receiver.consume(bytes, Instant::now(), cfg, publish);
+
+        let stats = self.receiver.stats();
+        critical_section::with(|cs| UBX_STATS.borrow(cs).set(stats));
+        ack
+    }
+
+    /// Reads the serial port, converting timeouts into "no data received"
+    async fn read_uart(&mut self, output: &mut [u8]) -> Result<usize, RxError> {
+        self.uart_port.read_async(output).await
+    }
+
+    async fn write_uart(&mut self, mut bytes: &[u8]) -> Result<(), TxError> {
+        while !bytes.is_empty() {
+            let written = self.uart_port.write_async(bytes).await?;
+            bytes = &bytes[written..];
+        }
+        Ok(())
+    }
+}
+
+/// Hands what the receiver brought to the rest of the firmware.
+fn publish(event: Event<'_>) {
+    match event {
+        Event::NavPvt(state) => {
+            if let (Some(utc), Some(received)) = (state.date_time(), state.received) {
+                clock::latch(utc, received);
+            }
+            trip::update(&state);
+            assist::update(&state);
+            persist::update(&state);
+            critical_section::with(|cs| NAV_PVT_STATE.borrow(cs).set(state));
         }
+        Event::Sentence(sentence, state) => {
+            critical_section::with(|cs| NAV_PVT_STATE.borrow(cs).set(state));
+            match sentence {
+                // GGA carries the time too, but only RMC brings the date that resolves it
+                Sentence::Rmc(_) => {
+                    if let Some(utc) = state.date_time() {
+                        clock::latch(utc, Instant::now());
+                    }
+                }
+                // GGA closes the epoch, after the RMC or VTG with its speed
+                Sentence::Gga(_) => {
+                    trip::update(&state);
+                    assist::update(&state);
+                    persist::update(&state);
+                }
+                _ => {}
+            }
+        }
+        Event::SkyView(view) => critical_section::with(|cs| SKY_VIEW.borrow(cs).set(*view)),
+    }
+}
+
+/// UART1 at `baud_rate`, 8N1, taking any protocol in and sending UBX only.
+fn port_config(baud_rate: u32) -> CfgPrtUartBuilder {
+    CfgPrtUartBuilder {
+        portid: UartPortId::Uart1,
+        reserved0: 0,
+        tx_ready: 0,
+        mode: UartMode::new(
+            ublox::DataBits::Eight,
+            ublox::Parity::None,
+            ublox::StopBits::One,
+        ),
+        baud_rate,
+        in_proto_mask: InProtoMask::all(),
+        out_proto_mask: OutProtoMask::UBLOX,
+        flags: 0,
+        reserved5: 0,
     }
 }
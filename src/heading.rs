@@ -0,0 +1,99 @@
+//! Turns calibrated magnetometer readings into directions for display.
+//!
+//! Device axes are x towards the top of the device, y to its right and z down through the back,
+//! so a level device facing magnetic north reads a positive x and zero y, and a level device at
+//! rest reads -1g on the accelerometer z axis.
+//! Headings and directions are in radians, measured clockwise and normalised to `[0, 2π)`.
+
+use core::f32::consts::TAU;
+
+use geoconv::Degrees;
+use libm::{atan2f, cosf, sinf, sqrtf};
+
+use crate::landmark::Landmark;
+
+/// Directions derived from the compass, GPS fix and selected landmark.
+/// All angles are in radians clockwise, see [`crate::heading`].
+#[derive(Debug, Clone, Copy)]
+pub struct NavCompassState {
+    /// Temperature in °C, see [`crate::compass::CompassState::temp`].
+    pub temp: Option<f32>,
+    /// Direction of magnetic north relative to the top of the device.
+    pub north_dir: f32,
+    /// Direction of the selected landmark relative to the top of the device.
+    pub target_dir: f32,
+    /// Rotation of the top of the device away from true north.
+    pub screen_offset: f32,
+    /// Whether the target is pointed to from the last known fix rather than a current one.
+    pub last_known: bool,
+}
+
+/// Wraps an angle into `[0, 2π)`.
+pub fn normalize(rad: f32) -> f32 {
+    let rad = rad % TAU;
+    if rad < 0. {
+        rad + TAU
+    } else {
+        rad
+    }
+}
+
+/// Heading of the top of the device clockwise from magnetic north, assuming it is held level.
+pub fn heading(mag: (f32, f32, f32)) -> f32 {
+    normalize(atan2f(-mag.1, mag.0))
+}
+
+/// Pitch (top of the device up) and roll (right side down) from an accelerometer reading taken
+/// at rest. Any unit works since only the direction of gravity matters.
+pub fn pitch_roll(accel: (f32, f32, f32)) -> (f32, f32) {
+    let roll = atan2f(-accel.1, -accel.2);
+    let pitch = atan2f(accel.0, sqrtf(accel.1 * accel.1 + accel.2 * accel.2));
+    (pitch, roll)
+}
+
+/// Heading of the top of the device clockwise from magnetic north, for any orientation where
+/// the top of the device is not pointing straight up or down.
+pub fn tilt_compensated_heading(mag: (f32, f32, f32), accel: (f32, f32, f32)) -> f32 {
+    let (pitch, roll) = pitch_roll(accel);
+    let (sin_pitch, cos_pitch) = (sinf(pitch), cosf(pitch));
+    let (sin_roll, cos_roll) = (sinf(roll), cosf(roll));
+
+    // Rotate the field back into the horizontal plane
+    let x = mag.0 * cos_pitch + mag.1 * sin_roll * sin_pitch + mag.2 * cos_roll * sin_pitch;
+    let y = mag.1 * cos_roll - mag.2 * sin_roll;
+
+    heading((x, y, 0.))
+}
+
+/// Converts a magnetic heading or bearing to a true one given the declination (positive east).
+pub fn magnetic_to_true(magnetic: f32, declination: f32) -> f32 {
+    normalize(magnetic + declination)
+}
+
+/// Converts a true heading or bearing to a magnetic one given the declination (positive east).
+pub fn true_to_magnetic(true_north: f32, declination: f32) -> f32 {
+    normalize(true_north - declination)
+}
+
+/// Builds a [`NavCompassState`] for a device at `position` navigating to `target`.
+///
+/// `heading` is the magnetic heading from [`heading`] or [`tilt_compensated_heading`] and
+/// `declination` is in radians, positive east.
+pub fn nav_compass_state(
+    heading: f32,
+    temp: Option<f32>,
+    declination: f32,
+    position: (Degrees, Degrees),
+    target: &Landmark,
+) -> NavCompassState {
+    let true_heading = magnetic_to_true(heading, declination);
+    let bearing = (target.bearing_from(position).as_float() as f32).to_radians();
+
+    NavCompassState {
+        temp,
+        north_dir: normalize(-heading),
+        target_dir: normalize(bearing - true_heading),
+        screen_offset: true_heading,
+        last_known: false,
+    }
+}
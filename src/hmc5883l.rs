@@ -0,0 +1,360 @@
+//! A embedded-hal driver to interface with the HMC5883L magnetometer.
+
+use embedded_hal::i2c::I2c;
+use embedded_hal_async::i2c::I2c as AsyncI2c;
+
+pub use crate::magnetometer::Error;
+
+const I2C_ADDRESS: u8 = 0x1e;
+
+#[allow(dead_code)]
+#[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone)]
+#[repr(u8)]
+enum Register {
+    CONFIG_A = 0,
+    CONFIG_B = 1,
+    MODE = 2,
+    DATA_OUT_X_H = 3,
+    DATA_OUT_X_L = 4,
+    DATA_OUT_Z_H = 5,
+    DATA_OUT_Z_L = 6,
+    DATA_OUT_Y_H = 7,
+    DATA_OUT_Y_L = 8,
+    STATUS = 9,
+    ID_A = 10,
+    ID_B = 11,
+    ID_C = 12,
+}
+
+const ID_VALUE: [u8; 3] = *b"H43";
+
+const STATUS_RDY: u8 = 0b01;
+
+const MODE_CONTINUOUS: u8 = 0b00;
+const MODE_IDLE: u8 = 0b11;
+
+/// An axis reads this when it overflows or underflows.
+const OVERFLOW: i16 = -4096;
+
+/// CONFIG_A with 1 sample averaged at 15Hz in normal measurement mode.
+const CONFIG_A_DEFAULT: u8 = 0b0001_0000;
+const CONFIG_A_DO_MASK: u8 = 0b111 << 2;
+const CONFIG_A_MA_MASK: u8 = 0b11 << 5;
+
+/// Update frequency in continuous mode.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum OutputDataRate {
+    /// 0.75Hz update rate.
+    Rate0_75Hz = 0,
+    /// 1.5Hz update rate.
+    Rate1_5Hz = 1 << 2,
+    /// 3Hz update rate.
+    Rate3Hz = 2 << 2,
+    /// 7.5Hz update rate.
+    Rate7_5Hz = 3 << 2,
+    /// 15Hz update rate.
+    Rate15Hz = 4 << 2,
+    /// 30Hz update rate.
+    Rate30Hz = 5 << 2,
+    /// 75Hz update rate.
+    Rate75Hz = 6 << 2,
+}
+
+/// Number of samples averaged per measurement.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum Averaging {
+    /// No averaging.
+    Samples1 = 0,
+    /// Average 2 samples.
+    Samples2 = 1 << 5,
+    /// Average 4 samples.
+    Samples4 = 2 << 5,
+    /// Average 8 samples.
+    Samples8 = 3 << 5,
+}
+
+/// Field range of magnetic sensor, set through the gain.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum FieldRange {
+    /// ± 0.88 gauss
+    Range0_88Gauss = 0,
+    /// ± 1.3 gauss
+    Range1_3Gauss = 1 << 5,
+    /// ± 1.9 gauss
+    Range1_9Gauss = 2 << 5,
+    /// ± 2.5 gauss
+    Range2_5Gauss = 3 << 5,
+    /// ± 4.0 gauss
+    Range4Gauss = 4 << 5,
+    /// ± 4.7 gauss
+    Range4_7Gauss = 5 << 5,
+    /// ± 5.6 gauss
+    Range5_6Gauss = 6 << 5,
+    /// ± 8.1 gauss
+    Range8_1Gauss = 7 << 5,
+}
+
+impl FieldRange {
+    /// Every range, smallest first.
+    pub const ALL: [FieldRange; 8] = [
+        FieldRange::Range0_88Gauss,
+        FieldRange::Range1_3Gauss,
+        FieldRange::Range1_9Gauss,
+        FieldRange::Range2_5Gauss,
+        FieldRange::Range4Gauss,
+        FieldRange::Range4_7Gauss,
+        FieldRange::Range5_6Gauss,
+        FieldRange::Range8_1Gauss,
+    ];
+
+    /// Sensitivity in LSB/G.
+    pub fn lsb_per_gauss(&self) -> f32 {
+        match self {
+            FieldRange::Range0_88Gauss => 1370.,
+            FieldRange::Range1_3Gauss => 1090.,
+            FieldRange::Range1_9Gauss => 820.,
+            FieldRange::Range2_5Gauss => 660.,
+            FieldRange::Range4Gauss => 440.,
+            FieldRange::Range4_7Gauss => 390.,
+            FieldRange::Range5_6Gauss => 330.,
+            FieldRange::Range8_1Gauss => 230.,
+        }
+    }
+
+    /// Converts raw (x,y,z) counts taken at this range to µT.
+    pub fn micro_tesla(&self, (x, y, z): (i16, i16, i16)) -> (f32, f32, f32) {
+        // 1 G = 100 µT
+        let lsb_per_micro_tesla = self.lsb_per_gauss() / 100.;
+        (
+            x as f32 / lsb_per_micro_tesla,
+            y as f32 / lsb_per_micro_tesla,
+            z as f32 / lsb_per_micro_tesla,
+        )
+    }
+}
+
+/// HMC5883L driver
+pub struct HMC5883L<I2C> {
+    i2c: I2C,
+    range: FieldRange,
+}
+
+impl<I2C> HMC5883L<I2C> {
+    /// Destroys the driver and returns the I2C bus.
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// The field range raw readings are currently taken at.
+    pub fn field_range(&self) -> FieldRange {
+        self.range
+    }
+}
+
+impl<I2C: I2c> HMC5883L<I2C> {
+    /// Creates a new HMC5883L device from an I2C bus; configures it with the power on defaults and
+    /// leaves it idle.
+    pub fn new(i2c: I2C) -> Result<Self, Error<I2C::Error>> {
+        let mut dev = HMC5883L {
+            i2c,
+            range: FieldRange::Range1_3Gauss,
+        };
+        let id: &mut [u8; 3] = &mut [0; 3];
+        dev.i2c
+            .write_read(I2C_ADDRESS, &[Register::ID_A as u8], id)?;
+        if *id != ID_VALUE {
+            return Err(Error::InvalidDevice(id[0]));
+        }
+        dev.reset()?;
+        Ok(dev)
+    }
+
+    /// Returns true if an HMC5883L answers on the bus.
+    pub fn probe(i2c: &mut I2C) -> bool {
+        let id: &mut [u8; 3] = &mut [0; 3];
+        i2c.write_read(I2C_ADDRESS, &[Register::ID_A as u8], id)
+            .is_ok()
+            && *id == ID_VALUE
+    }
+
+    /// Restore the power on configuration and stop measuring.
+    pub fn reset(&mut self) -> Result<(), I2C::Error> {
+        self.write_u8(Register::CONFIG_A, CONFIG_A_DEFAULT)?;
+        self.set_field_range(FieldRange::Range1_3Gauss)?;
+        self.idle()
+    }
+
+    /// Set the device field range.
+    pub fn set_field_range(&mut self, rng: FieldRange) -> Result<(), I2C::Error> {
+        self.write_u8(Register::CONFIG_B, rng as u8)?;
+        self.range = rng;
+        Ok(())
+    }
+
+    /// Set the number of samples averaged per measurement.
+    pub fn set_averaging(&mut self, avg: Averaging) -> Result<(), I2C::Error> {
+        let config_a = self.read_u8(Register::CONFIG_A)?;
+        self.write_u8(
+            Register::CONFIG_A,
+            (config_a & !CONFIG_A_MA_MASK) | avg as u8,
+        )
+    }
+
+    /// Set the device output data rate.
+    pub fn set_output_data_rate(&mut self, odr: OutputDataRate) -> Result<(), I2C::Error> {
+        let config_a = self.read_u8(Register::CONFIG_A)?;
+        self.write_u8(
+            Register::CONFIG_A,
+            (config_a & !CONFIG_A_DO_MASK) | odr as u8,
+        )
+    }
+
+    /// Put device in continous mode.
+    pub fn continuous(&mut self) -> Result<(), I2C::Error> {
+        self.write_u8(Register::MODE, MODE_CONTINUOUS)
+    }
+
+    /// Put device in idle mode.
+    pub fn idle(&mut self) -> Result<(), I2C::Error> {
+        self.write_u8(Register::MODE, MODE_IDLE)
+    }
+
+    /// Read raw (x,y,z) from magnetometer.
+    pub fn mag(&mut self) -> Result<(i16, i16, i16), Error<I2C::Error>> {
+        if self.read_u8(Register::STATUS)? & STATUS_RDY == 0 {
+            return Err(Error::NotReady);
+        }
+        // The address pointer wraps from DATA_OUT_Y_L back to DATA_OUT_X_H so STATUS can not be
+        // read in the same burst.
+        let buf: &mut [u8; 6] = &mut [0; 6];
+        self.i2c
+            .write_read(I2C_ADDRESS, &[Register::DATA_OUT_X_H as u8], buf)?;
+        decode_mag(buf)
+    }
+
+    fn read_u8(&mut self, reg: Register) -> Result<u8, I2C::Error> {
+        let buf: &mut [u8; 1] = &mut [0];
+        self.i2c.write_read(I2C_ADDRESS, &[reg as u8], buf)?;
+        Ok(buf[0])
+    }
+
+    fn write_u8(&mut self, reg: Register, v: u8) -> Result<(), I2C::Error> {
+        self.i2c.write(I2C_ADDRESS, &[reg as u8, v])
+    }
+}
+
+impl<I2C: AsyncI2c> HMC5883L<I2C> {
+    /// Creates a new HMC5883L device from an async I2C bus; configures it with the power on
+    /// defaults and leaves it idle.
+    pub async fn new_async(i2c: I2C) -> Result<Self, Error<I2C::Error>> {
+        let mut dev = HMC5883L {
+            i2c,
+            range: FieldRange::Range1_3Gauss,
+        };
+        let id: &mut [u8; 3] = &mut [0; 3];
+        dev.i2c
+            .write_read(I2C_ADDRESS, &[Register::ID_A as u8], id)
+            .await?;
+        if *id != ID_VALUE {
+            return Err(Error::InvalidDevice(id[0]));
+        }
+        dev.reset_async().await?;
+        Ok(dev)
+    }
+
+    /// Returns true if an HMC5883L answers on the bus.
+    pub async fn probe_async(i2c: &mut I2C) -> bool {
+        let id: &mut [u8; 3] = &mut [0; 3];
+        i2c.write_read(I2C_ADDRESS, &[Register::ID_A as u8], id)
+            .await
+            .is_ok()
+            && *id == ID_VALUE
+    }
+
+    /// Restore the power on configuration and stop measuring.
+    pub async fn reset_async(&mut self) -> Result<(), I2C::Error> {
+        self.write_u8_async(Register::CONFIG_A, CONFIG_A_DEFAULT)
+            .await?;
+        self.set_field_range_async(FieldRange::Range1_3Gauss)
+            .await?;
+        self.idle_async().await
+    }
+
+    /// Set the device field range.
+    pub async fn set_field_range_async(&mut self, rng: FieldRange) -> Result<(), I2C::Error> {
+        self.write_u8_async(Register::CONFIG_B, rng as u8).await?;
+        self.range = rng;
+        Ok(())
+    }
+
+    /// Set the number of samples averaged per measurement.
+    pub async fn set_averaging_async(&mut self, avg: Averaging) -> Result<(), I2C::Error> {
+        let config_a = self.read_u8_async(Register::CONFIG_A).await?;
+        self.write_u8_async(
+            Register::CONFIG_A,
+            (config_a & !CONFIG_A_MA_MASK) | avg as u8,
+        )
+        .await
+    }
+
+    /// Set the device output data rate.
+    pub async fn set_output_data_rate_async(
+        &mut self,
+        odr: OutputDataRate,
+    ) -> Result<(), I2C::Error> {
+        let config_a = self.read_u8_async(Register::CONFIG_A).await?;
+        self.write_u8_async(
+            Register::CONFIG_A,
+            (config_a & !CONFIG_A_DO_MASK) | odr as u8,
+        )
+        .await
+    }
+
+    /// Put device in continous mode.
+    pub async fn continuous_async(&mut self) -> Result<(), I2C::Error> {
+        self.write_u8_async(Register::MODE, MODE_CONTINUOUS).await
+    }
+
+    /// Put device in idle mode.
+    pub async fn idle_async(&mut self) -> Result<(), I2C::Error> {
+        self.write_u8_async(Register::MODE, MODE_IDLE).await
+    }
+
+    /// Read raw (x,y,z) from magnetometer.
+    pub async fn mag_async(&mut self) -> Result<(i16, i16, i16), Error<I2C::Error>> {
+        if self.read_u8_async(Register::STATUS).await? & STATUS_RDY == 0 {
+            return Err(Error::NotReady);
+        }
+        let buf: &mut [u8; 6] = &mut [0; 6];
+        self.i2c
+            .write_read(I2C_ADDRESS, &[Register::DATA_OUT_X_H as u8], buf)
+            .await?;
+        decode_mag(buf)
+    }
+
+    async fn read_u8_async(&mut self, reg: Register) -> Result<u8, I2C::Error> {
+        let buf: &mut [u8; 1] = &mut [0];
+        self.i2c.write_read(I2C_ADDRESS, &[reg as u8], buf).await?;
+        Ok(buf[0])
+    }
+
+    async fn write_u8_async(&mut self, reg: Register, v: u8) -> Result<(), I2C::Error> {
+        self.i2c.write(I2C_ADDRESS, &[reg as u8, v]).await
+    }
+}
+
+/// Decodes DATA_OUT_X_H through DATA_OUT_Y_L; note the axes are stored x, z, y.
+fn decode_mag<E>(buf: &[u8; 6]) -> Result<(i16, i16, i16), Error<E>> {
+    let x = i16::from_be_bytes([buf[0], buf[1]]);
+    let z = i16::from_be_bytes([buf[2], buf[3]]);
+    let y = i16::from_be_bytes([buf[4], buf[5]]);
+    if [x, y, z].contains(&OVERFLOW) {
+        return Err(Error::Overflow);
+    }
+    Ok((x, y, z))
+}
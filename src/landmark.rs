@@ -1,13 +1,30 @@
+use core::cell::Cell;
+
+use critical_section::{CriticalSection, Mutex};
 use geoconv::{bearing, haversine_distance, Degrees, Lle, Meters, Wgs84};
 
+use crate::generated::LANDMARKS;
+
+/// Index into [`LANDMARKS`] of the landmark currently being navigated to.
+pub static SELECTED_LANDMARK: Mutex<Cell<usize>> = Mutex::new(Cell::new(0));
+
+/// Returns the landmark currently being navigated to.
+pub fn selected(cs: CriticalSection) -> &'static Landmark {
+    &LANDMARKS[SELECTED_LANDMARK.borrow(cs).get() % LANDMARKS.len()]
+}
+
 pub struct Landmark {
     pub name: &'static str,
     pub lle: Lle<Wgs84, Degrees>,
 }
 
 impl Landmark {
+    /// Initial bearing from `other` to the landmark, clockwise from true north.
     pub fn bearing_from(&self, other: (Degrees, Degrees)) -> Degrees {
-        bearing(other, (self.lle.latitude, self.lle.longitude))
+        // geoconv takes the longitude difference the wrong way round and adds 180°, so it returns
+        // 180° minus the bearing
+        let mirrored = bearing(other, (self.lle.latitude, self.lle.longitude)).as_float();
+        Degrees::new((540. - mirrored) % 360.)
     }
     pub fn distance_from(&self, other: (Degrees, Degrees)) -> Meters {
         haversine_distance(other, (self.lle.latitude, self.lle.longitude))
@@ -39,6 +39,7 @@ pub async fn led_ring_task(
     }
 }
 
+#[allow(dead_code)]
 enum ProcessArgument {
     WithNorth(usize),
     WithNorthAndTarget(usize, usize),
@@ -49,6 +50,7 @@ blinksy::layout1d!(RingLayout, 16);
 
 const BUFFER_SIZE: usize = RingLayout::PIXEL_COUNT * 3 * 8 + 1;
 
+#[allow(dead_code)]
 fn radians_to_pixel(rad: f32) -> usize {
     Into::<f32>::into(micromath::F32(rad / PI * 8.).round()) as usize % RingLayout::PIXEL_COUNT
 }
@@ -73,9 +75,7 @@ impl LEDRing {
     fn process(&mut self, _arg: ProcessArgument) {
         self.driver
             .write_pixels(
-                RingLayout::points()
-                    .into_iter()
-                    .map(|_| LinearSrgb::new(0., 1., 0.)),
+                RingLayout::points().map(|_| LinearSrgb::new(0., 1., 0.)),
                 1f32,
                 ColorCorrection::default(),
             )
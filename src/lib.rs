@@ -1,26 +1,73 @@
 #![no_std]
 #![no_main]
-#![feature(f16)]
 #![deny(clippy::mem_forget)]
 
+// Modules that drive the board only build with the `esp` feature, the rest also on the host.
+
+#[cfg(feature = "esp")]
 pub mod app;
 
+pub mod assist;
+
+#[cfg(feature = "esp")]
 pub mod button;
 
+pub mod calibration;
+
+pub mod clock;
+
+#[cfg(feature = "esp")]
+pub mod console;
+
+#[cfg(feature = "esp")]
 pub mod compass;
 
+#[cfg(feature = "esp")]
 pub mod display;
 
+#[cfg(feature = "esp")]
 pub mod gps;
 
+pub mod heading;
+
+pub mod hmc5883l;
+
+#[cfg(feature = "esp")]
 pub mod led_ring;
 
+pub mod magnetometer;
+
+pub mod mpu6050;
+
+pub mod nmea;
+
+pub mod persist;
+
+pub mod power;
+
 pub mod qmc5883l;
 
+pub mod qmc5883p;
+
+pub mod receiver;
+
+pub mod satellites;
+
+pub mod timezone;
+
+pub mod track;
+
+pub mod trip;
+
+pub mod ubx;
+
 pub mod landmark;
 
+#[cfg(feature = "esp")]
 pub mod user_interface;
 
+pub mod wmm;
+
 pub mod generated {
     include! {concat!(env!("OUT_DIR"), "/generated_config.rs")}
 }
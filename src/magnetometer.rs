@@ -0,0 +1,364 @@
+//! Common interface over the magnetometers found on GPS+compass modules.
+//!
+//! Modules sold under the same name carry a QMC5883L, an HMC5883L or a QMC5883P, and QMC5883L
+//! clones do not always report the right CHIP_ID. [`detect`] probes the bus and returns whichever
+//! is fitted behind the [`Magnetometer`] trait.
+
+use core::fmt::Debug;
+
+use embedded_hal_async::i2c::I2c as AsyncI2c;
+
+use crate::generated;
+use crate::hmc5883l::{self, HMC5883L};
+use crate::qmc5883l::{self, QMC5883L};
+use crate::qmc5883p::{self, QMC5883P};
+
+/// Magnetometer Error
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error<E> {
+    /// ID register returned invalid value (first byte returned is argument).
+    InvalidDevice(u8),
+    /// No supported magnetometer answered on the bus.
+    NoDevice,
+    /// Read taken from magnetometer before ready.
+    NotReady,
+    /// Reading overflowed.
+    Overflow,
+    /// Underlying I2C bus error.
+    BusError(E),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::BusError(e)
+    }
+}
+
+/// A reading in units that do not depend on the part or its range.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sample {
+    /// Raw (x,y,z) counts at the active range.
+    pub raw: (i16, i16, i16),
+    /// (x,y,z) in µT.
+    pub mag: (f32, f32, f32),
+    /// Temperature in °C with the configured offset applied, None if the part has no
+    /// temperature sensor.
+    pub temp: Option<f32>,
+}
+
+/// How a DRDY pin signals a new measurement.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Polarity {
+    /// Driven high until the measurement is read.
+    ActiveHigh,
+    /// Open drain, pulled low briefly when the measurement is ready, so it needs a pull-up and
+    /// must be waited on for the falling edge.
+    ActiveLow,
+}
+
+#[allow(async_fn_in_trait)]
+pub trait Magnetometer {
+    type BusError: Debug;
+
+    /// Part name, for diagnostics.
+    fn name(&self) -> &'static str;
+
+    /// Full scale of each field range in gauss, smallest first.
+    fn ranges(&self) -> &'static [f32];
+
+    /// How the DRDY pin signals a new measurement, None if the part has no DRDY pin.
+    fn drdy(&self) -> Option<Polarity>;
+
+    /// Index into [`Magnetometer::ranges`] of the active range.
+    fn range(&self) -> usize;
+
+    /// Switches to `ranges()[range]`.
+    async fn set_range(&mut self, range: usize) -> Result<(), Self::BusError>;
+
+    /// Starts continuous measurements at the rate from the build config.
+    async fn start(&mut self) -> Result<(), Self::BusError>;
+
+    /// Reads the latest measurement.
+    async fn sample(&mut self) -> Result<Sample, Error<Self::BusError>>;
+}
+
+impl<I2C: AsyncI2c> Magnetometer for QMC5883L<I2C> {
+    type BusError = I2C::Error;
+
+    fn name(&self) -> &'static str {
+        "QMC5883L"
+    }
+
+    fn ranges(&self) -> &'static [f32] {
+        &[2., 8.]
+    }
+
+    fn drdy(&self) -> Option<Polarity> {
+        Some(Polarity::ActiveHigh)
+    }
+
+    fn range(&self) -> usize {
+        range_index(&qmc5883l::FieldRange::ALL, self.field_range())
+    }
+
+    async fn set_range(&mut self, range: usize) -> Result<(), Self::BusError> {
+        self.set_field_range_async(qmc5883l::FieldRange::ALL[range])
+            .await
+    }
+
+    async fn start(&mut self) -> Result<(), Self::BusError> {
+        self.set_output_data_rate_async(generated::MAG_OUTPUT_DATA_RATE)
+            .await?;
+        self.set_oversample_async(generated::MAG_OVERSAMPLE).await?;
+        self.enable_interrupt_async().await?;
+        self.continuous_async().await
+    }
+
+    async fn sample(&mut self) -> Result<Sample, Error<Self::BusError>> {
+        let range = self.field_range();
+        let measurement = self.measure_async().await?;
+        Ok(Sample {
+            raw: measurement.mag,
+            mag: range.micro_tesla(measurement.mag),
+            temp: Some(measurement.celsius() + generated::MAG_TEMPERATURE_OFFSET),
+        })
+    }
+}
+
+impl<I2C: AsyncI2c> Magnetometer for HMC5883L<I2C> {
+    type BusError = I2C::Error;
+
+    fn name(&self) -> &'static str {
+        "HMC5883L"
+    }
+
+    fn ranges(&self) -> &'static [f32] {
+        &[0.88, 1.3, 1.9, 2.5, 4.0, 4.7, 5.6, 8.1]
+    }
+
+    fn drdy(&self) -> Option<Polarity> {
+        // Low for 250µs once the data registers are updated
+        Some(Polarity::ActiveLow)
+    }
+
+    fn range(&self) -> usize {
+        range_index(&hmc5883l::FieldRange::ALL, self.field_range())
+    }
+
+    async fn set_range(&mut self, range: usize) -> Result<(), Self::BusError> {
+        self.set_field_range_async(hmc5883l::FieldRange::ALL[range])
+            .await
+    }
+
+    async fn start(&mut self) -> Result<(), Self::BusError> {
+        // Tops out at 75Hz
+        let odr = if generated::MAG_OUTPUT_DATA_RATE.hz() <= 15 {
+            hmc5883l::OutputDataRate::Rate15Hz
+        } else {
+            hmc5883l::OutputDataRate::Rate75Hz
+        };
+        self.set_output_data_rate_async(odr).await?;
+        self.set_averaging_async(hmc5883l::Averaging::Samples8)
+            .await?;
+        self.continuous_async().await
+    }
+
+    async fn sample(&mut self) -> Result<Sample, Error<Self::BusError>> {
+        let range = self.field_range();
+        let raw = self.mag_async().await?;
+        Ok(Sample {
+            raw,
+            mag: range.micro_tesla(raw),
+            temp: None,
+        })
+    }
+}
+
+impl<I2C: AsyncI2c> Magnetometer for QMC5883P<I2C> {
+    type BusError = I2C::Error;
+
+    fn name(&self) -> &'static str {
+        "QMC5883P"
+    }
+
+    fn ranges(&self) -> &'static [f32] {
+        &[2., 8., 12., 30.]
+    }
+
+    fn drdy(&self) -> Option<Polarity> {
+        None
+    }
+
+    fn range(&self) -> usize {
+        range_index(&qmc5883p::FieldRange::ALL, self.field_range())
+    }
+
+    async fn set_range(&mut self, range: usize) -> Result<(), Self::BusError> {
+        self.set_field_range_async(qmc5883p::FieldRange::ALL[range])
+            .await
+    }
+
+    async fn start(&mut self) -> Result<(), Self::BusError> {
+        let odr = match generated::MAG_OUTPUT_DATA_RATE {
+            qmc5883l::OutputDataRate::Rate10Hz => qmc5883p::OutputDataRate::Rate10Hz,
+            qmc5883l::OutputDataRate::Rate50Hz => qmc5883p::OutputDataRate::Rate50Hz,
+            qmc5883l::OutputDataRate::Rate100Hz => qmc5883p::OutputDataRate::Rate100Hz,
+            qmc5883l::OutputDataRate::Rate200Hz => qmc5883p::OutputDataRate::Rate200Hz,
+        };
+        self.set_output_data_rate_async(odr).await?;
+        self.set_oversample_async(qmc5883p::OversampleRate::Rate8)
+            .await?;
+        self.continuous_async().await
+    }
+
+    async fn sample(&mut self) -> Result<Sample, Error<Self::BusError>> {
+        let range = self.field_range();
+        let raw = self.mag_async().await?;
+        Ok(Sample {
+            raw,
+            mag: range.micro_tesla(raw),
+            temp: None,
+        })
+    }
+}
+
+fn range_index<R: PartialEq>(all: &[R], range: R) -> usize {
+    all.iter().position(|r| *r == range).unwrap()
+}
+
+/// Whichever magnetometer [`detect`] found.
+pub enum AnyMagnetometer<I2C> {
+    QMC5883L(QMC5883L<I2C>),
+    HMC5883L(HMC5883L<I2C>),
+    QMC5883P(QMC5883P<I2C>),
+}
+
+macro_rules! dispatch {
+    ($self:ident, $dev:ident => $body:expr) => {
+        match $self {
+            AnyMagnetometer::QMC5883L($dev) => $body,
+            AnyMagnetometer::HMC5883L($dev) => $body,
+            AnyMagnetometer::QMC5883P($dev) => $body,
+        }
+    };
+}
+
+impl<I2C: AsyncI2c> Magnetometer for AnyMagnetometer<I2C> {
+    type BusError = I2C::Error;
+
+    fn name(&self) -> &'static str {
+        dispatch!(self, dev => dev.name())
+    }
+
+    fn ranges(&self) -> &'static [f32] {
+        dispatch!(self, dev => dev.ranges())
+    }
+
+    fn drdy(&self) -> Option<Polarity> {
+        dispatch!(self, dev => dev.drdy())
+    }
+
+    fn range(&self) -> usize {
+        dispatch!(self, dev => dev.range())
+    }
+
+    async fn set_range(&mut self, range: usize) -> Result<(), Self::BusError> {
+        dispatch!(self, dev => dev.set_range(range).await)
+    }
+
+    async fn start(&mut self) -> Result<(), Self::BusError> {
+        dispatch!(self, dev => dev.start().await)
+    }
+
+    async fn sample(&mut self) -> Result<Sample, Error<Self::BusError>> {
+        dispatch!(self, dev => dev.sample().await)
+    }
+}
+
+/// Probes the bus for a supported magnetometer and resets it.
+///
+/// The HMC5883L and QMC5883P are identified by their ID registers. Anything answering at the
+/// QMC5883L address is assumed to be one, since clones report a different CHIP_ID.
+pub async fn detect<I2C: AsyncI2c>(
+    mut i2c: I2C,
+) -> Result<AnyMagnetometer<I2C>, Error<I2C::Error>> {
+    if HMC5883L::probe_async(&mut i2c).await {
+        Ok(AnyMagnetometer::HMC5883L(HMC5883L::new_async(i2c).await?))
+    } else if QMC5883P::probe_async(&mut i2c).await {
+        Ok(AnyMagnetometer::QMC5883P(QMC5883P::new_async(i2c).await?))
+    } else if QMC5883L::probe_async(&mut i2c).await {
+        Ok(AnyMagnetometer::QMC5883L(
+            QMC5883L::new_unchecked_async(i2c).await?,
+        ))
+    } else {
+        Err(Error::NoDevice)
+    }
+}
+
+/// Consecutive overflows before switching to the next larger range.
+pub const OVERFLOWS_TO_RANGE_UP: u8 = 3;
+
+/// Consecutive readings that would fit in the next smaller range before switching down.
+pub const FITS_TO_RANGE_DOWN: u8 = 50;
+
+/// Readings must stay under this fraction of the next smaller range to count towards switching
+/// down, leaving headroom so the range does not flap.
+const RANGE_DOWN_HEADROOM: f32 = 0.75;
+
+/// Picks the field range from recent readings.
+///
+/// Repeated overflows switch up a range so a strong field does not stop readings forever, and a
+/// run of small readings switches back down for the extra resolution.
+#[derive(Debug, Default)]
+pub struct AutoRange {
+    overflows: u8,
+    fits: u8,
+}
+
+impl AutoRange {
+    pub const fn new() -> Self {
+        Self {
+            overflows: 0,
+            fits: 0,
+        }
+    }
+
+    /// Records a reading taken at `ranges[range]` and returns the index of the range to switch
+    /// to, if it should change. `ranges` are full scales in gauss, smallest first.
+    pub fn update<E>(
+        &mut self,
+        ranges: &[f32],
+        range: usize,
+        reading: &Result<Sample, Error<E>>,
+    ) -> Option<usize> {
+        match reading {
+            Err(Error::Overflow) if range + 1 < ranges.len() => {
+                self.fits = 0;
+                self.overflows += 1;
+                if self.overflows >= OVERFLOWS_TO_RANGE_UP {
+                    *self = Self::new();
+                    return Some(range + 1);
+                }
+            }
+            Ok(sample) if range > 0 => {
+                self.overflows = 0;
+                // 1 G = 100 µT
+                let limit = RANGE_DOWN_HEADROOM * ranges[range - 1] * 100.;
+                let (x, y, z) = sample.mag;
+                if [x, y, z].iter().all(|v| v.abs() < limit) {
+                    self.fits += 1;
+                    if self.fits >= FITS_TO_RANGE_DOWN {
+                        *self = Self::new();
+                        return Some(range - 1);
+                    }
+                } else {
+                    self.fits = 0;
+                }
+            }
+            // Not ready readings and bus errors say nothing about the field
+            Err(Error::NotReady) | Err(Error::BusError(_)) => {}
+            _ => *self = Self::new(),
+        }
+        None
+    }
+}
@@ -0,0 +1,215 @@
+//! A driver for the accelerometer of the MPU6050 IMU.
+//! Only what is needed for tilt compensation is implemented, the gyroscope is left asleep.
+//! The MPU6500 and MPU9250 work too, their accelerometer registers are the same.
+
+use embedded_hal::{delay::DelayNs, i2c::I2c};
+use embedded_hal_async::{delay::DelayNs as AsyncDelayNs, i2c::I2c as AsyncI2c};
+
+const I2C_ADDRESS: u8 = 0x68;
+
+/// WHO_AM_I of the MPU6050, and of the MPU6500 and MPU9250 that share its accelerometer
+/// registers and are often sold on the same breakout boards.
+const WHO_AM_I_VALUES: [u8; 3] = [0x68, 0x70, 0x71];
+
+#[allow(dead_code)]
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+#[repr(u8)]
+enum Register {
+    ACCEL_CONFIG = 0x1c,
+    ACCEL_XOUT_H = 0x3b,
+    PWR_MGMT_1 = 0x6b,
+    PWR_MGMT_2 = 0x6c,
+    WHO_AM_I = 0x75,
+}
+
+const PWR_MGMT_1_DEVICE_RESET: u8 = 1 << 7;
+const PWR_MGMT_1_CLKSEL_PLL_X: u8 = 0b001;
+
+/// Time the registers and signal paths take to come out of a device reset.
+const RESET_TIME_MS: u32 = 100;
+
+/// Puts the three gyroscope axes in standby.
+const PWR_MGMT_2_STBY_GYRO: u8 = 0b111;
+
+/// Full scale range of the accelerometer.
+#[derive(Copy, Clone)]
+#[repr(u8)]
+pub enum AccelRange {
+    /// ± 2g
+    Range2G = 0,
+    /// ± 4g
+    Range4G = 1 << 3,
+    /// ± 8g
+    Range8G = 2 << 3,
+    /// ± 16g
+    Range16G = 3 << 3,
+}
+
+impl AccelRange {
+    /// Sensitivity in LSB/g.
+    pub fn lsb_per_g(&self) -> f32 {
+        match self {
+            AccelRange::Range2G => 16384.,
+            AccelRange::Range4G => 8192.,
+            AccelRange::Range8G => 4096.,
+            AccelRange::Range16G => 2048.,
+        }
+    }
+}
+
+/// MPU6050 Error
+#[derive(Debug, Copy, Clone)]
+pub enum Error<E> {
+    /// WHO_AM_I returned invalid value (returned value is argument).
+    InvalidDevice(u8),
+    /// Underlying I2C bus error.
+    BusError(E),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::BusError(e)
+    }
+}
+
+/// MPU6050 driver
+pub struct MPU6050<I2C> {
+    i2c: I2C,
+    range: AccelRange,
+}
+
+impl<I2C> MPU6050<I2C> {
+    /// Converts raw counts to g for the current range.
+    fn scale(&self, (x, y, z): (i16, i16, i16)) -> (f32, f32, f32) {
+        let scale = self.range.lsb_per_g();
+        (x as f32 / scale, y as f32 / scale, z as f32 / scale)
+    }
+}
+
+impl<I2C: I2c> MPU6050<I2C> {
+    /// Creates a new MPU6050 device from an I2C bus; resets it and starts the accelerometer.
+    pub fn new(i2c: I2C, delay: &mut impl DelayNs) -> Result<Self, Error<I2C::Error>> {
+        let mut dev = MPU6050 {
+            i2c,
+            range: AccelRange::Range2G,
+        };
+        let id = dev.read_u8(Register::WHO_AM_I)?;
+        if !WHO_AM_I_VALUES.contains(&id) {
+            return Err(Error::InvalidDevice(id));
+        }
+        dev.reset(delay)?;
+        Ok(dev)
+    }
+
+    /// Reset the device and wake it with only the accelerometer running.
+    pub fn reset(&mut self, delay: &mut impl DelayNs) -> Result<(), I2C::Error> {
+        self.write_u8(Register::PWR_MGMT_1, PWR_MGMT_1_DEVICE_RESET)?;
+        delay.delay_ms(RESET_TIME_MS);
+        self.write_u8(Register::PWR_MGMT_1, PWR_MGMT_1_CLKSEL_PLL_X)?;
+        self.write_u8(Register::PWR_MGMT_2, PWR_MGMT_2_STBY_GYRO)?;
+        self.set_accel_range(AccelRange::Range2G)
+    }
+
+    /// Set the accelerometer full scale range.
+    pub fn set_accel_range(&mut self, range: AccelRange) -> Result<(), I2C::Error> {
+        self.write_u8(Register::ACCEL_CONFIG, range as u8)?;
+        self.range = range;
+        Ok(())
+    }
+
+    /// Read raw (x,y,z) from accelerometer.
+    pub fn accel_raw(&mut self) -> Result<(i16, i16, i16), I2C::Error> {
+        let buf: &mut [u8; 6] = &mut [0; 6];
+        self.i2c
+            .write_read(I2C_ADDRESS, &[Register::ACCEL_XOUT_H as u8], buf)?;
+        Ok(decode_accel(buf))
+    }
+
+    /// Read (x,y,z) acceleration in g.
+    pub fn accel(&mut self) -> Result<(f32, f32, f32), I2C::Error> {
+        let raw = self.accel_raw()?;
+        Ok(self.scale(raw))
+    }
+
+    fn read_u8(&mut self, reg: Register) -> Result<u8, I2C::Error> {
+        let buf: &mut [u8; 1] = &mut [0];
+        self.i2c.write_read(I2C_ADDRESS, &[reg as u8], buf)?;
+        Ok(buf[0])
+    }
+
+    fn write_u8(&mut self, reg: Register, v: u8) -> Result<(), I2C::Error> {
+        self.i2c.write(I2C_ADDRESS, &[reg as u8, v])
+    }
+}
+
+impl<I2C: AsyncI2c> MPU6050<I2C> {
+    /// Creates a new MPU6050 device from an async I2C bus; resets it and starts the accelerometer.
+    pub async fn new_async(
+        i2c: I2C,
+        delay: &mut impl AsyncDelayNs,
+    ) -> Result<Self, Error<I2C::Error>> {
+        let mut dev = MPU6050 {
+            i2c,
+            range: AccelRange::Range2G,
+        };
+        let id = dev.read_u8_async(Register::WHO_AM_I).await?;
+        if !WHO_AM_I_VALUES.contains(&id) {
+            return Err(Error::InvalidDevice(id));
+        }
+        dev.reset_async(delay).await?;
+        Ok(dev)
+    }
+
+    /// Reset the device and wake it with only the accelerometer running.
+    pub async fn reset_async(&mut self, delay: &mut impl AsyncDelayNs) -> Result<(), I2C::Error> {
+        self.write_u8_async(Register::PWR_MGMT_1, PWR_MGMT_1_DEVICE_RESET)
+            .await?;
+        delay.delay_ms(RESET_TIME_MS).await;
+        self.write_u8_async(Register::PWR_MGMT_1, PWR_MGMT_1_CLKSEL_PLL_X)
+            .await?;
+        self.write_u8_async(Register::PWR_MGMT_2, PWR_MGMT_2_STBY_GYRO)
+            .await?;
+        self.set_accel_range_async(AccelRange::Range2G).await
+    }
+
+    /// Set the accelerometer full scale range.
+    pub async fn set_accel_range_async(&mut self, range: AccelRange) -> Result<(), I2C::Error> {
+        self.write_u8_async(Register::ACCEL_CONFIG, range as u8)
+            .await?;
+        self.range = range;
+        Ok(())
+    }
+
+    /// Read raw (x,y,z) from accelerometer.
+    pub async fn accel_raw_async(&mut self) -> Result<(i16, i16, i16), I2C::Error> {
+        let buf: &mut [u8; 6] = &mut [0; 6];
+        self.i2c
+            .write_read(I2C_ADDRESS, &[Register::ACCEL_XOUT_H as u8], buf)
+            .await?;
+        Ok(decode_accel(buf))
+    }
+
+    /// Read (x,y,z) acceleration in g.
+    pub async fn accel_async(&mut self) -> Result<(f32, f32, f32), I2C::Error> {
+        let raw = self.accel_raw_async().await?;
+        Ok(self.scale(raw))
+    }
+
+    async fn read_u8_async(&mut self, reg: Register) -> Result<u8, I2C::Error> {
+        let buf: &mut [u8; 1] = &mut [0];
+        self.i2c.write_read(I2C_ADDRESS, &[reg as u8], buf).await?;
+        Ok(buf[0])
+    }
+
+    async fn write_u8_async(&mut self, reg: Register, v: u8) -> Result<(), I2C::Error> {
+        self.i2c.write(I2C_ADDRESS, &[reg as u8, v]).await
+    }
+}
+
+fn decode_accel(buf: &[u8; 6]) -> (i16, i16, i16) {
+    let x = i16::from_be_bytes([buf[0], buf[1]]);
+    let y = i16::from_be_bytes([buf[2], buf[3]]);
+    let z = i16::from_be_bytes([buf[4], buf[5]]);
+    (x, y, z)
+}
@@ -0,0 +1,347 @@
+//! NMEA 0183 framing and parsing.
+//!
+//! Picks checksummed sentences out of the byte stream from the receiver. Like [`crate::ubx`] it
+//! keeps state between calls so sentences split across UART reads are recovered. [`parse`] then
+//! decodes the sentences needed to navigate with receivers that do not speak UBX.
+
+use core::str::FromStr;
+
+use chrono::{NaiveDate, NaiveTime};
+
+/// Longest sentence allowed by NMEA 0183, `$` and checksum included.
+pub const MAX_SENTENCE_LEN: usize = 82;
+
+/// XOR of everything between `$` and `*`.
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |acc, byte| acc ^ byte)
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        _ => None,
+    }
+}
+
+/// Collects bytes into NMEA sentences.
+pub struct Framer {
+    buf: [u8; MAX_SENTENCE_LEN],
+    len: usize,
+    sentences: u32,
+}
+
+impl Framer {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_SENTENCE_LEN],
+            len: 0,
+            sentences: 0,
+        }
+    }
+
+    /// Sentences with a valid checksum seen so far.
+    pub fn sentences(&self) -> u32 {
+        self.sentences
+    }
+
+    /// Adds a byte, returning the sentence it completes if any: everything between `$` and `*`,
+    /// e.g. `GNGGA,...`.
+    pub fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        if byte == b'$' {
+            self.buf[0] = byte;
+            self.len = 1;
+            return None;
+        }
+        // Waiting for `$`, or binary data from a UBX frame or the wrong baud rate
+        if self.len == 0 || !(0x20..0x7f).contains(&byte) || self.len == MAX_SENTENCE_LEN {
+            self.len = 0;
+            return None;
+        }
+
+        self.buf[self.len] = byte;
+        self.len += 1;
+
+        // `*` and two hex digits end the sentence
+        if self.len < 4 || self.buf[self.len - 3] != b'*' {
+            return None;
+        }
+        let len = self.len;
+        self.len = 0;
+
+        let (high, low) = (hex_digit(self.buf[len - 2])?, hex_digit(self.buf[len - 1])?);
+        let data = &self.buf[1..len - 3];
+        if checksum(data) != high << 4 | low {
+            return None;
+        }
+
+        self.sentences += 1;
+        Some(data)
+    }
+}
+
+impl Default for Framer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// m/s per knot.
+const KNOT: f64 = 1852. / 3600.;
+
+/// m/s per km/h.
+const KILOMETRE_PER_HOUR: f64 = 1. / 3.6;
+
+/// Most fields in a supported sentence, address excluded.
+const MAX_FIELDS: usize = 20;
+
+/// Fix data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gga {
+    /// UTC.
+    pub time: Option<NaiveTime>,
+    /// Latitude and longitude in degrees, positive north and east.
+    pub position: Option<(f64, f64)>,
+    /// 0 for no fix, 1 for autonomous GNSS, 2 for differential, 6 for dead reckoning...
+    pub quality: u8,
+    pub satellites_used: u8,
+    pub hdop: Option<f64>,
+    /// Altitude above mean sea level in metres.
+    pub altitude_msl: Option<f64>,
+    /// Height of the geoid above the ellipsoid in metres.
+    pub geoid_separation: Option<f64>,
+}
+
+/// Recommended minimum data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rmc {
+    /// UTC.
+    pub time: Option<NaiveTime>,
+    /// Status A, the receiver considers the position valid.
+    pub valid: bool,
+    /// Latitude and longitude in degrees, positive north and east.
+    pub position: Option<(f64, f64)>,
+    /// Speed over ground in m/s.
+    pub speed: Option<f64>,
+    /// Course over ground in degrees from true north.
+    pub course: Option<f64>,
+    pub date: Option<NaiveDate>,
+    /// Magnetic variation in degrees, positive east.
+    pub magnetic_variation: Option<f64>,
+}
+
+/// DOP and active satellites.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gsa {
+    /// Talker id, GN when the receiver combines constellations.
+    pub talker: [u8; 2],
+    /// NMEA 4.10 system id of the satellites, which GN sentences otherwise leave unsaid.
+    pub system: Option<u8>,
+    /// 1 for no fix, 2 for 2D, 3 for 3D.
+    pub fix: u8,
+    /// Ids of the satellites used in the fix.
+    pub satellites: [Option<u16>; 12],
+    pub pdop: Option<f64>,
+    pub hdop: Option<f64>,
+    pub vdop: Option<f64>,
+}
+
+/// A satellite from a GSV sentence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SatelliteInView {
+    pub id: u16,
+    /// Elevation in degrees.
+    pub elevation: Option<u8>,
+    /// Azimuth in degrees from true north.
+    pub azimuth: Option<u16>,
+    /// Signal strength in dBHz, None when not tracked.
+    pub cno: Option<u8>,
+}
+
+/// Satellites in view, up to four per sentence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gsv {
+    /// Talker id, which tells the constellation.
+    pub talker: [u8; 2],
+    /// Number of sentences in this group.
+    pub sentences: u8,
+    /// Index of this sentence in the group, from 1.
+    pub sentence: u8,
+    /// Satellites in view, across the whole group.
+    pub in_view: u8,
+    pub satellites: [Option<SatelliteInView>; 4],
+}
+
+/// Course and speed over ground.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vtg {
+    /// Course over ground in degrees from true north.
+    pub course: Option<f64>,
+    /// Speed over ground in m/s.
+    pub speed: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sentence {
+    Gga(Gga),
+    Rmc(Rmc),
+    Gsa(Gsa),
+    Gsv(Gsv),
+    Vtg(Vtg),
+}
+
+/// Decodes sentence data as returned by [`Framer::push`], any talker. None for other sentence
+/// types and malformed sentences.
+pub fn parse(data: &[u8]) -> Option<Sentence> {
+    let mut split = data.split(|&byte| byte == b',');
+    let address = split.next()?;
+    if address.len() != 5 {
+        return None;
+    }
+
+    let mut fields = Fields {
+        fields: [&[]; MAX_FIELDS],
+        len: 0,
+    };
+    for field in split.take(MAX_FIELDS) {
+        fields.fields[fields.len] = field;
+        fields.len += 1;
+    }
+
+    match &address[2..] {
+        b"GGA" if fields.len >= 14 => Some(Sentence::Gga(Gga {
+            time: fields.time(0),
+            position: fields.position(1),
+            quality: fields.get(5)?,
+            satellites_used: fields.get(6).unwrap_or(0),
+            hdop: fields.get(7),
+            altitude_msl: fields.get(8),
+            geoid_separation: fields.get(10),
+        })),
+        b"RMC" if fields.len >= 11 => Some(Sentence::Rmc(Rmc {
+            time: fields.time(0),
+            valid: fields.fields[1] == b"A",
+            position: fields.position(2),
+            speed: fields.get::<f64>(6).map(|knots| knots * KNOT),
+            course: fields.get(7),
+            date: fields.date(8),
+            magnetic_variation: fields
+                .get::<f64>(9)
+                .zip(fields.hemisphere(10, b"E", b"W"))
+                .map(|(variation, sign)| sign * variation),
+        })),
+        b"GSA" if fields.len >= 17 => Some(Sentence::Gsa(Gsa {
+            talker: [address[0], address[1]],
+            system: fields.get(17),
+            fix: fields.get(1)?,
+            satellites: core::array::from_fn(|i| fields.get(2 + i)),
+            pdop: fields.get(14),
+            hdop: fields.get(15),
+            vdop: fields.get(16),
+        })),
+        b"GSV" if fields.len >= 3 => {
+            let mut satellites = [None; 4];
+            // NMEA 4.10 adds a signal id after the last satellite
+            for (i, satellite) in satellites.iter_mut().enumerate().take((fields.len - 3) / 4) {
+                let first = 3 + 4 * i;
+                // Some receivers pad the last sentence of a group with empty slots, and leave out
+                // the elevation and azimuth of satellites whose orbit they do not know yet
+                *satellite = fields.get(first).map(|id| SatelliteInView {
+                    id,
+                    elevation: fields.get(first + 1),
+                    azimuth: fields.get(first + 2),
+                    cno: fields.get(first + 3),
+                });
+            }
+            Some(Sentence::Gsv(Gsv {
+                talker: [address[0], address[1]],
+                sentences: fields.get(0)?,
+                sentence: fields.get(1)?,
+                in_view: fields.get(2)?,
+                satellites,
+            }))
+        }
+        b"VTG" if fields.len >= 8 => Some(Sentence::Vtg(Vtg {
+            course: fields.get(0),
+            speed: fields
+                .get::<f64>(6)
+                .map(|kph| kph * KILOMETRE_PER_HOUR)
+                .or(fields.get::<f64>(4).map(|knots| knots * KNOT)),
+        })),
+        _ => None,
+    }
+}
+
+/// Comma separated fields after the address. Empty fields read as None.
+struct Fields<'a> {
+    fields: [&'a [u8]; MAX_FIELDS],
+    len: usize,
+}
+
+impl Fields<'_> {
+    fn get<T: FromStr>(&self, index: usize) -> Option<T> {
+        parse_field(self.fields[index])
+    }
+
+    /// `hhmmss.ss`
+    fn time(&self, index: usize) -> Option<NaiveTime> {
+        let field = self.fields[index];
+        if field.len() < 6 {
+            return None;
+        }
+        let nanosecond = match &field[6..] {
+            [] => 0,
+            fraction => libm::round(parse_field::<f64>(fraction)? * 1e9) as u32,
+        };
+        NaiveTime::from_hms_nano_opt(
+            parse_field(&field[0..2])?,
+            parse_field(&field[2..4])?,
+            parse_field(&field[4..6])?,
+            nanosecond,
+        )
+    }
+
+    /// `ddmmyy`
+    fn date(&self, index: usize) -> Option<NaiveDate> {
+        let field = self.fields[index];
+        if field.len() != 6 {
+            return None;
+        }
+        NaiveDate::from_ymd_opt(
+            2000 + parse_field::<i32>(&field[4..6])?,
+            parse_field(&field[2..4])?,
+            parse_field(&field[0..2])?,
+        )
+    }
+
+    /// `ddmm.mm,N,dddmm.mm,E` starting at `index`, in degrees.
+    fn position(&self, index: usize) -> Option<(f64, f64)> {
+        let latitude = self.angle(index, 2)? * self.hemisphere(index + 1, b"N", b"S")?;
+        let longitude = self.angle(index + 2, 3)? * self.hemisphere(index + 3, b"E", b"W")?;
+        Some((latitude, longitude))
+    }
+
+    /// Degrees from `degree_digits` digits of degrees followed by decimal minutes.
+    fn angle(&self, index: usize, degree_digits: usize) -> Option<f64> {
+        let field = self.fields[index];
+        if field.len() <= degree_digits {
+            return None;
+        }
+        let degrees: f64 = parse_field(&field[..degree_digits])?;
+        let minutes: f64 = parse_field(&field[degree_digits..])?;
+        Some(degrees + minutes / 60.)
+    }
+
+    fn hemisphere(&self, index: usize, positive: &[u8], negative: &[u8]) -> Option<f64> {
+        match self.fields[index] {
+            field if field == positive => Some(1.),
+            field if field == negative => Some(-1.),
+            _ => None,
+        }
+    }
+}
+
+fn parse_field<T: FromStr>(field: &[u8]) -> Option<T> {
+    core::str::from_utf8(field).ok()?.parse().ok()
+}
@@ -0,0 +1,261 @@
+//! State kept in RTC memory through deep sleep: the last known fix, the selected landmark and
+//! the compass calibration.
+//!
+//! The last fix is kept up to date while running, so the compass can point at the landmark from
+//! it while the receiver reacquires, whether after deep sleep or a fix lost on the way. Before
+//! deep sleep everything is written to RTC memory as a [`Snapshot`] of words ending in a
+//! checksum, and restored on boot if the checksum matches.
+
+use core::cell::Cell;
+
+use chrono::{DateTime, NaiveDateTime};
+use critical_section::Mutex;
+#[cfg(feature = "esp")]
+use esp_hal::ram;
+use geoconv::Degrees;
+
+use crate::calibration::Calibration;
+#[cfg(feature = "esp")]
+use crate::compass::CALIBRATION;
+#[cfg(feature = "esp")]
+use crate::landmark::SELECTED_LANDMARK;
+use crate::receiver::NavPvtState;
+
+/// The last fix good enough to navigate by, None until there was one.
+pub static LAST_FIX: Mutex<Cell<Option<LastFix>>> = Mutex::new(Cell::new(None));
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LastFix {
+    /// Latitude in degrees.
+    pub latitude: f64,
+    /// Longitude in degrees.
+    pub longitude: f64,
+    /// Height above the ellipsoid in metres.
+    pub height: f64,
+    /// UTC of the fix, None if the receiver had not resolved the date.
+    pub utc: Option<NaiveDateTime>,
+    /// Magnetic declination in degrees, positive east, NaN if unknown.
+    pub declination: f64,
+}
+
+impl LastFix {
+    /// From a navigation solution good enough to navigate by.
+    pub fn from_state(state: &NavPvtState) -> Option<Self> {
+        let lle = state.position()?;
+        Some(Self {
+            latitude: lle.latitude.as_float(),
+            longitude: lle.longitude.as_float(),
+            height: lle.elevation.as_float(),
+            utc: state.date_time(),
+            declination: state.declination().unwrap_or(f64::NAN),
+        })
+    }
+
+    /// Latitude and longitude.
+    pub fn position(&self) -> (Degrees, Degrees) {
+        (Degrees::new(self.latitude), Degrees::new(self.longitude))
+    }
+}
+
+/// Words in a [`Snapshot`].
+pub const WORDS: usize = 30;
+
+/// Marks a snapshot, RTC fast memory holds garbage after power on.
+const MAGIC: u32 = 0x7374_6174;
+
+const HAS_FIX: u32 = 1 << 0;
+const HAS_UTC: u32 = 1 << 1;
+const HAS_TEMP: u32 = 1 << 2;
+
+/// Everything kept through deep sleep.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapshot {
+    pub last_fix: Option<LastFix>,
+    /// Index into the landmarks.
+    pub landmark: usize,
+    pub calibration: Calibration,
+}
+
+impl Snapshot {
+    /// As words: [`MAGIC`], flags, the fix, the landmark and the calibration, then a checksum of
+    /// the rest.
+    pub fn to_words(&self) -> [u32; WORDS] {
+        let fix = self.last_fix.unwrap_or(LastFix {
+            latitude: 0.,
+            longitude: 0.,
+            height: 0.,
+            utc: None,
+            declination: f64::NAN,
+        });
+        let calibration = &self.calibration;
+
+        let mut flags = 0;
+        if self.last_fix.is_some() {
+            flags |= HAS_FIX;
+        }
+        if fix.utc.is_some() {
+            flags |= HAS_UTC;
+        }
+        if calibration.temp.is_some() {
+            flags |= HAS_TEMP;
+        }
+
+        let mut words = Words::new([0; WORDS]);
+        words.push(MAGIC);
+        words.push(flags);
+        words.push_u64(fix.latitude.to_bits());
+        words.push_u64(fix.longitude.to_bits());
+        words.push_u64(fix.height.to_bits());
+        words.push_u64(fix.utc.map_or(0, |utc| utc.and_utc().timestamp_micros()) as u64);
+        words.push_u64(fix.declination.to_bits());
+        words.push(self.landmark as u32);
+
+        let (x, y, z) = calibration.offset;
+        for value in [x, y, z] {
+            words.push(value.to_bits());
+        }
+        for value in calibration.soft_iron.as_flattened() {
+            words.push(value.to_bits());
+        }
+        words.push(calibration.temp.unwrap_or(0.).to_bits());
+        let (x, y, z) = calibration.drift;
+        for value in [x, y, z] {
+            words.push(value.to_bits());
+        }
+
+        let check = checksum(&words.words[..WORDS - 1]);
+        words.push(check);
+        words.words
+    }
+
+    /// From [`Snapshot::to_words`], None if the magic or checksum do not match.
+    pub fn from_words(words: &[u32; WORDS]) -> Option<Self> {
+        if words[0] != MAGIC || words[WORDS - 1] != checksum(&words[..WORDS - 1]) {
+            return None;
+        }
+        let mut words = Words::new(*words);
+        words.next();
+
+        let flags = words.next();
+        let fix = LastFix {
+            latitude: f64::from_bits(words.next_u64()),
+            longitude: f64::from_bits(words.next_u64()),
+            height: f64::from_bits(words.next_u64()),
+            utc: DateTime::from_timestamp_micros(words.next_u64() as i64)
+                .map(|utc| utc.naive_utc())
+                .filter(|_| flags & HAS_UTC != 0),
+            declination: f64::from_bits(words.next_u64()),
+        };
+        let landmark = words.next() as usize;
+
+        let offset = (words.next_f32(), words.next_f32(), words.next_f32());
+        let mut soft_iron = [[0.; 3]; 3];
+        for value in soft_iron.as_flattened_mut() {
+            *value = words.next_f32();
+        }
+        let temp = words.next_f32();
+        let drift = (words.next_f32(), words.next_f32(), words.next_f32());
+
+        Some(Self {
+            last_fix: (flags & HAS_FIX != 0).then_some(fix),
+            landmark,
+            calibration: Calibration {
+                offset,
+                soft_iron,
+                temp: (flags & HAS_TEMP != 0).then_some(temp),
+                drift,
+            },
+        })
+    }
+}
+
+/// Writes or reads a snapshot a word at a time.
+struct Words {
+    words: [u32; WORDS],
+    len: usize,
+}
+
+impl Words {
+    fn new(words: [u32; WORDS]) -> Self {
+        Self { words, len: 0 }
+    }
+
+    fn push(&mut self, word: u32) {
+        self.words[self.len] = word;
+        self.len += 1;
+    }
+
+    /// Low word first.
+    fn push_u64(&mut self, value: u64) {
+        self.push(value as u32);
+        self.push((value >> 32) as u32);
+    }
+
+    fn next(&mut self) -> u32 {
+        let word = self.words[self.len];
+        self.len += 1;
+        word
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.next() as u64 | (self.next() as u64) << 32
+    }
+
+    fn next_f32(&mut self) -> f32 {
+        f32::from_bits(self.next())
+    }
+}
+
+/// FNV-1a over the bytes of `words`.
+fn checksum(words: &[u32]) -> u32 {
+    words
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .fold(0x811c_9dc5, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        })
+}
+
+/// The last fix good enough to navigate by, see [`LAST_FIX`].
+pub fn last_fix() -> Option<LastFix> {
+    critical_section::with(|cs| LAST_FIX.borrow(cs).get())
+}
+
+/// Keeps the fix in `state` as the last one, if it is good enough to navigate by.
+pub fn update(state: &NavPvtState) {
+    if let Some(fix) = LastFix::from_state(state) {
+        critical_section::with(|cs| LAST_FIX.borrow(cs).set(Some(fix)));
+    }
+}
+
+#[cfg(feature = "esp")]
+#[ram(unstable(rtc_fast, persistent))]
+static mut SAVED: [u32; WORDS] = [0; WORDS];
+
+/// Keeps the last fix, selected landmark and calibration in RTC memory for [`restore`].
+#[cfg(feature = "esp")]
+pub fn save() {
+    let snapshot = critical_section::with(|cs| Snapshot {
+        last_fix: LAST_FIX.borrow(cs).get(),
+        landmark: SELECTED_LANDMARK.borrow(cs).get(),
+        calibration: CALIBRATION.borrow(cs).get(),
+    });
+    // SAFETY: only touched from here and `restore`, before tasks start and before deep sleep
+    unsafe { core::ptr::addr_of_mut!(SAVED).write_volatile(snapshot.to_words()) };
+}
+
+/// Brings back what [`save`] kept before deep sleep. Returns whether there was anything.
+#[cfg(feature = "esp")]
+pub fn restore() -> bool {
+    // SAFETY: see `save`
+    let words = unsafe { core::ptr::addr_of!(SAVED).read_volatile() };
+    let Some(snapshot) = Snapshot::from_words(&words) else {
+        return false;
+    };
+    critical_section::with(|cs| {
+        LAST_FIX.borrow(cs).set(snapshot.last_fix);
+        SELECTED_LANDMARK.borrow(cs).set(snapshot.landmark);
+        CALIBRATION.borrow(cs).set(snapshot.calibration);
+    });
+    true
+}
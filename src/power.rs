@@ -0,0 +1,161 @@
+//! Power management of the GNSS receiver, the biggest draw on the board.
+//!
+//! [`Policy`] picks a [`PowerMode`] from what the rest of the device is doing: continuous while
+//! in use and moving, cyclic tracking once the screen is off or the device has been stationary
+//! for a while, and backup before deep sleep. [`crate::gps`] applies it with the messages built
+//! here.
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use embassy_time::{Duration, Instant};
+
+use crate::trip::MOVING_SPEED;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerMode {
+    /// Tracking all the time, the best fix at the highest current.
+    Continuous,
+    /// Cyclic tracking, powering down between fixes.
+    PowerSave,
+    /// Everything off but the RTC and backup RAM, so the next fix is a hot start.
+    Backup,
+}
+
+/// Mode the receiver was last put in.
+pub static POWER_MODE: Mutex<Cell<PowerMode>> = Mutex::new(Cell::new(PowerMode::Continuous));
+
+static SCREEN_ON: Mutex<Cell<bool>> = Mutex::new(Cell::new(true));
+
+static SLEEP_REQUESTED: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// Speed over ground in m/s below which the device counts as stationary. Under
+/// [`MOVING_SPEED`], so noise in the speed does not switch modes back and forth.
+pub const STATIONARY_SPEED: f64 = 0.3;
+
+/// How long the device has to be stationary before the receiver saves power.
+pub const STATIONARY_AFTER: Duration = Duration::from_secs(60);
+
+/// Time between fixes in power save mode.
+pub const UPDATE_PERIOD_MS: u32 = 1000;
+
+/// Time between attempts to acquire satellites in power save mode without a fix.
+pub const SEARCH_PERIOD_MS: u32 = 10_000;
+
+pub const CFG_RXM: (u8, u8) = (0x06, 0x11);
+pub const CFG_PM2: (u8, u8) = (0x06, 0x3b);
+pub const RXM_PMREQ: (u8, u8) = (0x02, 0x41);
+
+/// Reports whether the screen is on.
+pub fn set_screen_on(on: bool) {
+    critical_section::with(|cs| SCREEN_ON.borrow(cs).set(on));
+}
+
+/// Asks for the receiver to go to backup mode ahead of deep sleep, see [`mode`] for when it has.
+pub fn request_sleep() {
+    critical_section::with(|cs| SLEEP_REQUESTED.borrow(cs).set(true));
+}
+
+pub fn mode() -> PowerMode {
+    critical_section::with(|cs| POWER_MODE.borrow(cs).get())
+}
+
+/// What the policy decides on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Inputs {
+    pub screen_on: bool,
+    pub sleep_requested: bool,
+    /// Speed over ground in m/s, None without a fix to navigate by.
+    pub speed: Option<f64>,
+}
+
+impl Inputs {
+    /// The screen and sleep request as reported, with `speed` from the navigation state.
+    pub fn now(speed: Option<f64>) -> Self {
+        critical_section::with(|cs| Self {
+            screen_on: SCREEN_ON.borrow(cs).get(),
+            sleep_requested: SLEEP_REQUESTED.borrow(cs).get(),
+            speed,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Policy {
+    /// Since when the device has been stationary.
+    stationary_since: Option<Instant>,
+}
+
+impl Policy {
+    pub const fn new() -> Self {
+        Self {
+            stationary_since: None,
+        }
+    }
+
+    /// The mode the receiver should be in at `now`.
+    pub fn mode(&mut self, inputs: Inputs, now: Instant) -> PowerMode {
+        match inputs.speed {
+            Some(speed) if speed < STATIONARY_SPEED => {
+                self.stationary_since.get_or_insert(now);
+            }
+            // In between, carry on as before
+            Some(speed) if speed < MOVING_SPEED => {}
+            // Without a fix the receiver needs to search at full power
+            _ => self.stationary_since = None,
+        }
+        let stationary = self.stationary_since.is_some_and(|since| {
+            now.checked_duration_since(since)
+                .is_some_and(|stationary| stationary >= STATIONARY_AFTER)
+        });
+
+        if inputs.sleep_requested {
+            PowerMode::Backup
+        } else if !inputs.screen_on || stationary {
+            PowerMode::PowerSave
+        } else {
+            PowerMode::Continuous
+        }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CFG-RXM payload selecting continuous or power save mode.
+pub fn cfg_rxm(power_save: bool) -> [u8; 2] {
+    // The first byte is reserved and must be 8
+    [0x08, power_save as u8]
+}
+
+/// CFG-PM2 payload for cyclic tracking every [`UPDATE_PERIOD_MS`], searching every
+/// [`SEARCH_PERIOD_MS`] without a fix. The receiver is kept from turning off for good when
+/// acquisition fails and updates its ephemeris while tracking.
+pub fn cfg_pm2() -> [u8; 44] {
+    const UPDATE_EPH: u32 = 1 << 12;
+    const DO_NOT_ENTER_OFF: u32 = 1 << 16;
+    const CYCLIC_TRACKING: u32 = 1 << 17;
+
+    let mut payload = [0u8; 44];
+    // Version
+    payload[0] = 0x01;
+    payload[4..8].copy_from_slice(&(UPDATE_EPH | DO_NOT_ENTER_OFF | CYCLIC_TRACKING).to_le_bytes());
+    payload[8..12].copy_from_slice(&UPDATE_PERIOD_MS.to_le_bytes());
+    payload[12..16].copy_from_slice(&SEARCH_PERIOD_MS.to_le_bytes());
+    payload
+}
+
+/// RXM-PMREQ payload putting the receiver in backup mode until woken by activity on its UART.
+pub fn rxm_pmreq_backup() -> [u8; 16] {
+    const BACKUP: u32 = 1 << 1;
+    const WAKE_ON_UART_RX: u32 = 1 << 3;
+
+    // Version 0, then a duration of 0 which lasts until woken
+    let mut payload = [0u8; 16];
+    payload[8..12].copy_from_slice(&BACKUP.to_le_bytes());
+    payload[12..16].copy_from_slice(&WAKE_ON_UART_RX.to_le_bytes());
+    payload
+}
@@ -1,15 +1,16 @@
-//! A esp-hal driver to interface with the QMC5883L magnetometer.
+//! A embedded-hal driver to interface with the QMC5883L magnetometer.
 //! Forked from https://github.com/heyitsanthony/qmc5883l
 
-use esp_hal::{
-    i2c::{self, master::I2c},
-    Async,
-};
+use embedded_hal::i2c::I2c;
+use embedded_hal_async::i2c::I2c as AsyncI2c;
+
+pub use crate::magnetometer::Error;
 
 const I2C_ADDRESS: u8 = 0x0d;
 
 #[allow(dead_code)]
 #[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone)]
 #[repr(u8)]
 enum Register {
@@ -33,11 +34,17 @@ const STATUS_DRDY: u8 = 0b001;
 
 const MODE_CONTINUOUS: u8 = 0b01;
 
+const CHIP_ID_VALUE: u8 = 0xff;
+
 const CTRL2_SOFT_RST: u8 = 1 << 7;
+/// Pointer roll-over wraps reads at STATUS back to DATA_OUT_X_L. It is left disabled so a burst
+/// read can run on into TOUT.
+#[allow(dead_code)]
 const CTRL2_ROL_PNT: u8 = 1 << 6;
 const CTRL2_INT_ENB: u8 = 1 << 0;
 
 /// Update frequency; 10Hz recommended for low power consumption.
+#[derive(Debug, Copy, Clone, PartialEq)]
 #[repr(u8)]
 pub enum OutputDataRate {
     /// 10Hz update rate.
@@ -50,8 +57,21 @@ pub enum OutputDataRate {
     Rate200Hz = 0b1100,
 }
 
+impl OutputDataRate {
+    /// Samples per second.
+    pub fn hz(&self) -> u32 {
+        match self {
+            OutputDataRate::Rate10Hz => 10,
+            OutputDataRate::Rate50Hz => 50,
+            OutputDataRate::Rate100Hz => 100,
+            OutputDataRate::Rate200Hz => 200,
+        }
+    }
+}
+
 /// Oversampling rate; controls bandwidth of internal digital filter.
 /// Larger oversampling gets less in-band noise but higher power consumption.
+#[derive(Debug, Copy, Clone, PartialEq)]
 #[repr(u8)]
 pub enum OversampleRate {
     /// Oversample by 64.
@@ -65,131 +85,361 @@ pub enum OversampleRate {
 }
 
 /// Field range of magnetic sensor.
+#[derive(Debug, Copy, Clone, PartialEq)]
 #[repr(u8)]
 pub enum FieldRange {
     /// ± 2 gauss
     Range2Gauss = 0,
     /// ± 8 gauss
-    Range8Gauss = 1 << 3,
+    Range8Gauss = 1 << 4,
 }
 
-/// QMC5883L Error
-#[derive(Debug, Copy, Clone)]
-pub enum Error {
-    /// CHIP_ID returned invalid value (returned value is argument).
-    InvalidDevice(u8),
-    /// Read taken from magnetometer before ready.
-    NotReady,
-    /// Reading overflowed.
-    Overflow,
-    /// Underlying I2C bus error.
-    BusError(i2c::master::Error),
+/// RNG occupies bits 4 and 5 of CONTROL1.
+const CTRL1_RNG_MASK: u8 = 0b11 << 4;
+
+impl FieldRange {
+    /// Every range, smallest first.
+    pub const ALL: [FieldRange; 2] = [FieldRange::Range2Gauss, FieldRange::Range8Gauss];
+
+    /// Sensitivity in LSB/G.
+    pub fn lsb_per_gauss(&self) -> f32 {
+        match self {
+            FieldRange::Range2Gauss => 12000.,
+            FieldRange::Range8Gauss => 3000.,
+        }
+    }
+
+    /// Converts raw (x,y,z) counts taken at this range to µT.
+    pub fn micro_tesla(&self, (x, y, z): (i16, i16, i16)) -> (f32, f32, f32) {
+        // 1 G = 100 µT
+        let lsb_per_micro_tesla = self.lsb_per_gauss() / 100.;
+        (
+            x as f32 / lsb_per_micro_tesla,
+            y as f32 / lsb_per_micro_tesla,
+            z as f32 / lsb_per_micro_tesla,
+        )
+    }
 }
 
-impl From<i2c::master::Error> for Error {
-    fn from(e: i2c::master::Error) -> Self {
-        Error::BusError(e)
+/// Sensitivity of the temperature sensor. Only the slope is specified, the offset differs from
+/// part to part.
+pub const TEMP_LSB_PER_CELSIUS: f32 = 100.;
+
+/// Magnetometer and temperature read in one transaction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Measurement {
+    /// Raw (x,y,z) from magnetometer.
+    pub mag: (i16, i16, i16),
+    /// Raw temperature; temperature coefficient is about 100 LSB/°C.
+    pub temp: i16,
+}
+
+impl Measurement {
+    /// Temperature in °C, before correcting for the part's offset.
+    pub fn celsius(&self) -> f32 {
+        self.temp as f32 / TEMP_LSB_PER_CELSIUS
     }
 }
 
 /// QMC5883L driver
-pub struct QMC5883L {
-    i2c: I2c<'static, Async>,
+pub struct QMC5883L<I2C> {
+    i2c: I2C,
+    range: FieldRange,
+}
+
+impl<I2C> QMC5883L<I2C> {
+    /// Destroys the driver and returns the I2C bus.
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// The field range raw readings are currently taken at.
+    pub fn field_range(&self) -> FieldRange {
+        self.range
+    }
 }
 
-impl QMC5883L {
-    /// Creates a new QMC5883L device from an I2C peripheral; begins with a soft reset.
-    pub fn new(i2c: I2c<'static, Async>) -> Result<Self, Error> {
-        let mut dev = QMC5883L { i2c: i2c };
+impl<I2C: I2c> QMC5883L<I2C> {
+    /// Creates a new QMC5883L device from an I2C bus; begins with a soft reset.
+    pub fn new(i2c: I2C) -> Result<Self, Error<I2C::Error>> {
+        let mut dev = QMC5883L {
+            i2c,
+            range: FieldRange::Range2Gauss,
+        };
         let id = dev.read_u8(Register::CHIP_ID)?;
-        if id != 0xff {
+        if id != CHIP_ID_VALUE {
             return Err(Error::InvalidDevice(id));
         }
         dev.reset()?;
         Ok(dev)
     }
 
+    /// Creates a new QMC5883L device without checking CHIP_ID, for clones that report another
+    /// value; begins with a soft reset.
+    pub fn new_unchecked(i2c: I2C) -> Result<Self, Error<I2C::Error>> {
+        let mut dev = QMC5883L {
+            i2c,
+            range: FieldRange::Range2Gauss,
+        };
+        dev.reset()?;
+        Ok(dev)
+    }
+
+    /// Returns true if anything answers at the QMC5883L address, clones included.
+    pub fn probe(i2c: &mut I2C) -> bool {
+        let buf: &mut [u8; 1] = &mut [0];
+        i2c.write_read(I2C_ADDRESS, &[Register::CHIP_ID as u8], buf)
+            .is_ok()
+    }
+
     /// Soft reset the device.
-    pub fn reset(&mut self) -> Result<(), i2c::master::Error> {
+    pub fn reset(&mut self) -> Result<(), I2C::Error> {
         self.write_u8(Register::CONTROL2, CTRL2_SOFT_RST)?;
-        self.write_u8(Register::CONTROL2, CTRL2_ROL_PNT | CTRL2_INT_ENB)?;
+        self.write_u8(Register::CONTROL2, CTRL2_INT_ENB)?;
+        self.range = FieldRange::Range2Gauss;
         self.write_u8(Register::PERIOD, 1)
     }
 
     /// Set the device field range.
-    pub fn set_field_range(&mut self, rng: FieldRange) -> Result<(), i2c::master::Error> {
+    pub fn set_field_range(&mut self, rng: FieldRange) -> Result<(), I2C::Error> {
         let ctrl1 = self.read_u8(Register::CONTROL1)?;
-        let v = (ctrl1 & !(FieldRange::Range8Gauss as u8)) | (rng as u8);
-        self.write_u8(Register::CONTROL1, v)
+        self.write_u8(Register::CONTROL1, with_field_range(ctrl1, rng))?;
+        self.range = rng;
+        Ok(())
     }
 
     /// Set the device oversampling rate.
-    pub fn set_oversample(&mut self, osr: OversampleRate) -> Result<(), i2c::master::Error> {
+    pub fn set_oversample(&mut self, osr: OversampleRate) -> Result<(), I2C::Error> {
         let ctrl1 = self.read_u8(Register::CONTROL1)?;
-        let v = (ctrl1 & !(OversampleRate::Rate64 as u8)) | (osr as u8);
-        self.write_u8(Register::CONTROL1, v)
+        self.write_u8(Register::CONTROL1, with_oversample(ctrl1, osr))
     }
 
     /// Set the device output data rate.
-    pub fn set_output_data_rate(&mut self, odr: OutputDataRate) -> Result<(), i2c::master::Error> {
+    pub fn set_output_data_rate(&mut self, odr: OutputDataRate) -> Result<(), I2C::Error> {
         let ctrl1 = self.read_u8(Register::CONTROL1)?;
-        let v = (ctrl1 & !(OutputDataRate::Rate200Hz as u8)) | (odr as u8);
-        self.write_u8(Register::CONTROL1, v)
+        self.write_u8(Register::CONTROL1, with_output_data_rate(ctrl1, odr))
     }
 
     /// Put device in continous mode.
-    pub fn continuous(&mut self) -> Result<(), i2c::master::Error> {
+    pub fn continuous(&mut self) -> Result<(), I2C::Error> {
         let ctrl1 = self.read_u8(Register::CONTROL1)?;
         self.write_u8(Register::CONTROL1, ctrl1 | MODE_CONTINUOUS)
     }
 
     /// Put device in standby mode.
-    pub fn standby(&mut self) -> Result<(), i2c::master::Error> {
+    pub fn standby(&mut self) -> Result<(), I2C::Error> {
         let ctrl1 = self.read_u8(Register::CONTROL1)?;
         self.write_u8(Register::CONTROL1, ctrl1 & !MODE_CONTINUOUS)
     }
 
     /// Enable interrupt pin.
-    pub fn enable_interrupt(&mut self) -> Result<(), i2c::master::Error> {
-        self.write_u8(Register::CONTROL2, CTRL2_ROL_PNT)
+    pub fn enable_interrupt(&mut self) -> Result<(), I2C::Error> {
+        self.write_u8(Register::CONTROL2, 0)
     }
 
     /// Disable interrupt pin.
-    pub fn disable_interrupt(&mut self) -> Result<(), i2c::master::Error> {
-        self.write_u8(Register::CONTROL2, CTRL2_ROL_PNT | CTRL2_INT_ENB)
+    pub fn disable_interrupt(&mut self) -> Result<(), I2C::Error> {
+        self.write_u8(Register::CONTROL2, CTRL2_INT_ENB)
     }
 
     /// Read temperature sensor; temperature coefficient is about 100 LSB/°C.
-    pub fn temp(&mut self) -> Result<i16, i2c::master::Error> {
-        let temp_l = self.read_u8(Register::TOUT_L)? as i16;
-        let temp_h = self.read_u8(Register::TOUT_H)? as i16;
-        Ok((temp_h << 8) | temp_l)
+    pub fn temp(&mut self) -> Result<i16, I2C::Error> {
+        let buf: &mut [u8; 2] = &mut [0; 2];
+        self.i2c
+            .write_read(I2C_ADDRESS, &[Register::TOUT_L as u8], buf)?;
+        Ok(i16::from_le_bytes(*buf))
     }
 
     /// Read raw (x,y,z) from magnetometer.
-    pub fn mag(&mut self) -> Result<(i16, i16, i16), Error> {
+    pub fn mag(&mut self) -> Result<(i16, i16, i16), Error<I2C::Error>> {
         let buf: &mut [u8; 7] = &mut [0; 7];
         self.i2c
-            .write_read(I2C_ADDRESS, &[Register::STATUS as u8], buf)?;
-        let status = buf[0];
-        if (status & STATUS_DRDY) == 0 {
-            return Err(Error::NotReady);
-        } else if (status & STATUS_OVL) != 0 {
-            return Err(Error::Overflow);
-        }
-        let x = ((buf[2] as i16) << 8) | (buf[1] as i16);
-        let y = ((buf[4] as i16) << 8) | (buf[3] as i16);
-        let z = ((buf[6] as i16) << 8) | (buf[5] as i16);
-        Ok((x, y, z))
+            .write_read(I2C_ADDRESS, &[Register::DATA_OUT_X_L as u8], buf)?;
+        decode_mag(buf)
     }
 
-    fn read_u8(&mut self, reg: Register) -> Result<u8, i2c::master::Error> {
+    /// Read magnetometer, status and temperature in a single burst.
+    pub fn measure(&mut self) -> Result<Measurement, Error<I2C::Error>> {
+        let buf: &mut [u8; 9] = &mut [0; 9];
+        self.i2c
+            .write_read(I2C_ADDRESS, &[Register::DATA_OUT_X_L as u8], buf)?;
+        decode_measurement(buf)
+    }
+
+    fn read_u8(&mut self, reg: Register) -> Result<u8, I2C::Error> {
         let buf: &mut [u8; 1] = &mut [0];
         self.i2c.write_read(I2C_ADDRESS, &[reg as u8], buf)?;
         Ok(buf[0])
     }
 
-    fn write_u8(&mut self, reg: Register, v: u8) -> Result<(), i2c::master::Error> {
+    fn write_u8(&mut self, reg: Register, v: u8) -> Result<(), I2C::Error> {
         self.i2c.write(I2C_ADDRESS, &[reg as u8, v])
     }
 }
+
+impl<I2C: AsyncI2c> QMC5883L<I2C> {
+    /// Creates a new QMC5883L device from an async I2C bus; begins with a soft reset.
+    pub async fn new_async(i2c: I2C) -> Result<Self, Error<I2C::Error>> {
+        let mut dev = QMC5883L {
+            i2c,
+            range: FieldRange::Range2Gauss,
+        };
+        let id = dev.read_u8_async(Register::CHIP_ID).await?;
+        if id != CHIP_ID_VALUE {
+            return Err(Error::InvalidDevice(id));
+        }
+        dev.reset_async().await?;
+        Ok(dev)
+    }
+
+    /// Creates a new QMC5883L device without checking CHIP_ID, for clones that report another
+    /// value; begins with a soft reset.
+    pub async fn new_unchecked_async(i2c: I2C) -> Result<Self, Error<I2C::Error>> {
+        let mut dev = QMC5883L {
+            i2c,
+            range: FieldRange::Range2Gauss,
+        };
+        dev.reset_async().await?;
+        Ok(dev)
+    }
+
+    /// Returns true if anything answers at the QMC5883L address, clones included.
+    pub async fn probe_async(i2c: &mut I2C) -> bool {
+        let buf: &mut [u8; 1] = &mut [0];
+        i2c.write_read(I2C_ADDRESS, &[Register::CHIP_ID as u8], buf)
+            .await
+            .is_ok()
+    }
+
+    /// Soft reset the device.
+    pub async fn reset_async(&mut self) -> Result<(), I2C::Error> {
+        self.write_u8_async(Register::CONTROL2, CTRL2_SOFT_RST)
+            .await?;
+        self.write_u8_async(Register::CONTROL2, CTRL2_INT_ENB)
+            .await?;
+        self.range = FieldRange::Range2Gauss;
+        self.write_u8_async(Register::PERIOD, 1).await
+    }
+
+    /// Set the device field range.
+    pub async fn set_field_range_async(&mut self, rng: FieldRange) -> Result<(), I2C::Error> {
+        let ctrl1 = self.read_u8_async(Register::CONTROL1).await?;
+        self.write_u8_async(Register::CONTROL1, with_field_range(ctrl1, rng))
+            .await?;
+        self.range = rng;
+        Ok(())
+    }
+
+    /// Set the device oversampling rate.
+    pub async fn set_oversample_async(&mut self, osr: OversampleRate) -> Result<(), I2C::Error> {
+        let ctrl1 = self.read_u8_async(Register::CONTROL1).await?;
+        self.write_u8_async(Register::CONTROL1, with_oversample(ctrl1, osr))
+            .await
+    }
+
+    /// Set the device output data rate.
+    pub async fn set_output_data_rate_async(
+        &mut self,
+        odr: OutputDataRate,
+    ) -> Result<(), I2C::Error> {
+        let ctrl1 = self.read_u8_async(Register::CONTROL1).await?;
+        self.write_u8_async(Register::CONTROL1, with_output_data_rate(ctrl1, odr))
+            .await
+    }
+
+    /// Put device in continous mode.
+    pub async fn continuous_async(&mut self) -> Result<(), I2C::Error> {
+        let ctrl1 = self.read_u8_async(Register::CONTROL1).await?;
+        self.write_u8_async(Register::CONTROL1, ctrl1 | MODE_CONTINUOUS)
+            .await
+    }
+
+    /// Put device in standby mode.
+    pub async fn standby_async(&mut self) -> Result<(), I2C::Error> {
+        let ctrl1 = self.read_u8_async(Register::CONTROL1).await?;
+        self.write_u8_async(Register::CONTROL1, ctrl1 & !MODE_CONTINUOUS)
+            .await
+    }
+
+    /// Enable interrupt pin.
+    pub async fn enable_interrupt_async(&mut self) -> Result<(), I2C::Error> {
+        self.write_u8_async(Register::CONTROL2, 0).await
+    }
+
+    /// Disable interrupt pin.
+    pub async fn disable_interrupt_async(&mut self) -> Result<(), I2C::Error> {
+        self.write_u8_async(Register::CONTROL2, CTRL2_INT_ENB).await
+    }
+
+    /// Read temperature sensor; temperature coefficient is about 100 LSB/°C.
+    pub async fn temp_async(&mut self) -> Result<i16, I2C::Error> {
+        let buf: &mut [u8; 2] = &mut [0; 2];
+        self.i2c
+            .write_read(I2C_ADDRESS, &[Register::TOUT_L as u8], buf)
+            .await?;
+        Ok(i16::from_le_bytes(*buf))
+    }
+
+    /// Read raw (x,y,z) from magnetometer.
+    pub async fn mag_async(&mut self) -> Result<(i16, i16, i16), Error<I2C::Error>> {
+        let buf: &mut [u8; 7] = &mut [0; 7];
+        self.i2c
+            .write_read(I2C_ADDRESS, &[Register::DATA_OUT_X_L as u8], buf)
+            .await?;
+        decode_mag(buf)
+    }
+
+    /// Read magnetometer, status and temperature in a single burst.
+    pub async fn measure_async(&mut self) -> Result<Measurement, Error<I2C::Error>> {
+        let buf: &mut [u8; 9] = &mut [0; 9];
+        self.i2c
+            .write_read(I2C_ADDRESS, &[Register::DATA_OUT_X_L as u8], buf)
+            .await?;
+        decode_measurement(buf)
+    }
+
+    async fn read_u8_async(&mut self, reg: Register) -> Result<u8, I2C::Error> {
+        let buf: &mut [u8; 1] = &mut [0];
+        self.i2c.write_read(I2C_ADDRESS, &[reg as u8], buf).await?;
+        Ok(buf[0])
+    }
+
+    async fn write_u8_async(&mut self, reg: Register, v: u8) -> Result<(), I2C::Error> {
+        self.i2c.write(I2C_ADDRESS, &[reg as u8, v]).await
+    }
+}
+
+fn with_field_range(ctrl1: u8, rng: FieldRange) -> u8 {
+    (ctrl1 & !CTRL1_RNG_MASK) | (rng as u8)
+}
+
+fn with_oversample(ctrl1: u8, osr: OversampleRate) -> u8 {
+    (ctrl1 & !(OversampleRate::Rate64 as u8)) | (osr as u8)
+}
+
+fn with_output_data_rate(ctrl1: u8, odr: OutputDataRate) -> u8 {
+    (ctrl1 & !(OutputDataRate::Rate200Hz as u8)) | (odr as u8)
+}
+
+/// Decodes DATA_OUT_X_L through STATUS.
+fn decode_mag<E>(buf: &[u8; 7]) -> Result<(i16, i16, i16), Error<E>> {
+    let status = buf[6];
+    if (status & STATUS_DRDY) == 0 {
+        return Err(Error::NotReady);
+    } else if (status & STATUS_OVL) != 0 {
+        return Err(Error::Overflow);
+    }
+    let x = i16::from_le_bytes([buf[0], buf[1]]);
+    let y = i16::from_le_bytes([buf[2], buf[3]]);
+    let z = i16::from_le_bytes([buf[4], buf[5]]);
+    Ok((x, y, z))
+}
+
+/// Decodes DATA_OUT_X_L through TOUT_H.
+fn decode_measurement<E>(buf: &[u8; 9]) -> Result<Measurement, Error<E>> {
+    let (mag, temp) = buf.split_at(7);
+    Ok(Measurement {
+        mag: decode_mag(mag.try_into().unwrap())?,
+        temp: i16::from_le_bytes([temp[0], temp[1]]),
+    })
+}
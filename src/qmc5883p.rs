@@ -0,0 +1,333 @@
+//! A embedded-hal driver to interface with the QMC5883P magnetometer.
+
+use embedded_hal::i2c::I2c;
+use embedded_hal_async::i2c::I2c as AsyncI2c;
+
+pub use crate::magnetometer::Error;
+
+const I2C_ADDRESS: u8 = 0x2c;
+
+#[allow(dead_code)]
+#[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone)]
+#[repr(u8)]
+enum Register {
+    CHIP_ID = 0x00,
+    DATA_OUT_X_L = 0x01,
+    DATA_OUT_X_H = 0x02,
+    DATA_OUT_Y_L = 0x03,
+    DATA_OUT_Y_H = 0x04,
+    DATA_OUT_Z_L = 0x05,
+    DATA_OUT_Z_H = 0x06,
+    STATUS = 0x09,
+    CONTROL1 = 0x0a,
+    CONTROL2 = 0x0b,
+    AXIS_SIGN = 0x29,
+}
+
+const CHIP_ID_VALUE: u8 = 0x80;
+
+const STATUS_OVFL: u8 = 0b010;
+const STATUS_DRDY: u8 = 0b001;
+
+const MODE_CONTINUOUS: u8 = 0b11;
+const MODE_MASK: u8 = 0b11;
+
+const CTRL2_SOFT_RST: u8 = 1 << 7;
+const CTRL2_RNG_MASK: u8 = 0b11 << 2;
+
+/// Axis sign configuration recommended by the datasheet for a right handed frame.
+const AXIS_SIGN_VALUE: u8 = 0x06;
+
+/// Update frequency in continuous mode.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum OutputDataRate {
+    /// 10Hz update rate.
+    Rate10Hz = 0,
+    /// 50Hz update rate.
+    Rate50Hz = 1 << 2,
+    /// 100Hz update rate.
+    Rate100Hz = 2 << 2,
+    /// 200Hz update rate.
+    Rate200Hz = 3 << 2,
+}
+
+/// Oversampling rate; controls bandwidth of internal digital filter.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum OversampleRate {
+    /// Oversample by 8.
+    Rate8 = 0,
+    /// Oversample by 4.
+    Rate4 = 1 << 4,
+    /// Oversample by 2.
+    Rate2 = 2 << 4,
+    /// No oversampling.
+    Rate1 = 3 << 4,
+}
+
+/// Field range of magnetic sensor.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum FieldRange {
+    /// ± 2 gauss
+    Range2Gauss = 3 << 2,
+    /// ± 8 gauss
+    Range8Gauss = 2 << 2,
+    /// ± 12 gauss
+    Range12Gauss = 1 << 2,
+    /// ± 30 gauss
+    Range30Gauss = 0,
+}
+
+impl FieldRange {
+    /// Every range, smallest first.
+    pub const ALL: [FieldRange; 4] = [
+        FieldRange::Range2Gauss,
+        FieldRange::Range8Gauss,
+        FieldRange::Range12Gauss,
+        FieldRange::Range30Gauss,
+    ];
+
+    /// Sensitivity in LSB/G.
+    pub fn lsb_per_gauss(&self) -> f32 {
+        match self {
+            FieldRange::Range2Gauss => 15000.,
+            FieldRange::Range8Gauss => 3750.,
+            FieldRange::Range12Gauss => 2500.,
+            FieldRange::Range30Gauss => 1000.,
+        }
+    }
+
+    /// Converts raw (x,y,z) counts taken at this range to µT.
+    pub fn micro_tesla(&self, (x, y, z): (i16, i16, i16)) -> (f32, f32, f32) {
+        // 1 G = 100 µT
+        let lsb_per_micro_tesla = self.lsb_per_gauss() / 100.;
+        (
+            x as f32 / lsb_per_micro_tesla,
+            y as f32 / lsb_per_micro_tesla,
+            z as f32 / lsb_per_micro_tesla,
+        )
+    }
+}
+
+/// QMC5883P driver
+pub struct QMC5883P<I2C> {
+    i2c: I2C,
+    range: FieldRange,
+}
+
+impl<I2C> QMC5883P<I2C> {
+    /// Destroys the driver and returns the I2C bus.
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// The field range raw readings are currently taken at.
+    pub fn field_range(&self) -> FieldRange {
+        self.range
+    }
+}
+
+impl<I2C: I2c> QMC5883P<I2C> {
+    /// Creates a new QMC5883P device from an I2C bus; begins with a soft reset.
+    pub fn new(i2c: I2C) -> Result<Self, Error<I2C::Error>> {
+        let mut dev = QMC5883P {
+            i2c,
+            range: FieldRange::Range8Gauss,
+        };
+        let id = dev.read_u8(Register::CHIP_ID)?;
+        if id != CHIP_ID_VALUE {
+            return Err(Error::InvalidDevice(id));
+        }
+        dev.reset()?;
+        Ok(dev)
+    }
+
+    /// Returns true if a QMC5883P answers on the bus.
+    pub fn probe(i2c: &mut I2C) -> bool {
+        let buf: &mut [u8; 1] = &mut [0];
+        i2c.write_read(I2C_ADDRESS, &[Register::CHIP_ID as u8], buf)
+            .is_ok()
+            && buf[0] == CHIP_ID_VALUE
+    }
+
+    /// Soft reset the device, leaving it suspended at ± 8 gauss.
+    pub fn reset(&mut self) -> Result<(), I2C::Error> {
+        self.write_u8(Register::CONTROL2, CTRL2_SOFT_RST)?;
+        self.write_u8(Register::AXIS_SIGN, AXIS_SIGN_VALUE)?;
+        self.write_u8(Register::CONTROL2, FieldRange::Range8Gauss as u8)?;
+        self.range = FieldRange::Range8Gauss;
+        Ok(())
+    }
+
+    /// Set the device field range.
+    pub fn set_field_range(&mut self, rng: FieldRange) -> Result<(), I2C::Error> {
+        let ctrl2 = self.read_u8(Register::CONTROL2)?;
+        self.write_u8(Register::CONTROL2, (ctrl2 & !CTRL2_RNG_MASK) | rng as u8)?;
+        self.range = rng;
+        Ok(())
+    }
+
+    /// Set the device oversampling rate.
+    pub fn set_oversample(&mut self, osr: OversampleRate) -> Result<(), I2C::Error> {
+        let ctrl1 = self.read_u8(Register::CONTROL1)?;
+        self.write_u8(Register::CONTROL1, with_oversample(ctrl1, osr))
+    }
+
+    /// Set the device output data rate.
+    pub fn set_output_data_rate(&mut self, odr: OutputDataRate) -> Result<(), I2C::Error> {
+        let ctrl1 = self.read_u8(Register::CONTROL1)?;
+        self.write_u8(Register::CONTROL1, with_output_data_rate(ctrl1, odr))
+    }
+
+    /// Put device in continous mode.
+    pub fn continuous(&mut self) -> Result<(), I2C::Error> {
+        let ctrl1 = self.read_u8(Register::CONTROL1)?;
+        self.write_u8(Register::CONTROL1, ctrl1 | MODE_CONTINUOUS)
+    }
+
+    /// Put device in suspend mode.
+    pub fn suspend(&mut self) -> Result<(), I2C::Error> {
+        let ctrl1 = self.read_u8(Register::CONTROL1)?;
+        self.write_u8(Register::CONTROL1, ctrl1 & !MODE_MASK)
+    }
+
+    /// Read raw (x,y,z) from magnetometer.
+    pub fn mag(&mut self) -> Result<(i16, i16, i16), Error<I2C::Error>> {
+        let buf: &mut [u8; 9] = &mut [0; 9];
+        self.i2c
+            .write_read(I2C_ADDRESS, &[Register::DATA_OUT_X_L as u8], buf)?;
+        decode_mag(buf)
+    }
+
+    fn read_u8(&mut self, reg: Register) -> Result<u8, I2C::Error> {
+        let buf: &mut [u8; 1] = &mut [0];
+        self.i2c.write_read(I2C_ADDRESS, &[reg as u8], buf)?;
+        Ok(buf[0])
+    }
+
+    fn write_u8(&mut self, reg: Register, v: u8) -> Result<(), I2C::Error> {
+        self.i2c.write(I2C_ADDRESS, &[reg as u8, v])
+    }
+}
+
+impl<I2C: AsyncI2c> QMC5883P<I2C> {
+    /// Creates a new QMC5883P device from an async I2C bus; begins with a soft reset.
+    pub async fn new_async(i2c: I2C) -> Result<Self, Error<I2C::Error>> {
+        let mut dev = QMC5883P {
+            i2c,
+            range: FieldRange::Range8Gauss,
+        };
+        let id = dev.read_u8_async(Register::CHIP_ID).await?;
+        if id != CHIP_ID_VALUE {
+            return Err(Error::InvalidDevice(id));
+        }
+        dev.reset_async().await?;
+        Ok(dev)
+    }
+
+    /// Returns true if a QMC5883P answers on the bus.
+    pub async fn probe_async(i2c: &mut I2C) -> bool {
+        let buf: &mut [u8; 1] = &mut [0];
+        i2c.write_read(I2C_ADDRESS, &[Register::CHIP_ID as u8], buf)
+            .await
+            .is_ok()
+            && buf[0] == CHIP_ID_VALUE
+    }
+
+    /// Soft reset the device, leaving it suspended at ± 8 gauss.
+    pub async fn reset_async(&mut self) -> Result<(), I2C::Error> {
+        self.write_u8_async(Register::CONTROL2, CTRL2_SOFT_RST)
+            .await?;
+        self.write_u8_async(Register::AXIS_SIGN, AXIS_SIGN_VALUE)
+            .await?;
+        self.write_u8_async(Register::CONTROL2, FieldRange::Range8Gauss as u8)
+            .await?;
+        self.range = FieldRange::Range8Gauss;
+        Ok(())
+    }
+
+    /// Set the device field range.
+    pub async fn set_field_range_async(&mut self, rng: FieldRange) -> Result<(), I2C::Error> {
+        let ctrl2 = self.read_u8_async(Register::CONTROL2).await?;
+        self.write_u8_async(Register::CONTROL2, (ctrl2 & !CTRL2_RNG_MASK) | rng as u8)
+            .await?;
+        self.range = rng;
+        Ok(())
+    }
+
+    /// Set the device oversampling rate.
+    pub async fn set_oversample_async(&mut self, osr: OversampleRate) -> Result<(), I2C::Error> {
+        let ctrl1 = self.read_u8_async(Register::CONTROL1).await?;
+        self.write_u8_async(Register::CONTROL1, with_oversample(ctrl1, osr))
+            .await
+    }
+
+    /// Set the device output data rate.
+    pub async fn set_output_data_rate_async(
+        &mut self,
+        odr: OutputDataRate,
+    ) -> Result<(), I2C::Error> {
+        let ctrl1 = self.read_u8_async(Register::CONTROL1).await?;
+        self.write_u8_async(Register::CONTROL1, with_output_data_rate(ctrl1, odr))
+            .await
+    }
+
+    /// Put device in continous mode.
+    pub async fn continuous_async(&mut self) -> Result<(), I2C::Error> {
+        let ctrl1 = self.read_u8_async(Register::CONTROL1).await?;
+        self.write_u8_async(Register::CONTROL1, ctrl1 | MODE_CONTINUOUS)
+            .await
+    }
+
+    /// Put device in suspend mode.
+    pub async fn suspend_async(&mut self) -> Result<(), I2C::Error> {
+        let ctrl1 = self.read_u8_async(Register::CONTROL1).await?;
+        self.write_u8_async(Register::CONTROL1, ctrl1 & !MODE_MASK)
+            .await
+    }
+
+    /// Read raw (x,y,z) from magnetometer.
+    pub async fn mag_async(&mut self) -> Result<(i16, i16, i16), Error<I2C::Error>> {
+        let buf: &mut [u8; 9] = &mut [0; 9];
+        self.i2c
+            .write_read(I2C_ADDRESS, &[Register::DATA_OUT_X_L as u8], buf)
+            .await?;
+        decode_mag(buf)
+    }
+
+    async fn read_u8_async(&mut self, reg: Register) -> Result<u8, I2C::Error> {
+        let buf: &mut [u8; 1] = &mut [0];
+        self.i2c.write_read(I2C_ADDRESS, &[reg as u8], buf).await?;
+        Ok(buf[0])
+    }
+
+    async fn write_u8_async(&mut self, reg: Register, v: u8) -> Result<(), I2C::Error> {
+        self.i2c.write(I2C_ADDRESS, &[reg as u8, v]).await
+    }
+}
+
+fn with_oversample(ctrl1: u8, osr: OversampleRate) -> u8 {
+    (ctrl1 & !(OversampleRate::Rate1 as u8)) | (osr as u8)
+}
+
+fn with_output_data_rate(ctrl1: u8, odr: OutputDataRate) -> u8 {
+    (ctrl1 & !(OutputDataRate::Rate200Hz as u8)) | (odr as u8)
+}
+
+/// Decodes DATA_OUT_X_L through STATUS, skipping the two reserved registers in between.
+fn decode_mag<E>(buf: &[u8; 9]) -> Result<(i16, i16, i16), Error<E>> {
+    let status = buf[8];
+    if (status & STATUS_DRDY) == 0 {
+        return Err(Error::NotReady);
+    } else if (status & STATUS_OVFL) != 0 {
+        return Err(Error::Overflow);
+    }
+    let x = i16::from_le_bytes([buf[0], buf[1]]);
+    let y = i16::from_le_bytes([buf[2], buf[3]]);
+    let z = i16::from_le_bytes([buf[4], buf[5]]);
+    Ok((x, y, z))
+}
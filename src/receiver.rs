@@ -0,0 +1,491 @@
+//! Decoding of the byte stream from the receiver into navigation state, apart from the UART.
+//!
+//! [`Receiver`] frames UBX and NMEA out of whatever bytes it is given and passes each message on
+//! as an [`Event`], so [`crate::gps`] can feed it from the UART and tests can replay captures.
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Timelike};
+use embassy_time::{Duration, Instant};
+use geoconv::{Degrees, Lle, Meters, Wgs84};
+use ublox::{FixedLinearBuffer, GnssFixType, NavPvtFlags, NavPvtRef, PacketRef, Parser};
+
+use crate::nmea::{self, Sentence};
+use crate::satellites::{self, GsvCollector, SkyView};
+use crate::ubx::{self, Ack, Framer};
+use crate::wmm;
+
+/// Navigation data older than this is stale, a few missed solutions at the configured rate.
+pub const STALE_AFTER: Duration = Duration::from_secs(3);
+
+/// Horizontal accuracy estimate in metres for a 3D fix to count as good.
+pub const GOOD_HORIZONTAL_ACCURACY: f64 = 10.;
+
+/// NMEA sentences are used once no UBX frame has arrived for this long, for receivers that only
+/// speak NMEA.
+pub const NMEA_AFTER: Duration = Duration::from_secs(5);
+
+/// NAV-PVT valid bits: UTC date valid.
+pub const VALID_DATE: u8 = 0x01;
+/// UTC time of day valid.
+pub const VALID_TIME: u8 = 0x02;
+/// UTC time of day fully resolved, no seconds uncertainty.
+pub const FULLY_RESOLVED: u8 = 0x04;
+/// Magnetic declination valid.
+pub const VALID_MAG: u8 = 0x08;
+
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+pub struct NavPvtState {
+    pub time_tag: f64,
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub min: u8,
+    pub sec: u8,
+    /// [`VALID_DATE`], [`VALID_TIME`], [`FULLY_RESOLVED`] and [`VALID_MAG`] bits, which say whether the date and time can be used.
+    pub valid: u8,
+    /// Time accuracy estimate in ns.
+    pub time_accuracy: u32,
+    /// Fraction of the second in ns, -1e9 to 1e9.
+    pub nanosecond: i32,
+    /// Latitude, Longitude, Elevation
+    pub lle: Option<Lle<Wgs84, Degrees>>,
+
+    pub msl: f64,
+    pub vel_ned: (f64, f64, f64),
+    pub speed_over_ground: f64,
+    pub heading_motion: f64,
+    pub heading_vehicle: f64,
+    pub magnetic_declination: f64,
+    /// World Magnetic Model declination in degrees at `lle` on the date, NaN without them. Filled
+    /// in by [`Receiver`], so readers do not evaluate the model.
+    pub model_declination: f64,
+
+    pub pdop: f64,
+    pub satellites_used: u8,
+
+    pub position_fix_type: GnssFixType,
+    /// [`NavPvtFlags`] bits, including gnssFixOK.
+    pub flags: u8,
+    /// Horizontal accuracy estimate in metres, NaN if the receiver does not report one.
+    pub horizontal_accuracy: f64,
+    /// Vertical accuracy estimate in metres, NaN if the receiver does not report one.
+    pub vertical_accuracy: f64,
+    /// When the last packet or position sentence arrived.
+    pub received: Option<Instant>,
+}
+
+/// How far the navigation data can be trusted, worst first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FixQuality {
+    /// No position, or the receiver does not consider it valid.
+    None,
+    /// Nothing received for [`STALE_AFTER`].
+    Stale,
+    Fix2D,
+    Fix3D,
+    /// 3D with a horizontal accuracy within [`GOOD_HORIZONTAL_ACCURACY`].
+    Good,
+}
+
+impl FixQuality {
+    /// Whether the position is good enough to navigate by.
+    pub fn can_navigate(self) -> bool {
+        self >= FixQuality::Fix2D
+    }
+
+    /// Short label for the display.
+    pub fn label(self) -> &'static str {
+        match self {
+            FixQuality::None => "NO FIX",
+            FixQuality::Stale => "STALE",
+            FixQuality::Fix2D => "2D",
+            FixQuality::Fix3D => "3D",
+            FixQuality::Good => "GOOD",
+        }
+    }
+}
+
+impl NavPvtState {
+    pub const fn new() -> Self {
+        Self {
+            time_tag: f64::NAN,
+            year: 0,
+            month: 0,
+            day: 0,
+            hour: 0,
+            min: 0,
+            sec: 0,
+            valid: 0,
+            time_accuracy: 0,
+            nanosecond: 0,
+            lle: None,
+            msl: f64::NAN,
+            vel_ned: (f64::NAN, f64::NAN, f64::NAN),
+            speed_over_ground: f64::NAN,
+            heading_motion: f64::NAN,
+            heading_vehicle: f64::NAN,
+            magnetic_declination: f64::NAN,
+            model_declination: f64::NAN,
+            pdop: f64::NAN,
+            satellites_used: 0,
+            position_fix_type: GnssFixType::NoFix,
+            flags: 0,
+            horizontal_accuracy: f64::NAN,
+            vertical_accuracy: f64::NAN,
+            received: None,
+        }
+    }
+
+    /// Copies a NAV-PVT, leaving out the date, time and position unless the receiver flags them
+    /// as valid. `received` is left for the caller.
+    pub fn from_nav_pvt(pkg: &NavPvtRef<'_>) -> Self {
+        let valid = pkg.valid();
+        let mut state = Self {
+            time_tag: pkg.itow() as f64 / 1000.,
+            valid,
+            flags: pkg.flags().bits(),
+            position_fix_type: pkg.fix_type(),
+            horizontal_accuracy: pkg.horizontal_accuracy(),
+            vertical_accuracy: pkg.vertical_accuracy(),
+            msl: pkg.height_msl(),
+            vel_ned: (pkg.vel_north(), pkg.vel_east(), pkg.vel_down()),
+            speed_over_ground: pkg.ground_speed_2d(),
+            heading_motion: pkg.heading_motion(),
+            heading_vehicle: pkg.heading_vehicle(),
+            magnetic_declination: pkg.magnetic_declination(),
+            pdop: pkg.pdop(),
+            satellites_used: pkg.num_satellites(),
+            ..Self::new()
+        };
+
+        if valid & VALID_DATE != 0 {
+            state.year = pkg.year();
+            state.month = pkg.month();
+            state.day = pkg.day();
+        }
+        if valid & VALID_TIME != 0 {
+            state.hour = pkg.hour();
+            state.min = pkg.min();
+            state.sec = pkg.sec();
+            state.nanosecond = pkg.nanosec();
+            state.time_accuracy = pkg.time_accuracy();
+        }
+
+        // Leave the position out rather than pass on one the receiver does not trust
+        let has_position = matches!(
+            state.position_fix_type,
+            GnssFixType::Fix2D | GnssFixType::Fix3D | GnssFixType::GPSPlusDeadReckoning
+        );
+        if has_position && state.gnss_fix_ok() {
+            state.lle = Some(Lle::new(
+                Degrees::new(pkg.latitude()),
+                Degrees::new(pkg.longitude()),
+                Meters::new(pkg.height_above_ellipsoid()),
+            ));
+        }
+
+        state
+    }
+
+    /// UTC date and time, None unless both are valid and fully resolved.
+    pub fn date_time(&self) -> Option<NaiveDateTime> {
+        let required = VALID_DATE | VALID_TIME | FULLY_RESOLVED;
+        if self.valid & required != required {
+            return None;
+        }
+
+        NaiveDate::from_ymd_opt(self.year as i32, self.month as u32, self.day as u32)?
+            .and_hms_opt(self.hour as u32, self.min as u32, self.sec as u32)?
+            .checked_add_signed(TimeDelta::nanoseconds(self.nanosecond as i64))
+    }
+
+    /// gnssFixOK, the fix is within the receiver's accuracy masks.
+    pub fn gnss_fix_ok(&self) -> bool {
+        NavPvtFlags::from_bits_truncate(self.flags).contains(NavPvtFlags::GPS_FIX_OK)
+    }
+
+    pub fn fix_quality(&self) -> FixQuality {
+        self.fix_quality_at(Instant::now())
+    }
+
+    /// Fix quality as of `now`.
+    pub fn fix_quality_at(&self, now: Instant) -> FixQuality {
+        let Some(received) = self.received else {
+            return FixQuality::None;
+        };
+        if self.lle.is_none() || !self.gnss_fix_ok() {
+            return FixQuality::None;
+        }
+        if now.checked_duration_since(received).unwrap_or_default() > STALE_AFTER {
+            return FixQuality::Stale;
+        }
+
+        match self.position_fix_type {
+            GnssFixType::Fix2D => FixQuality::Fix2D,
+            GnssFixType::Fix3D | GnssFixType::GPSPlusDeadReckoning => {
+                if self.horizontal_accuracy <= GOOD_HORIZONTAL_ACCURACY {
+                    FixQuality::Good
+                } else {
+                    FixQuality::Fix3D
+                }
+            }
+            _ => FixQuality::None,
+        }
+    }
+
+    /// Position, if the fix is good enough to navigate by.
+    pub fn position(&self) -> Option<Lle<Wgs84, Degrees>> {
+        self.lle.filter(|_| self.fix_quality().can_navigate())
+    }
+
+    /// Magnetic declination in degrees, positive east.
+    ///
+    /// Prefers the value reported by the receiver and otherwise falls back to the World Magnetic
+    /// Model. None without a position and date.
+    pub fn declination(&self) -> Option<f64> {
+        if self.valid & VALID_MAG != 0 && self.magnetic_declination.is_finite() {
+            return Some(self.magnetic_declination);
+        }
+        Some(self.model_declination).filter(|declination| declination.is_finite())
+    }
+
+    /// Fills in [`NavPvtState::model_declination`] from `model`.
+    fn set_model_declination(&mut self, model: &mut wmm::Declination) {
+        let date = NaiveDate::from_ymd_opt(self.year as i32, self.month as u32, self.day as u32);
+        self.model_declination = match (self.lle, date) {
+            (Some(lle), Some(date)) => model.at(lle, wmm::decimal_year(date)),
+            _ => f64::NAN,
+        };
+    }
+
+    fn set_fix_ok(&mut self, fix_ok: bool) {
+        let mut flags = NavPvtFlags::from_bits_truncate(self.flags);
+        flags.set(NavPvtFlags::GPS_FIX_OK, fix_ok);
+        self.flags = flags.bits();
+    }
+
+    fn set_time(&mut self, time: NaiveTime) {
+        self.hour = time.hour() as u8;
+        self.min = time.minute() as u8;
+        self.sec = time.second() as u8;
+        self.nanosecond = time.nanosecond() as i32;
+    }
+
+    /// Speed over ground in m/s and course in degrees, as reported by NMEA receivers.
+    fn set_motion(&mut self, speed: Option<f64>, course: Option<f64>) {
+        let speed = speed.unwrap_or(f64::NAN);
+        let course = course.unwrap_or(f64::NAN);
+        self.speed_over_ground = speed;
+        self.heading_motion = course;
+
+        let course = course.to_radians();
+        self.vel_ned = (
+            speed * libm::cos(course),
+            speed * libm::sin(course),
+            f64::NAN,
+        );
+    }
+}
+
+impl Default for NavPvtState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What a message from the receiver brought.
+#[derive(Clone, Copy)]
+pub enum Event<'a> {
+    /// Navigation state from a NAV-PVT, which replaces the last one.
+    NavPvt(NavPvtState),
+    /// An NMEA sentence and the navigation state it updated, as a fix builds up over the
+    /// sentences of each epoch.
+    Sentence(Sentence, NavPvtState),
+    /// Satellites from a NAV-SAT or the GSV sentences so far.
+    SkyView(&'a SkyView),
+}
+
+/// Framing is done by [`Framer`] so partial frames survive between reads and dropped data can be
+/// counted; ublox only decodes the complete frames.
+pub struct Receiver<'a> {
+    framer: Framer,
+    nmea: nmea::Framer,
+    gsv: GsvCollector,
+    parser: Parser<FixedLinearBuffer<'a>>,
+    /// Built up from NMEA sentences.
+    state: NavPvtState,
+    declination: wmm::Declination,
+    /// Last UBX frame or NMEA sentence.
+    last_frame: Option<Instant>,
+    last_ubx: Option<Instant>,
+}
+
+impl<'a> Receiver<'a> {
+    /// `parser_buf` holds a frame for ublox, [`ubx::MAX_FRAME_LEN`] is enough for any.
+    pub fn new(parser_buf: &'a mut [u8]) -> Self {
+        Self {
+            framer: Framer::new(),
+            nmea: nmea::Framer::new(),
+            gsv: GsvCollector::new(),
+            parser: Parser::new(FixedLinearBuffer::new(parser_buf)),
+            state: NavPvtState::new(),
+            declination: wmm::Declination::new(),
+            last_frame: None,
+            last_ubx: None,
+        }
+    }
+
+    /// When the last UBX frame or NMEA sentence arrived, None if none has yet.
+    pub fn last_frame(&self) -> Option<Instant> {
+        self.last_frame
+    }
+
+    pub fn stats(&self) -> ubx::Stats {
+        self.framer.stats()
+    }
+
+    /// Frames and decodes `bytes`, received at `now`, passing what they bring to `handle`.
+    /// Returns the reply to the CFG message with `cfg`'s class and id if it is among them.
+    pub fn consume(
+        &mut self,
+        bytes: &[u8],
+        now: Instant,
+        cfg: Option<(u8, u8)>,
+        mut handle: impl FnMut(Event<'_>),
+    ) -> Option<Ack> {
+        let use_nmea = self.last_ubx.is_none_or(|last_ubx| {
+            now.checked_duration_since(last_ubx)
+                .is_some_and(|silence| silence > NMEA_AFTER)
+        });
+
+        let mut ack = None;
+        for &byte in bytes {
+            if let Some(data) = self.nmea.push(byte) {
+                self.last_frame = Some(now);
+                if use_nmea {
+                    if let Some(sentence) = nmea::parse(data) {
+                        self.handle_sentence(sentence, now, &mut handle);
+                    }
+                }
+            }
+
+            let Some(frame) = self.framer.push(byte) else {
+                continue;
+            };
+
+            self.last_frame = Some(now);
+            self.last_ubx = Some(now);
+
+            if (frame.class(), frame.id()) == satellites::NAV_SAT {
+                if let Some(view) = SkyView::from_nav_sat(frame.payload()) {
+                    handle(Event::SkyView(&view));
+                }
+            }
+            if let Some((class, id)) = cfg {
+                ack = ack.or(frame.ack_for(class, id));
+            }
+
+            let mut it = self.parser.consume_ubx(frame.as_bytes());
+            loop {
+                match it.next() {
+                    Some(Ok(PacketRef::NavPvt(pkg))) => {
+                        let mut state = NavPvtState {
+                            received: Some(now),
+                            ..NavPvtState::from_nav_pvt(&pkg)
+                        };
+                        state.set_model_declination(&mut self.declination);
+                        handle(Event::NavPvt(state));
+                    }
+                    Some(Ok(packet)) => log::debug!("Handle Packet:{:?}", packet),
+                    Some(Err(err)) => log::warn!("Bad Packet! Err:{}", err),
+                    None => break,
+                }
+            }
+        }
+        ack
+    }
+
+    /// Updates the navigation state with the fields a sentence carries.
+    fn handle_sentence(
+        &mut self,
+        sentence: Sentence,
+        now: Instant,
+        handle: &mut impl FnMut(Event<'_>),
+    ) {
+        let state = &mut self.state;
+        let mut valid = state.valid;
+
+        match sentence {
+            Sentence::Gga(gga) => {
+                if let Some(time) = gga.time {
+                    state.set_time(time);
+                    valid |= VALID_TIME;
+                }
+                state.received = Some(now);
+                state.set_fix_ok(gga.quality != 0);
+                state.lle = gga.position.map(|(latitude, longitude)| {
+                    let height =
+                        gga.altitude_msl.unwrap_or(0.) + gga.geoid_separation.unwrap_or(0.);
+                    Lle::new(
+                        Degrees::new(latitude),
+                        Degrees::new(longitude),
+                        Meters::new(height),
+                    )
+                });
+                state.msl = gga.altitude_msl.unwrap_or(f64::NAN);
+                state.satellites_used = gga.satellites_used;
+                // GSA tells 2D from 3D
+                if gga.quality == 0 {
+                    state.position_fix_type = GnssFixType::NoFix;
+                } else if state.position_fix_type == GnssFixType::NoFix {
+                    state.position_fix_type = GnssFixType::Fix3D;
+                }
+            }
+            Sentence::Rmc(rmc) => {
+                if let Some(time) = rmc.time {
+                    state.set_time(time);
+                    valid |= VALID_TIME;
+                }
+                if let Some(date) = rmc.date {
+                    state.year = date.year() as u16;
+                    state.month = date.month() as u8;
+                    state.day = date.day() as u8;
+                    valid |= VALID_DATE;
+                }
+                // NMEA has no such flag, trust the date and time once the fix is valid
+                if rmc.valid && rmc.time.is_some() && rmc.date.is_some() {
+                    valid |= FULLY_RESOLVED;
+                } else {
+                    valid &= !FULLY_RESOLVED;
+                }
+                match rmc.magnetic_variation {
+                    Some(variation) => {
+                        state.magnetic_declination = variation;
+                        valid |= VALID_MAG;
+                    }
+                    None => valid &= !VALID_MAG,
+                }
+                state.set_motion(rmc.speed, rmc.course);
+            }
+            Sentence::Gsa(gsa) => {
+                self.gsv.add_gsa(&gsa);
+                state.position_fix_type = match gsa.fix {
+                    2 => GnssFixType::Fix2D,
+                    3 => GnssFixType::Fix3D,
+                    _ => GnssFixType::NoFix,
+                };
+                state.pdop = gsa.pdop.unwrap_or(f64::NAN);
+            }
+            Sentence::Vtg(vtg) => state.set_motion(vtg.speed, vtg.course),
+            Sentence::Gsv(sentence) => {
+                self.gsv.add_gsv(&sentence);
+                handle(Event::SkyView(self.gsv.view()));
+            }
+        }
+
+        state.valid = valid;
+        state.set_model_declination(&mut self.declination);
+        handle(Event::Sentence(sentence, *state));
+    }
+}
@@ -0,0 +1,258 @@
+//! Per-satellite data for the sky view.
+//!
+//! Filled from UBX NAV-SAT, or from NMEA GSV and GSA sentences on receivers without UBX.
+
+use crate::nmea::{Gsa, Gsv};
+
+/// Most satellites kept, enough for a multi-constellation receiver.
+pub const MAX_SATELLITES: usize = 40;
+
+/// Class and id of UBX NAV-SAT.
+pub const NAV_SAT: (u8, u8) = (0x01, 0x35);
+
+const NAV_SAT_HEADER_LEN: usize = 8;
+const NAV_SAT_BLOCK_LEN: usize = 12;
+const NAV_SAT_SV_USED: u32 = 1 << 3;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Constellation {
+    Gps,
+    Sbas,
+    Galileo,
+    BeiDou,
+    Imes,
+    Qzss,
+    Glonass,
+    NavIc,
+    Unknown,
+}
+
+impl Constellation {
+    /// From a UBX gnssId.
+    pub fn from_gnss_id(gnss_id: u8) -> Self {
+        match gnss_id {
+            0 => Self::Gps,
+            1 => Self::Sbas,
+            2 => Self::Galileo,
+            3 => Self::BeiDou,
+            4 => Self::Imes,
+            5 => Self::Qzss,
+            6 => Self::Glonass,
+            7 => Self::NavIc,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// From an NMEA talker id. SBAS satellites come from the GPS talker.
+    pub fn from_talker(talker: [u8; 2]) -> Self {
+        match &talker {
+            b"GP" => Self::Gps,
+            b"GA" => Self::Galileo,
+            b"GB" | b"BD" => Self::BeiDou,
+            b"GQ" => Self::Qzss,
+            b"GL" => Self::Glonass,
+            b"GI" => Self::NavIc,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// From an NMEA 4.10 system id.
+    pub fn from_system_id(system: u8) -> Self {
+        match system {
+            1 => Self::Gps,
+            2 => Self::Glonass,
+            3 => Self::Galileo,
+            4 => Self::BeiDou,
+            5 => Self::Qzss,
+            6 => Self::NavIc,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Single letter used by RINEX.
+    pub fn letter(&self) -> char {
+        match self {
+            Self::Gps => 'G',
+            Self::Sbas => 'S',
+            Self::Galileo => 'E',
+            Self::BeiDou => 'C',
+            Self::Imes => 'I',
+            Self::Qzss => 'J',
+            Self::Glonass => 'R',
+            Self::NavIc => 'N',
+            Self::Unknown => '?',
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Satellite {
+    pub constellation: Constellation,
+    pub id: u16,
+    /// Elevation in degrees, None if unknown.
+    pub elevation: Option<i8>,
+    /// Azimuth in degrees from true north, None if unknown.
+    pub azimuth: Option<u16>,
+    /// Signal strength in dBHz, 0 when not tracked.
+    pub cno: u8,
+    /// Used in the navigation solution.
+    pub used: bool,
+}
+
+impl Satellite {
+    const NONE: Self = Self {
+        constellation: Constellation::Unknown,
+        id: 0,
+        elevation: None,
+        azimuth: None,
+        cno: 0,
+        used: false,
+    };
+}
+
+/// The satellites the receiver knows about.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SkyView {
+    satellites: [Satellite; MAX_SATELLITES],
+    len: usize,
+}
+
+impl SkyView {
+    pub const fn new() -> Self {
+        Self {
+            satellites: [Satellite::NONE; MAX_SATELLITES],
+            len: 0,
+        }
+    }
+
+    pub fn satellites(&self) -> &[Satellite] {
+        &self.satellites[..self.len]
+    }
+
+    /// Decodes a NAV-SAT payload. None if it is malformed.
+    pub fn from_nav_sat(payload: &[u8]) -> Option<Self> {
+        let count = *payload.get(5)? as usize;
+        let blocks =
+            payload.get(NAV_SAT_HEADER_LEN..NAV_SAT_HEADER_LEN + count * NAV_SAT_BLOCK_LEN)?;
+
+        // A whole number of blocks, nothing is left over
+        let (blocks, _) = blocks.as_chunks::<NAV_SAT_BLOCK_LEN>();
+
+        let mut view = Self::new();
+        for block in blocks {
+            let elevation = block[3] as i8;
+            let azimuth = i16::from_le_bytes([block[4], block[5]]);
+            let flags = u32::from_le_bytes([block[8], block[9], block[10], block[11]]);
+            view.push(Satellite {
+                constellation: Constellation::from_gnss_id(block[0]),
+                id: block[1] as u16,
+                // Both out of range when the orbit is not known
+                elevation: (-90..=90).contains(&elevation).then_some(elevation),
+                azimuth: (0..360).contains(&azimuth).then_some(azimuth as u16),
+                cno: block[2],
+                used: flags & NAV_SAT_SV_USED != 0,
+            });
+        }
+        Some(view)
+    }
+
+    /// Adds a satellite, ignoring it when full.
+    fn push(&mut self, satellite: Satellite) {
+        if self.len < MAX_SATELLITES {
+            self.satellites[self.len] = satellite;
+            self.len += 1;
+        }
+    }
+}
+
+impl Default for SkyView {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Most satellites listed as used by the GSA sentences of one epoch.
+const MAX_USED: usize = 32;
+
+/// Builds a [`SkyView`] from NMEA sentences.
+///
+/// GSV only carries a few satellites per sentence, so each constellation's entries are replaced
+/// when the first sentence of its group arrives. Satellites are marked as used when the GSA
+/// sentences before them list their id for the same constellation. Ids repeat across
+/// constellations since NMEA 4.10, which names the constellation with the system id; a GN GSA
+/// without one leaves it unknown and matches on the id alone.
+pub struct GsvCollector {
+    view: SkyView,
+    used: [(Constellation, u16); MAX_USED],
+    used_len: usize,
+    /// A GSV arrived since the last GSA, so the next GSA starts a new epoch.
+    gsv_seen: bool,
+}
+
+impl GsvCollector {
+    pub const fn new() -> Self {
+        Self {
+            view: SkyView::new(),
+            used: [(Constellation::Unknown, 0); MAX_USED],
+            used_len: 0,
+            gsv_seen: false,
+        }
+    }
+
+    pub fn view(&self) -> &SkyView {
+        &self.view
+    }
+
+    pub fn add_gsa(&mut self, gsa: &Gsa) {
+        if self.gsv_seen {
+            self.used_len = 0;
+            self.gsv_seen = false;
+        }
+        let constellation = match gsa.system {
+            Some(system) => Constellation::from_system_id(system),
+            None => Constellation::from_talker(gsa.talker),
+        };
+        for id in gsa.satellites.iter().flatten() {
+            if self.used_len < MAX_USED {
+                self.used[self.used_len] = (constellation, *id);
+                self.used_len += 1;
+            }
+        }
+    }
+
+    pub fn add_gsv(&mut self, gsv: &Gsv) {
+        self.gsv_seen = true;
+        let constellation = Constellation::from_talker(gsv.talker);
+
+        if gsv.sentence == 1 {
+            let view = &mut self.view;
+            let mut kept = 0;
+            for i in 0..view.len {
+                if view.satellites[i].constellation != constellation {
+                    view.satellites[kept] = view.satellites[i];
+                    kept += 1;
+                }
+            }
+            view.len = kept;
+        }
+
+        for satellite in gsv.satellites.iter().flatten() {
+            self.view.push(Satellite {
+                constellation,
+                id: satellite.id,
+                elevation: satellite.elevation.map(|elevation| elevation as i8),
+                azimuth: satellite.azimuth,
+                cno: satellite.cno.unwrap_or(0),
+                used: self.used[..self.used_len].iter().any(|&(used, id)| {
+                    id == satellite.id && (used == constellation || used == Constellation::Unknown)
+                }),
+            });
+        }
+    }
+}
+
+impl Default for GsvCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,387 @@
+//! Local time from UTC.
+//!
+//! Zones are POSIX TZ strings, as in the last line of a tzfile, e.g.
+//! `NZST-12NZDT,M9.5.0,M4.1.0/3` for New Zealand. Their offsets count hours west of Greenwich,
+//! the opposite of ISO 8601; everything here holds seconds east. Without a configured zone one
+//! can be picked from the longitude.
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, TimeDelta};
+
+const HOUR: i32 = 3600;
+
+/// Largest offset from UTC allowed by POSIX, in hours.
+const MAX_OFFSET_HOURS: i32 = 24;
+
+/// Largest DST transition time in hours, from the RFC 8536 extension to POSIX.
+const MAX_TRANSITION_HOURS: i32 = 167;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ParseError {
+    /// Zone abbreviation missing or shorter than three characters.
+    Name,
+    /// Offset missing or out of range.
+    Offset,
+    /// Malformed DST start or end rule.
+    Rule,
+    /// Characters left after the zone.
+    Trailing,
+}
+
+/// Day of the year a DST transition falls on.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Rule {
+    /// `Jn`, day 1 to 365 not counting 29 February.
+    Julian(u16),
+    /// `n`, day 0 to 365 counting 29 February.
+    Day(u16),
+    /// `Mm.w.d`, day `d` (0 for Sunday) of week `w` (5 for the last) of month `m`.
+    MonthWeekDay { month: u8, week: u8, weekday: u8 },
+}
+
+impl Rule {
+    /// Date the rule falls on in `year`, None if there is no such day.
+    fn date(self, year: i32) -> Option<NaiveDate> {
+        match self {
+            Rule::Julian(day) => {
+                // Day 60 is 1 March even in leap years
+                let leap = NaiveDate::from_ymd_opt(year, 2, 29).is_some();
+                let day = if leap && day >= 60 { day + 1 } else { day };
+                NaiveDate::from_yo_opt(year, day as u32)
+            }
+            Rule::Day(day) => NaiveDate::from_yo_opt(year, day as u32 + 1),
+            Rule::MonthWeekDay {
+                month,
+                week,
+                weekday,
+            } => {
+                let first = NaiveDate::from_ymd_opt(year, month as u32, 1)?;
+                let first_weekday = first.weekday().num_days_from_sunday();
+                let day = 1 + (weekday as u32 + 7 - first_weekday) % 7 + 7 * (week as u32 - 1);
+                // Week 5 is the last, which may be the fourth
+                NaiveDate::from_ymd_opt(year, month as u32, day)
+                    .or_else(|| NaiveDate::from_ymd_opt(year, month as u32, day - 7))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transition {
+    pub rule: Rule,
+    /// Local time of the change in seconds after midnight, which may fall on another day.
+    pub time: i32,
+}
+
+impl Transition {
+    /// UTC of the change in `year`, given the offset in force before it.
+    fn utc(self, year: i32, offset_before: i32) -> Option<NaiveDateTime> {
+        let midnight = self.rule.date(year)?.and_hms_opt(0, 0, 0)?;
+        midnight.checked_add_signed(TimeDelta::seconds((self.time - offset_before) as i64))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Dst<'a> {
+    pub name: &'a str,
+    /// Seconds east of UTC.
+    pub offset: i32,
+    pub start: Transition,
+    pub end: Transition,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TimeZone<'a> {
+    /// Abbreviation of standard time.
+    pub name: &'a str,
+    /// Seconds east of UTC.
+    pub offset: i32,
+    pub dst: Option<Dst<'a>>,
+}
+
+pub const UTC: TimeZone<'static> = TimeZone {
+    name: "UTC",
+    offset: 0,
+    dst: None,
+};
+
+/// Names of the nautical zones from UTC-12 to UTC+12.
+const NAUTICAL_NAMES: [&str; 25] = [
+    "UTC-12", "UTC-11", "UTC-10", "UTC-9", "UTC-8", "UTC-7", "UTC-6", "UTC-5", "UTC-4", "UTC-3",
+    "UTC-2", "UTC-1", "UTC", "UTC+1", "UTC+2", "UTC+3", "UTC+4", "UTC+5", "UTC+6", "UTC+7",
+    "UTC+8", "UTC+9", "UTC+10", "UTC+11", "UTC+12",
+];
+
+/// Wall clock time in a zone.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LocalTime<'a> {
+    pub time: NaiveDateTime,
+    /// Abbreviation of the zone at `time`, e.g. NZDT.
+    pub name: &'a str,
+    pub dst: bool,
+}
+
+impl<'a> TimeZone<'a> {
+    /// Parses a POSIX TZ string, `std offset [dst [offset] [,start[/time],end[/time]]]`. DST
+    /// follows the US rules when it has none, and is an hour ahead when it has no offset.
+    pub fn parse(tz: &'a str) -> Result<Self, ParseError> {
+        let mut cursor = Cursor { tz, pos: 0 };
+
+        let name = cursor.name()?;
+        let offset = -cursor.hms(MAX_OFFSET_HOURS).ok_or(ParseError::Offset)?;
+        if cursor.is_empty() {
+            return Ok(Self {
+                name,
+                offset,
+                dst: None,
+            });
+        }
+
+        let dst_name = cursor.name()?;
+        let dst_offset = match cursor.peek() {
+            None | Some(b',') => offset + HOUR,
+            _ => -cursor.hms(MAX_OFFSET_HOURS).ok_or(ParseError::Offset)?,
+        };
+        let (start, end) = if cursor.is_empty() {
+            (
+                Transition {
+                    rule: Rule::MonthWeekDay {
+                        month: 3,
+                        week: 2,
+                        weekday: 0,
+                    },
+                    time: 2 * HOUR,
+                },
+                Transition {
+                    rule: Rule::MonthWeekDay {
+                        month: 11,
+                        week: 1,
+                        weekday: 0,
+                    },
+                    time: 2 * HOUR,
+                },
+            )
+        } else {
+            cursor.expect(b',').ok_or(ParseError::Rule)?;
+            let start = cursor.transition().ok_or(ParseError::Rule)?;
+            cursor.expect(b',').ok_or(ParseError::Rule)?;
+            let end = cursor.transition().ok_or(ParseError::Rule)?;
+            (start, end)
+        };
+        if !cursor.is_empty() {
+            return Err(ParseError::Trailing);
+        }
+
+        Ok(Self {
+            name,
+            offset,
+            dst: Some(Dst {
+                name: dst_name,
+                offset: dst_offset,
+                start,
+                end,
+            }),
+        })
+    }
+
+    /// Nautical zone of `longitude` in degrees, whole hours from UTC-12 to UTC+12 without DST.
+    /// Only a guess, as most zones follow borders rather than meridians.
+    pub fn from_longitude(longitude: f64) -> TimeZone<'static> {
+        let hours = libm::round(longitude / 15.).clamp(-12., 12.) as i32;
+        TimeZone {
+            name: NAUTICAL_NAMES[(hours + 12) as usize],
+            offset: hours * HOUR,
+            dst: None,
+        }
+    }
+
+    /// The zone from `configured` if it parses, else from `longitude` when `from_longitude` is
+    /// set and it is known, else UTC.
+    pub fn select(
+        configured: Option<&'a str>,
+        from_longitude: bool,
+        longitude: Option<f64>,
+    ) -> TimeZone<'a> {
+        if let Some(zone) = configured.and_then(|tz| Self::parse(tz).ok()) {
+            return zone;
+        }
+        match longitude {
+            Some(longitude) if from_longitude => Self::from_longitude(longitude),
+            _ => UTC,
+        }
+    }
+
+    /// Whether DST is in force at `utc`.
+    pub fn is_dst(&self, utc: NaiveDateTime) -> bool {
+        let Some(dst) = self.dst else {
+            return false;
+        };
+        // The rules are for the local year
+        let Some(year) = utc
+            .checked_add_signed(TimeDelta::seconds(self.offset as i64))
+            .map(|local| local.year())
+        else {
+            return false;
+        };
+        let (Some(start), Some(end)) = (
+            dst.start.utc(year, self.offset),
+            dst.end.utc(year, dst.offset),
+        ) else {
+            return false;
+        };
+
+        if start < end {
+            start <= utc && utc < end
+        } else {
+            // Southern hemisphere, DST spans the new year
+            utc < end || start <= utc
+        }
+    }
+
+    /// Local time at `utc`.
+    pub fn local(&self, utc: NaiveDateTime) -> LocalTime<'a> {
+        let (name, offset, dst) = match self.dst {
+            Some(dst) if self.is_dst(utc) => (dst.name, dst.offset, true),
+            _ => (self.name, self.offset, false),
+        };
+        LocalTime {
+            time: utc
+                .checked_add_signed(TimeDelta::seconds(offset as i64))
+                .unwrap_or(utc),
+            name,
+            dst,
+        }
+    }
+}
+
+/// How hours are shown.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HourFormat {
+    H24,
+    H12,
+}
+
+impl HourFormat {
+    /// Hour to show for `hour` from 0 to 23, with AM or PM in 12-hour format.
+    pub fn hour(self, hour: u32) -> (u32, Option<&'static str>) {
+        match self {
+            HourFormat::H24 => (hour, None),
+            HourFormat::H12 => {
+                let suffix = if hour < 12 { "AM" } else { "PM" };
+                match hour % 12 {
+                    0 => (12, Some(suffix)),
+                    hour => (hour, Some(suffix)),
+                }
+            }
+        }
+    }
+}
+
+struct Cursor<'a> {
+    tz: &'a str,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.tz.as_bytes().get(self.pos).copied()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.tz.len()
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    /// Advances over bytes matching `accept`, returning them.
+    fn take_while(&mut self, accept: impl Fn(u8) -> bool) -> &'a str {
+        let start = self.pos;
+        while self.peek().is_some_and(&accept) {
+            self.pos += 1;
+        }
+        &self.tz[start..self.pos]
+    }
+
+    /// Alphabetic abbreviation, or any of letters, digits and signs between `<` and `>`.
+    fn name(&mut self) -> Result<&'a str, ParseError> {
+        let name = if self.expect(b'<').is_some() {
+            let name = self
+                .take_while(|byte| byte.is_ascii_alphanumeric() || byte == b'+' || byte == b'-');
+            self.expect(b'>').ok_or(ParseError::Name)?;
+            name
+        } else {
+            self.take_while(|byte| byte.is_ascii_alphabetic())
+        };
+        if name.len() < 3 {
+            return Err(ParseError::Name);
+        }
+        Ok(name)
+    }
+
+    /// Decimal number of at most `max_digits` digits.
+    fn number(&mut self, max_digits: usize) -> Option<i32> {
+        let digits = self.take_while(|byte| byte.is_ascii_digit());
+        if digits.is_empty() || digits.len() > max_digits {
+            return None;
+        }
+        digits.parse().ok()
+    }
+
+    /// `[+|-]hh[:mm[:ss]]` in seconds, hours up to `max_hours`.
+    fn hms(&mut self, max_hours: i32) -> Option<i32> {
+        let sign = match self.peek() {
+            Some(b'-') => -1,
+            _ => 1,
+        };
+        if matches!(self.peek(), Some(b'-' | b'+')) {
+            self.pos += 1;
+        }
+
+        let hours = self.number(3).filter(|hours| *hours <= max_hours)?;
+        let mut seconds = hours * HOUR;
+        for unit in [60, 1] {
+            if self.expect(b':').is_none() {
+                break;
+            }
+            seconds += self.number(2).filter(|value| *value < 60)? * unit;
+        }
+        Some(sign * seconds)
+    }
+
+    /// `start[/time]` or `end[/time]`, the time defaulting to 02:00.
+    fn transition(&mut self) -> Option<Transition> {
+        let rule = match self.peek()? {
+            b'J' => {
+                self.pos += 1;
+                let day = self.number(3).filter(|day| (1..=365).contains(day))?;
+                Rule::Julian(day as u16)
+            }
+            b'M' => {
+                self.pos += 1;
+                let month = self.number(2).filter(|month| (1..=12).contains(month))?;
+                self.expect(b'.')?;
+                let week = self.number(1).filter(|week| (1..=5).contains(week))?;
+                self.expect(b'.')?;
+                let weekday = self.number(1).filter(|weekday| *weekday <= 6)?;
+                Rule::MonthWeekDay {
+                    month: month as u8,
+                    week: week as u8,
+                    weekday: weekday as u8,
+                }
+            }
+            _ => Rule::Day(self.number(3).filter(|day| *day <= 365)? as u16),
+        };
+
+        let time = match self.expect(b'/') {
+            Some(()) => self.hms(MAX_TRANSITION_HOURS)?,
+            None => 2 * HOUR,
+        };
+        Some(Transition { rule, time })
+    }
+}
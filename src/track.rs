@@ -0,0 +1,795 @@
+//! Track log.
+//!
+//! Fixes are sampled every few seconds or metres, delta encoded and appended to a ring of flash
+//! sectors in the `track` partition, so the oldest sector is erased once the partition is full.
+//! The log is dumped as GPX 1.1 over the USB serial console.
+//!
+//! Each sector starts with [`MAGIC`] and a sequence number, followed by records padded to the
+//! flash write size. A record is a tag byte and the zigzag varint encoded differences of
+//! latitude, longitude, elevation and time from the point before it. The first record of a
+//! sector or track segment is relative to zero, so every sector decodes on its own.
+
+use core::fmt::{self, Write};
+
+use chrono::{DateTime, Datelike, NaiveDateTime, Timelike};
+use embedded_storage::nor_flash::NorFlash;
+use geoconv::{haversine_distance, Degrees};
+#[cfg(feature = "esp")]
+use {
+    crate::assist::{self, AssistStore, Upload, FIRST_FIX},
+    crate::{clock, console, generated, gps::NAV_PVT_STATE, println},
+    embassy_futures::yield_now,
+    embassy_time::{with_timeout, Duration, Instant},
+    embedded_io_async::{Read as _, Write as _},
+    esp_hal::{
+        peripherals::*,
+        usb_serial_jtag::{UsbSerialJtag, UsbSerialJtagTx},
+        Async,
+    },
+    esp_storage::FlashStorage,
+};
+
+/// Offset of the `track` partition, which must match `partitions.csv`.
+pub const PARTITION_OFFSET: u32 = 0x30_0000;
+/// Size of the `track` partition, which must match `partitions.csv`.
+pub const PARTITION_SIZE: u32 = 0x10_0000;
+
+/// Starts every sector in use.
+pub const MAGIC: [u8; 4] = *b"TRK1";
+const HEADER_LEN: usize = 8;
+
+/// Records start and end on this alignment, a multiple of the flash write size.
+const ALIGN: usize = 4;
+
+const TAG_PAD: u8 = 0x00;
+const TAG_POINT: u8 = 0x01;
+/// A point starting a new track segment.
+const TAG_SEGMENT: u8 = 0x02;
+const ERASED: u8 = 0xff;
+
+/// Longest record: tag, three 5 byte and one 10 byte varint, and padding.
+pub const MAX_RECORD_LEN: usize = 28;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TrackPoint {
+    /// Latitude in 1e-7 degrees.
+    pub latitude: i32,
+    /// Longitude in 1e-7 degrees.
+    pub longitude: i32,
+    /// Height above mean sea level in decimetres.
+    pub elevation: i32,
+    /// Seconds since the Unix epoch.
+    pub time: i64,
+}
+
+impl TrackPoint {
+    /// From degrees, metres and UTC. An unknown (NaN) elevation reads as 0.
+    pub fn new(latitude: f64, longitude: f64, elevation: f64, time: NaiveDateTime) -> Self {
+        Self {
+            latitude: libm::round(latitude * 1e7) as i32,
+            longitude: libm::round(longitude * 1e7) as i32,
+            elevation: libm::round(elevation * 10.) as i32,
+            time: time.and_utc().timestamp(),
+        }
+    }
+
+    pub fn latitude_degrees(&self) -> f64 {
+        self.latitude as f64 / 1e7
+    }
+
+    pub fn longitude_degrees(&self) -> f64 {
+        self.longitude as f64 / 1e7
+    }
+
+    /// Great circle distance to `other` in metres.
+    pub fn distance_to(&self, other: &TrackPoint) -> f64 {
+        haversine_distance(
+            (
+                Degrees::new(self.latitude_degrees()),
+                Degrees::new(self.longitude_degrees()),
+            ),
+            (
+                Degrees::new(other.latitude_degrees()),
+                Degrees::new(other.longitude_degrees()),
+            ),
+        )
+        .as_float()
+    }
+}
+
+/// Encodes `point` as a record relative to `reference`, or to zero when there is none or it
+/// starts a new segment. Returns the length, padded to the flash write size.
+pub fn encode(
+    point: &TrackPoint,
+    reference: Option<&TrackPoint>,
+    new_segment: bool,
+    out: &mut [u8; MAX_RECORD_LEN],
+) -> usize {
+    let reference = match reference {
+        Some(reference) if !new_segment => *reference,
+        _ => ZERO,
+    };
+
+    out[0] = if new_segment { TAG_SEGMENT } else { TAG_POINT };
+    let mut len = 1;
+    for delta in [
+        point.latitude as i64 - reference.latitude as i64,
+        point.longitude as i64 - reference.longitude as i64,
+        point.elevation as i64 - reference.elevation as i64,
+        point.time.wrapping_sub(reference.time),
+    ] {
+        put_varint(out, &mut len, delta);
+    }
+    while !len.is_multiple_of(ALIGN) {
+        out[len] = TAG_PAD;
+        len += 1;
+    }
+    len
+}
+
+const ZERO: TrackPoint = TrackPoint {
+    latitude: 0,
+    longitude: 0,
+    elevation: 0,
+    time: 0,
+};
+
+/// A decoded record.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Record {
+    /// A point, and whether it starts a new track segment.
+    Point(TrackPoint, bool),
+    Padding,
+}
+
+/// Decodes the records of one sector, after its header.
+pub struct Decoder {
+    previous: Option<TrackPoint>,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self { previous: None }
+    }
+
+    /// Decodes the record at the start of `data`, returning it with its length. None at the end
+    /// of the written data, or if the record is cut short or corrupt.
+    pub fn decode(&mut self, data: &[u8]) -> Option<(Record, usize)> {
+        let new_segment = match *data.first()? {
+            TAG_PAD => return Some((Record::Padding, 1)),
+            TAG_POINT => false,
+            TAG_SEGMENT => true,
+            _ => return None,
+        };
+        let reference = match self.previous {
+            Some(previous) if !new_segment => previous,
+            _ => ZERO,
+        };
+
+        let mut len = 1;
+        let latitude = reference.latitude as i64 + get_varint(data, &mut len)?;
+        let longitude = reference.longitude as i64 + get_varint(data, &mut len)?;
+        let elevation = reference.elevation as i64 + get_varint(data, &mut len)?;
+        let time = reference.time.wrapping_add(get_varint(data, &mut len)?);
+        let point = TrackPoint {
+            latitude: latitude.try_into().ok()?,
+            longitude: longitude.try_into().ok()?,
+            elevation: elevation.try_into().ok()?,
+            time,
+        };
+
+        // Take the padding with it, the next record starts aligned
+        while !len.is_multiple_of(ALIGN) && data.get(len) == Some(&TAG_PAD) {
+            len += 1;
+        }
+
+        self.previous = Some(point);
+        Some((Record::Point(point, new_segment), len))
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn put_varint(out: &mut [u8], len: &mut usize, value: i64) {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    loop {
+        let byte = (zigzag & 0x7f) as u8;
+        zigzag >>= 7;
+        if zigzag == 0 {
+            out[*len] = byte;
+            *len += 1;
+            return;
+        }
+        out[*len] = byte | 0x80;
+        *len += 1;
+    }
+}
+
+fn get_varint(data: &[u8], len: &mut usize) -> Option<i64> {
+    let mut zigzag = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*len)?;
+        *len += 1;
+        zigzag |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+        }
+    }
+    None
+}
+
+/// Ring of flash sectors holding the track.
+pub struct TrackLog<F> {
+    flash: F,
+    offset: u32,
+    sectors: u32,
+    /// Sector being written, its sequence number and the write position in it.
+    sector: u32,
+    sequence: u32,
+    position: usize,
+    previous: Option<TrackPoint>,
+    new_segment: bool,
+}
+
+impl<F: NorFlash> TrackLog<F> {
+    /// Opens the log in the `size` bytes of `flash` from `offset`, carrying on after the last
+    /// point written. The next point starts a new segment.
+    pub fn open(flash: F, offset: u32, size: u32) -> Result<Self, F::Error> {
+        assert!(ALIGN.is_multiple_of(F::WRITE_SIZE) && ALIGN.is_multiple_of(F::READ_SIZE));
+        assert!(F::ERASE_SIZE >= HEADER_LEN + MAX_RECORD_LEN);
+        assert!(size as usize >= F::ERASE_SIZE);
+
+        let mut log = Self {
+            flash,
+            offset,
+            sectors: size / F::ERASE_SIZE as u32,
+            sector: 0,
+            sequence: 0,
+            position: HEADER_LEN,
+            previous: None,
+            new_segment: true,
+        };
+
+        let mut newest = None;
+        for sector in 0..log.sectors {
+            if let Some(sequence) = log.sequence(sector)? {
+                if newest.is_none_or(|(_, newest)| sequence > newest) {
+                    newest = Some((sector, sequence));
+                }
+            }
+        }
+
+        match newest {
+            Some((sector, sequence)) => {
+                log.sector = sector;
+                log.sequence = sequence;
+                let mut decoder = Decoder::new();
+                while let Some((record, len)) =
+                    log.read_record(sector, log.position, &mut decoder)?
+                {
+                    if let Record::Point(point, _) = record {
+                        log.previous = Some(point);
+                    }
+                    log.position += len;
+                }
+                // A record cut short by a reset cannot be written over, go on in the next sector
+                if log.position < F::ERASE_SIZE {
+                    let mut word = [0u8; ALIGN];
+                    let address = log.address(sector) + log.position as u32;
+                    log.flash.read(address, &mut word)?;
+                    if word != [ERASED; ALIGN] {
+                        log.position = F::ERASE_SIZE;
+                    }
+                }
+            }
+            None => log.start_sector(0, 0)?,
+        }
+        Ok(log)
+    }
+
+    /// Gives the flash back.
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /// The last point written, None if the log is empty.
+    pub fn last(&self) -> Option<TrackPoint> {
+        self.previous
+    }
+
+    /// Starts a new track segment with the next point, e.g. after the fix was lost.
+    pub fn break_segment(&mut self) {
+        self.new_segment = true;
+    }
+
+    pub fn append(&mut self, point: &TrackPoint) -> Result<(), F::Error> {
+        if self.position + MAX_RECORD_LEN > F::ERASE_SIZE {
+            let sector = (self.sector + 1) % self.sectors;
+            self.start_sector(sector, self.sequence.wrapping_add(1))?;
+        }
+
+        // The first point of a sector is relative to zero
+        let reference = self.previous.filter(|_| self.position > HEADER_LEN);
+        let mut record = [0u8; MAX_RECORD_LEN];
+        let len = encode(point, reference.as_ref(), self.new_segment, &mut record);
+        self.flash.write(
+            self.address(self.sector) + self.position as u32,
+            &record[..len],
+        )?;
+
+        self.position += len;
+        self.previous = Some(*point);
+        self.new_segment = false;
+        Ok(())
+    }
+
+    /// Erases the whole log.
+    pub fn clear(&mut self) -> Result<(), F::Error> {
+        (0..self.sectors).try_for_each(|step| self.clear_step(step))
+    }
+
+    /// Number of flash sectors in the log.
+    pub fn sectors(&self) -> u32 {
+        self.sectors
+    }
+
+    /// Erases one sector for each `step` from 0 to [`TrackLog::sectors`] - 1, so the caller can
+    /// let others run in between. The log is empty after the last step, and must not be appended
+    /// to before.
+    pub fn clear_step(&mut self, step: u32) -> Result<(), F::Error> {
+        // Sector 0 goes last, the log starts over in it
+        let sector = (step + 1) % self.sectors;
+        if sector != 0 {
+            let address = self.address(sector);
+            return self.flash.erase(address, address + F::ERASE_SIZE as u32);
+        }
+        self.previous = None;
+        self.new_segment = true;
+        self.start_sector(0, 0)
+    }
+
+    /// Points oldest first, with whether each starts a new track segment.
+    pub fn points(&mut self) -> Points<'_, F> {
+        Points {
+            // The sector after the one being written is the oldest
+            sector: self.sector + 1,
+            last_sector: self.sector + self.sectors,
+            position: None,
+            decoder: Decoder::new(),
+            log: self,
+        }
+    }
+
+    fn address(&self, sector: u32) -> u32 {
+        self.offset + sector * F::ERASE_SIZE as u32
+    }
+
+    /// Sequence number of `sector`, None if it is not in use.
+    fn sequence(&mut self, sector: u32) -> Result<Option<u32>, F::Error> {
+        let mut header = [0u8; HEADER_LEN];
+        self.flash.read(self.address(sector), &mut header)?;
+        Ok((header[..4] == MAGIC).then(|| u32::from_le_bytes(header[4..].try_into().unwrap())))
+    }
+
+    fn start_sector(&mut self, sector: u32, sequence: u32) -> Result<(), F::Error> {
+        let address = self.address(sector);
+        self.flash.erase(address, address + F::ERASE_SIZE as u32)?;
+        let mut header = [0u8; HEADER_LEN];
+        header[..4].copy_from_slice(&MAGIC);
+        header[4..].copy_from_slice(&sequence.to_le_bytes());
+        self.flash.write(address, &header)?;
+
+        self.sector = sector;
+        self.sequence = sequence;
+        self.position = HEADER_LEN;
+        Ok(())
+    }
+
+    /// Reads the record at `position` in `sector`, None at the end of its data.
+    fn read_record(
+        &mut self,
+        sector: u32,
+        position: usize,
+        decoder: &mut Decoder,
+    ) -> Result<Option<(Record, usize)>, F::Error> {
+        // Records start aligned, but padding bytes are read one at a time
+        let start = position - position % ALIGN;
+        let end = (start + MAX_RECORD_LEN).min(F::ERASE_SIZE);
+        if position >= end {
+            return Ok(None);
+        }
+        let mut window = [ERASED; MAX_RECORD_LEN];
+        let window = &mut window[..end - start];
+        self.flash
+            .read(self.address(sector) + start as u32, window)?;
+        Ok(decoder.decode(&window[position - start..]))
+    }
+}
+
+/// Iterator over the points of a [`TrackLog`].
+pub struct Points<'a, F> {
+    log: &'a mut TrackLog<F>,
+    /// Sector being read, counting on past the end of the ring.
+    sector: u32,
+    last_sector: u32,
+    /// Read position in `sector`, None before its header is checked.
+    position: Option<usize>,
+    decoder: Decoder,
+}
+
+impl<F: NorFlash> Iterator for Points<'_, F> {
+    type Item = Result<(TrackPoint, bool), F::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.sector <= self.last_sector {
+            let sector = self.sector % self.log.sectors;
+            let position = match self.position {
+                Some(position) => position,
+                None => match self.log.sequence(sector) {
+                    Ok(Some(_)) => {
+                        self.decoder = Decoder::new();
+                        HEADER_LEN
+                    }
+                    Ok(None) => {
+                        self.sector += 1;
+                        continue;
+                    }
+                    Err(error) => return Some(Err(error)),
+                },
+            };
+
+            match self.log.read_record(sector, position, &mut self.decoder) {
+                Ok(Some((record, len))) => {
+                    self.position = Some(position + len);
+                    if let Record::Point(point, new_segment) = record {
+                        return Some(Ok((point, new_segment)));
+                    }
+                }
+                Ok(None) => {
+                    self.sector += 1;
+                    self.position = None;
+                }
+                Err(error) => return Some(Err(error)),
+            }
+        }
+        None
+    }
+}
+
+/// Decides which fixes go in the log: the first, then one every `interval` seconds or sooner
+/// once `distance` metres from the last. A zero threshold is left out, and with both zero every
+/// fix is kept.
+pub struct Sampler {
+    interval: u32,
+    distance: f64,
+    last: Option<TrackPoint>,
+}
+
+impl Sampler {
+    pub const fn new(interval: u32, distance: f64) -> Self {
+        Self {
+            interval,
+            distance,
+            last: None,
+        }
+    }
+
+    /// Whether to log `point`, which becomes the last one if so.
+    pub fn sample(&mut self, point: &TrackPoint) -> bool {
+        let keep = match self.last {
+            None => true,
+            Some(last) => {
+                let due = self.interval > 0 && point.time - last.time >= self.interval as i64;
+                let moved = self.distance > 0. && point.distance_to(&last) >= self.distance;
+                due || moved || (self.interval == 0 && self.distance <= 0.)
+            }
+        };
+        if keep {
+            self.last = Some(*point);
+        }
+        keep
+    }
+}
+
+/// Writes a GPX 1.1 document a point at a time.
+pub struct Gpx {
+    in_segment: bool,
+}
+
+impl Gpx {
+    /// Writes the header and opens the track.
+    pub fn start(out: &mut impl Write) -> Result<Self, fmt::Error> {
+        out.write_str(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<gpx version=\"1.1\" creator=\"compass\" ",
+            "xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+            "<trk>\n",
+        ))?;
+        Ok(Self { in_segment: false })
+    }
+
+    pub fn point(
+        &mut self,
+        out: &mut impl Write,
+        point: &TrackPoint,
+        new_segment: bool,
+    ) -> fmt::Result {
+        if new_segment && self.in_segment {
+            out.write_str("</trkseg>\n")?;
+            self.in_segment = false;
+        }
+        if !self.in_segment {
+            out.write_str("<trkseg>\n")?;
+            self.in_segment = true;
+        }
+
+        write!(
+            out,
+            "<trkpt lat=\"{}\" lon=\"{}\"><ele>{}</ele>",
+            Decimal(point.latitude as i64, 7),
+            Decimal(point.longitude as i64, 7),
+            Decimal(point.elevation as i64, 1),
+        )?;
+        if let Some(time) = DateTime::from_timestamp(point.time, 0) {
+            write!(
+                out,
+                "<time>{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z</time>",
+                time.year(),
+                time.month(),
+                time.day(),
+                time.hour(),
+                time.minute(),
+                time.second()
+            )?;
+        }
+        out.write_str("</trkpt>\n")
+    }
+
+    /// Closes the track and document.
+    pub fn finish(self, out: &mut impl Write) -> fmt::Result {
+        if self.in_segment {
+            out.write_str("</trkseg>\n")?;
+        }
+        out.write_str("</trk>\n</gpx>\n")
+    }
+}
+
+/// Fixed point number with this many decimal places, written exactly.
+struct Decimal(i64, u32);
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Decimal(value, places) = *self;
+        let scale = 10u64.pow(places);
+        let sign = if value < 0 { "-" } else { "" };
+        let value = value.unsigned_abs();
+        write!(
+            f,
+            "{sign}{}.{:0width$}",
+            value / scale,
+            value % scale,
+            width = places as usize
+        )
+    }
+}
+
+/// How often the fix is checked for a point to log.
+#[cfg(feature = "esp")]
+const SAMPLE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Longest console command.
+#[cfg(feature = "esp")]
+const MAX_COMMAND_LEN: usize = 16;
+
+/// Bytes read from the console at once, the size of a USB packet.
+#[cfg(feature = "esp")]
+const INPUT_LEN: usize = 64;
+
+/// Logs the track, and answers on the USB serial console:
+///
+/// - `gpx`: dump the track.
+/// - `clear`: erase it.
+/// - `assist <bytes>`: take an assistance file of that many bytes, sent right after.
+/// - `assist clear`: erase the assistance file.
+/// - `ttff`: show the time to first fix.
+#[cfg(feature = "esp")]
+#[embassy_executor::task]
+pub async fn track_task(usb: USB_DEVICE<'static>) -> ! {
+    println!("Started Track Task");
+
+    let mut log = match TrackLog::open(FlashStorage::new(), PARTITION_OFFSET, PARTITION_SIZE) {
+        Ok(log) => log,
+        Err(error) => {
+            println!("Track log unavailable: {:?}", error);
+            loop {
+                core::future::pending::<()>().await;
+            }
+        }
+    };
+    let mut assist = AssistStore::new(
+        FlashStorage::new(),
+        assist::PARTITION_OFFSET,
+        assist::PARTITION_SIZE,
+    );
+    let mut upload = None;
+    let mut sampler = Sampler::new(generated::TRACK_INTERVAL, generated::TRACK_DISTANCE);
+    let (mut rx, mut tx) = UsbSerialJtag::new(usb).into_async().split();
+
+    let mut command = [0u8; MAX_COMMAND_LEN];
+    let mut command_len = 0;
+    let mut last_sample = Instant::now();
+    loop {
+        let mut input = [0u8; INPUT_LEN];
+        if let Ok(Ok(len)) = with_timeout(SAMPLE_PERIOD, rx.read(&mut input)).await {
+            let mut input = &input[..len];
+            while let Some((&byte, rest)) = input.split_first() {
+                // An assistance file being uploaded takes the bytes it is due
+                if let Some(file) = &mut upload {
+                    match assist.write(file, input) {
+                        Ok(taken) => input = &input[taken..],
+                        Err(error) => {
+                            // What is left of the file reads as bad commands
+                            println!("Assistance upload failed: {:?}", error);
+                            upload = None;
+                            continue;
+                        }
+                    }
+                    if upload.as_ref().is_some_and(Upload::is_complete) {
+                        match assist.finish(upload.take().unwrap()) {
+                            Ok(messages) => println!("Assistance file of {} messages", messages),
+                            Err(error) => println!("Assistance upload failed: {:?}", error),
+                        }
+                    }
+                    continue;
+                }
+                input = rest;
+
+                if byte != b'\n' && byte != b'\r' {
+                    if command_len < MAX_COMMAND_LEN {
+                        command[command_len] = byte;
+                    }
+                    command_len += 1;
+                    continue;
+                }
+                match &command[..command_len.min(MAX_COMMAND_LEN)] {
+                    b"" => {}
+                    b"gpx" => {
+                        // The logs share the port, nothing else may print into the file
+                        console::mute();
+                        let dumped = dump_gpx(&mut log, &mut tx).await;
+                        console::unmute();
+                        if dumped.is_err() {
+                            println!("Track dump failed");
+                        }
+                    }
+                    b"clear" => match clear(&mut log).await {
+                        Ok(()) => println!("Track cleared"),
+                        Err(error) => println!("Track clear failed: {:?}", error),
+                    },
+                    b"assist clear" => match assist.clear() {
+                        Ok(()) => println!("Assistance file cleared"),
+                        Err(error) => println!("Assistance clear failed: {:?}", error),
+                    },
+                    b"ttff" => {
+                        let first_fix = critical_section::with(|cs| FIRST_FIX.borrow(cs).get());
+                        println!("{}", first_fix);
+                    }
+                    other => match other.strip_prefix(b"assist ").and_then(parse_len) {
+                        Some(len) => match assist.begin(len) {
+                            Ok(file) => {
+                                println!("Send {} bytes", len);
+                                upload = Some(file);
+                            }
+                            Err(error) => println!("Assistance upload failed: {:?}", error),
+                        },
+                        None => {
+                            println!("Commands: gpx, clear, assist <bytes>, assist clear, ttff")
+                        }
+                    },
+                }
+                command_len = 0;
+            }
+        }
+
+        if last_sample.elapsed() < SAMPLE_PERIOD {
+            continue;
+        }
+        last_sample = Instant::now();
+
+        let state = critical_section::with(|cs| NAV_PVT_STATE.borrow(cs).get());
+        match (state.position(), clock::now_utc()) {
+            (Some(lle), Some(utc)) => {
+                let point = TrackPoint::new(
+                    lle.latitude.as_float(),
+                    lle.longitude.as_float(),
+                    state.msl,
+                    utc,
+                );
+                if sampler.sample(&point) {
+                    if let Err(error) = log.append(&point) {
+                        println!("Track append failed: {:?}", error);
+                    }
+                }
+            }
+            // The gap is left out of the track
+            _ => log.break_segment(),
+        }
+    }
+}
+
+/// Writes the whole log as GPX to the console.
+#[cfg(feature = "esp")]
+async fn dump_gpx<F: NorFlash>(
+    log: &mut TrackLog<F>,
+    tx: &mut UsbSerialJtagTx<'_, Async>,
+) -> Result<(), ()> {
+    let mut line = Line::new();
+    let mut gpx = Gpx::start(&mut line).map_err(|_| ())?;
+    tx.write_all(line.take()).await.map_err(|_| ())?;
+
+    for point in log.points() {
+        let (point, new_segment) = point.map_err(|_| ())?;
+        gpx.point(&mut line, &point, new_segment).map_err(|_| ())?;
+        tx.write_all(line.take()).await.map_err(|_| ())?;
+    }
+
+    gpx.finish(&mut line).map_err(|_| ())?;
+    tx.write_all(line.take()).await.map_err(|_| ())?;
+    tx.flush().await.map_err(|_| ())
+}
+
+/// Erases the log a sector at a time, letting the other tasks run while each is erased.
+#[cfg(feature = "esp")]
+async fn clear<F: NorFlash>(log: &mut TrackLog<F>) -> Result<(), F::Error> {
+    for step in 0..log.sectors() {
+        log.clear_step(step)?;
+        yield_now().await;
+    }
+    Ok(())
+}
+
+/// Parses a length in decimal.
+#[cfg(feature = "esp")]
+fn parse_len(digits: &[u8]) -> Option<u32> {
+    core::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+/// Buffer for one line of GPX at a time.
+#[cfg(feature = "esp")]
+struct Line {
+    buf: [u8; 192],
+    len: usize,
+}
+
+#[cfg(feature = "esp")]
+impl Line {
+    fn new() -> Self {
+        Self {
+            buf: [0; 192],
+            len: 0,
+        }
+    }
+
+    /// The line so far, leaving the buffer empty for the next.
+    fn take(&mut self) -> &[u8] {
+        let len = core::mem::take(&mut self.len);
+        &self.buf[..len]
+    }
+}
+
+#[cfg(feature = "esp")]
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+//! Trip computer: distance travelled, moving time and speeds since the last reset, and progress
+//! toward the selected landmark.
+//!
+//! Distance is counted in steps of at least [`MIN_STEP`] between fixes taken while moving, so the
+//! wander of a stationary fix does not add up.
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use embassy_time::{Duration, Instant};
+use geoconv::{haversine_distance, Degrees};
+
+use crate::{landmark::Landmark, receiver::NavPvtState};
+
+pub static TRIP: Mutex<Cell<Trip>> = Mutex::new(Cell::new(Trip::new()));
+
+/// Speed over ground in m/s below which the receiver is taken to be stationary.
+pub const MOVING_SPEED: f64 = 0.5;
+
+/// Shortest distance in metres counted at once, above the typical wander of a fix.
+pub const MIN_STEP: f64 = 5.;
+
+/// Longest time between fixes counted as moving time, longer means the fix was lost.
+pub const MAX_GAP: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trip {
+    /// Distance travelled in metres.
+    pub distance: f64,
+    /// Time spent moving.
+    pub moving_time: Duration,
+    /// Highest speed over ground in m/s, 0 until moving.
+    pub max_speed: f64,
+    /// Latitude and longitude the distance was last counted to.
+    anchor: Option<(Degrees, Degrees)>,
+    /// When the last fix with a position was taken.
+    last: Option<Instant>,
+}
+
+impl Trip {
+    pub const fn new() -> Self {
+        Self {
+            distance: 0.,
+            moving_time: Duration::from_secs(0),
+            max_speed: 0.,
+            anchor: None,
+            last: None,
+        }
+    }
+
+    /// Adds the fix taken at `at`, with `position` None when it is not good enough to navigate
+    /// by and `speed` over ground in m/s. A fix taken no later than the last one is ignored.
+    pub fn update(&mut self, position: Option<(Degrees, Degrees)>, speed: f64, at: Instant) {
+        if self.last.is_some_and(|last| at <= last) {
+            return;
+        }
+        // Through a lost fix the anchor stays, the way back counts as a straight line
+        let Some(position) = position else {
+            return;
+        };
+        let last = self.last.replace(at);
+        let Some(anchor) = self.anchor else {
+            self.anchor = Some(position);
+            return;
+        };
+        // NaN when NMEA receivers give no speed
+        if speed.is_nan() || speed < MOVING_SPEED {
+            return;
+        }
+
+        if let Some(elapsed) = last.and_then(|last| at.checked_duration_since(last)) {
+            if elapsed <= MAX_GAP {
+                self.moving_time += elapsed;
+            }
+        }
+        self.max_speed = self.max_speed.max(speed);
+
+        let step = haversine_distance(anchor, position).as_float();
+        if step >= MIN_STEP {
+            self.distance += step;
+            self.anchor = Some(position);
+        }
+    }
+
+    /// Average speed in m/s over the moving time, None before moving.
+    pub fn average_speed(&self) -> Option<f64> {
+        let seconds = self.moving_time.as_millis() as f64 / 1000.;
+        (seconds > 0.).then(|| self.distance / seconds)
+    }
+}
+
+impl Default for Trip {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How a landmark is being approached.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// Distance left in metres.
+    pub distance: f64,
+    /// Speed toward the landmark in m/s, negative when moving away, NaN without a velocity.
+    pub velocity_made_good: f64,
+    /// Time to arrival at the current velocity made good, None unless moving toward it.
+    pub eta: Option<Duration>,
+}
+
+impl Progress {
+    /// Progress toward `landmark` from `position` with the north and east velocity `vel_ne`
+    /// in m/s.
+    pub fn toward(landmark: &Landmark, position: (Degrees, Degrees), vel_ne: (f64, f64)) -> Self {
+        let distance = landmark.distance_from(position).as_float();
+        let bearing = landmark.bearing_from(position).as_float().to_radians();
+        let velocity_made_good = vel_ne.0 * libm::cos(bearing) + vel_ne.1 * libm::sin(bearing);
+        let eta = (velocity_made_good >= MOVING_SPEED)
+            .then(|| Duration::from_millis((distance / velocity_made_good * 1000.) as u64));
+
+        Self {
+            distance,
+            velocity_made_good,
+            eta,
+        }
+    }
+}
+
+/// Adds the navigation solution in `state`, see [`Trip::update`].
+pub fn update(state: &NavPvtState) {
+    let Some(received) = state.received else {
+        return;
+    };
+    let position = state
+        .lle
+        .filter(|_| state.fix_quality_at(received).can_navigate())
+        .map(|lle| (lle.latitude, lle.longitude));
+
+    critical_section::with(|cs| {
+        let cell = TRIP.borrow(cs);
+        let mut trip = cell.get();
+        trip.update(position, state.speed_over_ground, received);
+        cell.set(trip);
+    })
+}
+
+/// Starts a new trip.
+pub fn reset() {
+    critical_section::with(|cs| TRIP.borrow(cs).set(Trip::new()))
+}
@@ -0,0 +1,200 @@
+//! UBX framing.
+//!
+//! Splits the byte stream from the receiver into complete, checksummed UBX frames. State is kept
+//! between calls to [`Framer::push`] so a frame split across several UART reads is still
+//! recovered, and everything that is thrown away is counted.
+//!
+//! Nothing in here touches hardware so it can be exercised with recorded streams.
+
+const SYNC_1: u8 = 0xb5;
+const SYNC_2: u8 = 0x62;
+
+/// Sync characters, class, id and the two byte length.
+const HEADER_LEN: usize = 6;
+const CHECKSUM_LEN: usize = 2;
+
+/// Largest payload [`Framer`] is sized for.
+pub const MAX_PAYLOAD_LEN: usize = 1280;
+
+/// Largest frame [`Framer`] can hold.
+pub const MAX_FRAME_LEN: usize = HEADER_LEN + MAX_PAYLOAD_LEN + CHECKSUM_LEN;
+
+/// 8-bit Fletcher checksum over class, id, length and payload.
+pub fn checksum(data: &[u8]) -> (u8, u8) {
+    data.iter().fold((0u8, 0u8), |(a, b), byte| {
+        let a = a.wrapping_add(*byte);
+        (a, b.wrapping_add(a))
+    })
+}
+
+/// Frames a message with `class`, `id` and `payload` into `buf` and returns the frame.
+///
+/// Panics if `buf` is shorter than the payload plus the header and checksum.
+pub fn encode<'b>(class: u8, id: u8, payload: &[u8], buf: &'b mut [u8]) -> &'b [u8] {
+    let len = HEADER_LEN + payload.len() + CHECKSUM_LEN;
+    let frame = &mut buf[..len];
+    let [len_low, len_high] = (payload.len() as u16).to_le_bytes();
+    frame[..HEADER_LEN].copy_from_slice(&[SYNC_1, SYNC_2, class, id, len_low, len_high]);
+    frame[HEADER_LEN..len - CHECKSUM_LEN].copy_from_slice(payload);
+    let (ck_a, ck_b) = checksum(&frame[2..len - CHECKSUM_LEN]);
+    frame[len - 2] = ck_a;
+    frame[len - 1] = ck_b;
+    frame
+}
+
+/// Counters for diagnostics.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    /// Complete frames with a valid checksum.
+    pub packets: u32,
+    /// Frames thrown away because the checksum did not match.
+    pub checksum_failures: u32,
+    /// Bytes skipped outside of frames, including those of failed or oversized frames.
+    pub dropped_bytes: u32,
+}
+
+/// ACK-ACK or ACK-NAK, the receiver's reply to a CFG message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ack {
+    Ack,
+    Nak,
+}
+
+const CLASS_ACK: u8 = 0x05;
+const ID_ACK_NAK: u8 = 0x00;
+const ID_ACK_ACK: u8 = 0x01;
+
+/// A complete UBX frame, sync characters and checksum included.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    pub fn class(&self) -> u8 {
+        self.bytes[2]
+    }
+
+    pub fn id(&self) -> u8 {
+        self.bytes[3]
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        &self.bytes[HEADER_LEN..self.bytes.len() - CHECKSUM_LEN]
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// The reply to the message with `class` and `id`, if this frame is one.
+    pub fn ack_for(&self, class: u8, id: u8) -> Option<Ack> {
+        if self.class() != CLASS_ACK || self.payload() != [class, id] {
+            return None;
+        }
+        match self.id() {
+            ID_ACK_ACK => Some(Ack::Ack),
+            ID_ACK_NAK => Some(Ack::Nak),
+            _ => None,
+        }
+    }
+}
+
+/// Collects bytes into UBX frames.
+pub struct Framer {
+    buf: [u8; MAX_FRAME_LEN],
+    len: usize,
+    /// Length of the frame last returned from the front of `buf`, dropped on the next push.
+    returned: usize,
+    stats: Stats,
+}
+
+impl Framer {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME_LEN],
+            len: 0,
+            returned: 0,
+            stats: Stats {
+                packets: 0,
+                checksum_failures: 0,
+                dropped_bytes: 0,
+            },
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Adds a byte, returning the frame it completes if any.
+    pub fn push(&mut self, byte: u8) -> Option<Frame<'_>> {
+        // Bytes a resync kept after the frame returned last are still waiting
+        let returned = core::mem::take(&mut self.returned);
+        self.buf.copy_within(returned..self.len, 0);
+        self.len -= returned;
+
+        match self.len {
+            0 if byte != SYNC_1 => {
+                self.stats.dropped_bytes += 1;
+                return None;
+            }
+            1 if byte != SYNC_2 => {
+                // The byte after a lone SYNC_1 may itself start a frame
+                self.stats.dropped_bytes += 1;
+                self.len = 0;
+                return self.push(byte);
+            }
+            _ => {}
+        }
+
+        self.buf[self.len] = byte;
+        self.len += 1;
+
+        loop {
+            if self.len < HEADER_LEN {
+                return None;
+            }
+
+            let payload_len = u16::from_le_bytes([self.buf[4], self.buf[5]]) as usize;
+            let frame_len = HEADER_LEN + payload_len + CHECKSUM_LEN;
+            if frame_len > MAX_FRAME_LEN {
+                self.resync();
+                continue;
+            }
+            if self.len < frame_len {
+                return None;
+            }
+
+            let (ck_a, ck_b) = checksum(&self.buf[2..frame_len - CHECKSUM_LEN]);
+            if (ck_a, ck_b) != (self.buf[frame_len - 2], self.buf[frame_len - 1]) {
+                self.stats.checksum_failures += 1;
+                self.resync();
+                continue;
+            }
+
+            self.stats.packets += 1;
+            self.returned = frame_len;
+            return Some(Frame {
+                bytes: &self.buf[..frame_len],
+            });
+        }
+    }
+
+    /// Drops the bad frame in `buf` up to the next sync characters after its start. A frame that
+    /// lost bytes on the way runs into the one after it, which is then still recovered.
+    fn resync(&mut self) {
+        let start = (1..self.len)
+            .find(|&i| self.buf[i] == SYNC_1 && (i + 1 == self.len || self.buf[i + 1] == SYNC_2))
+            .unwrap_or(self.len);
+        self.stats.dropped_bytes += start as u32;
+        self.buf.copy_within(start..self.len, 0);
+        self.len -= start;
+    }
+}
+
+impl Default for Framer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
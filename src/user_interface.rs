@@ -1,18 +1,20 @@
 use arrform::*;
 use async_button::ButtonEvent;
+use chrono::{Datelike, Timelike};
 use core::cell::Cell;
 use critical_section::Mutex;
+use embassy_time::{Duration, Instant};
 use pcd8544_hal::Pcd8544;
 
 use crate::{
-    display::DrawCommand,
-    generated,
-    gps::NAV_PVT_STATE,
-    landmark::Landmark,
-    user_interface::{
-        screen::ScreenBuffer,
-        sprites::{Anim, Frame},
-    },
+    clock, generated,
+    gps::{NAV_PVT_STATE, SKY_VIEW},
+    landmark::{self, Landmark, SELECTED_LANDMARK},
+    persist::LAST_FIX,
+    satellites::{Satellite, SkyView, MAX_SATELLITES},
+    timezone::TimeZone,
+    trip::{self, Progress, TRIP},
+    user_interface::{screen::ScreenBuffer, sprites::Anim},
 };
 
 pub mod sprites {
@@ -75,19 +77,37 @@ pub mod sprites {
 }
 
 pub static UI: Mutex<Cell<UserInterface>> = Mutex::new(Cell::new(UserInterface {
-    landmark_index: 0,
-
+    menu: Menu::Boot,
+    entered: Instant::from_ticks(0),
     anim: None,
 }));
 
+/// How long the boot logo stays up before the time screen.
+const BOOT_SCREEN: Duration = Duration::from_secs(2);
+
 enum Menu {
     Boot,
+    // Nothing to draw yet
+    #[allow(dead_code)]
     Neko,
     Time,
+    // Nothing to draw yet
+    #[allow(dead_code)]
     Compass,
+    SkyView,
+    Trip,
 }
 
 impl Menu {
+    /// The screen a short press moves on to.
+    fn next(&self) -> Menu {
+        match self {
+            Menu::Time => Menu::SkyView,
+            Menu::SkyView => Menu::Trip,
+            _ => Menu::Time,
+        }
+    }
+
     pub fn draw(&self, display: &mut impl Pcd8544) {
         display.clear();
         match self {
@@ -95,28 +115,210 @@ impl Menu {
                 display.draw_buffer(include_bytes!("./assets/rust_logo.bin"));
             }
             Menu::Time => {
-                critical_section::with(|cs| {
-                    let state = NAV_PVT_STATE.borrow(cs).get();
-                    display.print(
-                        arrform!(
-                            64,
-                            "  {day:02}:{month:02}:{year:04}  \n   {hour:02}:{min:02}:{sec:02}   ",
-                            day = state.day,
-                            month = state.month,
-                            year = state.year,
-                            hour = state.hour,
-                            min = state.min,
-                            sec = state.sec
+                let (state, last_fix) = critical_section::with(|cs| {
+                    (NAV_PVT_STATE.borrow(cs).get(), LAST_FIX.borrow(cs).get())
+                });
+                // The compass points from the last known fix until there is a new one
+                let fix = match (state.position(), last_fix) {
+                    (None, Some(_)) => "LAST KNOWN",
+                    _ => state.fix_quality().label(),
+                };
+                match clock::now_utc() {
+                    Some(utc) => {
+                        let zone = TimeZone::select(
+                            generated::TIME_ZONE,
+                            generated::TIME_ZONE_FROM_LONGITUDE,
+                            state.lle.map(|lle| lle.longitude.as_float()),
+                        );
+                        let local = zone.local(utc);
+                        let (hour, suffix) = generated::HOUR_FORMAT.hour(local.time.hour());
+                        let time = arrform!(
+                            14,
+                            "{hour:02}:{min:02}:{sec:02}{space}{suffix}",
+                            hour = hour,
+                            min = local.time.minute(),
+                            sec = local.time.second(),
+                            space = if suffix.is_some() { " " } else { "" },
+                            suffix = suffix.unwrap_or("")
+                        );
+                        display.print(
+                            arrform!(
+                                64,
+                                "  {day:02}:{month:02}:{year:04}  \n{time:^14}\n{zone:^14}\n{fix:^14}",
+                                day = local.time.day(),
+                                month = local.time.month(),
+                                year = local.time.year(),
+                                time = time.as_str(),
+                                zone = local.name,
+                                fix = fix
+                            )
+                            .as_str(),
                         )
-                        .as_str(),
-                    );
+                    }
+                    None => display.print(
+                        arrform!(64, "  --:--:----  \n   --:--:--   \n\n{fix:^14}", fix = fix)
+                            .as_str(),
+                    ),
+                }
+            }
+            Menu::SkyView => {
+                let view = critical_section::with(|cs| SKY_VIEW.borrow(cs).get());
+                display.draw_buffer(sky_view(&view).as_bytes());
+            }
+            Menu::Trip => {
+                let (trip, state, landmark) = critical_section::with(|cs| {
+                    (
+                        TRIP.borrow(cs).get(),
+                        NAV_PVT_STATE.borrow(cs).get(),
+                        landmark::selected(cs),
+                    )
+                });
+                let progress = state.position().map(|lle| {
+                    let velocity = (state.vel_ned.0, state.vel_ned.1);
+                    Progress::toward(landmark, (lle.latitude, lle.longitude), velocity)
                 });
+                let average = match trip.average_speed() {
+                    Some(speed) => arrform!(8, "{:.1}", speed * 3.6),
+                    None => arrform!(8, "-"),
+                };
+                let vmg = match progress.map(|progress| progress.velocity_made_good) {
+                    Some(speed) if speed.is_finite() => arrform!(8, "{:.1}", speed * 3.6),
+                    _ => arrform!(8, "--"),
+                };
+                let remaining = match progress {
+                    Some(progress) => distance(progress.distance),
+                    None => arrform!(12, "--"),
+                };
+                let eta = match progress.and_then(|progress| progress.eta) {
+                    Some(eta) => hours_minutes_seconds(eta),
+                    None => arrform!(16, "--:--"),
+                };
+                display.print(
+                    arrform!(
+                        128,
+                        "Trip{trip:>10}\nMove{moving:>10}\nAvg{average:>4} Max{max:>3.0}\n\
+                         {name:<6.6}{remaining:>8}\nVMG{vmg:>6} km/h\nETA{eta:>11}",
+                        trip = distance(trip.distance).as_str(),
+                        moving = hours_minutes_seconds(trip.moving_time).as_str(),
+                        average = average.as_str(),
+                        max = trip.max_speed * 3.6,
+                        name = landmark.name,
+                        remaining = remaining.as_str(),
+                        vmg = vmg.as_str(),
+                        eta = eta.as_str()
+                    )
+                    .as_str(),
+                )
             }
             _ => {}
         }
     }
 }
 
+/// Distance in metres in at most 8 characters.
+fn distance(metres: f64) -> ArrForm<12> {
+    if metres < 1000. {
+        arrform!(12, "{:.0}m", metres)
+    } else if metres < 100_000. {
+        arrform!(12, "{:.2}km", metres / 1000.)
+    } else {
+        arrform!(12, "{:.0}km", metres / 1000.)
+    }
+}
+
+/// Duration as h:mm:ss.
+fn hours_minutes_seconds(duration: Duration) -> ArrForm<16> {
+    let seconds = duration.as_secs();
+    arrform!(
+        16,
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Centre and radius of the polar plot, which fills the left of the screen.
+const SKY_CENTRE: (usize, usize) = (23, 23);
+const SKY_RADIUS: f32 = 23.;
+
+/// Left edge of the signal bars, which fill the right of the screen.
+const BARS_LEFT: usize = 51;
+const BAR_WIDTH: usize = 2;
+const BAR_GAP: usize = 1;
+
+/// C/N0 in dBHz of a full height bar.
+const BAR_FULL_CNO: usize = 50;
+
+/// Polar plot of the satellites, north up with the horizon on the outer ring and 45° on the inner
+/// one, next to their signal strengths, strongest first. Satellites used in the fix are filled.
+fn sky_view(view: &SkyView) -> ScreenBuffer {
+    let mut buffer = ScreenBuffer::default();
+
+    buffer.circle(SKY_CENTRE, SKY_RADIUS as usize, true);
+    buffer.circle(SKY_CENTRE, SKY_RADIUS as usize / 2, true);
+    // North
+    for y in 0..3 {
+        buffer.set_pixel(SKY_CENTRE.0, y, true);
+    }
+
+    for satellite in view.satellites() {
+        let (Some(elevation), Some(azimuth)) = (satellite.elevation, satellite.azimuth) else {
+            continue;
+        };
+        if elevation < 0 {
+            continue;
+        }
+        let r = SKY_RADIUS * (90. - elevation as f32) / 90.;
+        let azimuth = (azimuth as f32).to_radians();
+        let x = SKY_CENTRE.0 as f32 + r * libm::sinf(azimuth);
+        let y = SKY_CENTRE.1 as f32 - r * libm::cosf(azimuth);
+
+        // 3x3 marker, hollow when not used
+        for dx in 0..3 {
+            for dy in 0..3 {
+                if satellite.used || dx != 1 || dy != 1 {
+                    let px = libm::roundf(x) as isize + dx - 1;
+                    let py = libm::roundf(y) as isize + dy - 1;
+                    if (0..screen::WIDTH as isize).contains(&px)
+                        && (0..screen::HEIGHT as isize).contains(&py)
+                    {
+                        buffer.set_pixel(px as usize, py as usize, true);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut by_signal: [Option<Satellite>; MAX_SATELLITES] = [None; MAX_SATELLITES];
+    for (slot, satellite) in by_signal.iter_mut().zip(view.satellites()) {
+        *slot = Some(*satellite);
+    }
+    by_signal.sort_unstable_by_key(|satellite| core::cmp::Reverse(satellite.map(|s| s.cno)));
+
+    let bars = by_signal
+        .iter()
+        .flatten()
+        .filter(|satellite| satellite.cno > 0);
+    for (i, satellite) in bars.enumerate() {
+        let left = BARS_LEFT + i * (BAR_WIDTH + BAR_GAP);
+        if left + BAR_WIDTH > screen::WIDTH {
+            break;
+        }
+        let height = (satellite.cno as usize).min(BAR_FULL_CNO) * screen::HEIGHT / BAR_FULL_CNO;
+        for y in screen::HEIGHT - height..screen::HEIGHT {
+            // Dotted when not used
+            if satellite.used || y % 2 == 0 {
+                for x in left..left + BAR_WIDTH {
+                    buffer.set_pixel(x, y, true);
+                }
+            }
+        }
+    }
+
+    buffer
+}
+
 //impl into bitmap
 // buttons
 // we have 14 chars x 6
@@ -130,27 +332,58 @@ impl Menu {
 //
 // 12345678901234
 
-#[derive(Default)]
 pub struct UserInterface {
-    landmark_index: usize,
-    // menu: Menu,
+    menu: Menu,
+    /// When the current screen was entered.
+    entered: Instant,
+    #[allow(dead_code)]
     anim: Option<Anim>,
 }
 
+impl Default for UserInterface {
+    fn default() -> Self {
+        Self {
+            menu: Menu::Boot,
+            entered: Instant::now(),
+            anim: None,
+        }
+    }
+}
+
 // like this for changing menus
 // async fn transition() {}
+#[allow(dead_code)]
 enum UI {
     Menu,
     Transition(Anim),
 }
 
 impl UserInterface {
-    pub fn process(&mut self) -> &'static [DrawCommand] {
-        &[]
+    /// Draws the current screen, moving on from the boot logo once it has been up long enough.
+    pub fn draw(&mut self, display: &mut impl Pcd8544) {
+        if let Menu::Boot = self.menu {
+            if self.entered.elapsed() > BOOT_SCREEN {
+                self.show(Menu::Time);
+            }
+        }
+        self.menu.draw(display);
+    }
+
+    fn show(&mut self, menu: Menu) {
+        self.menu = menu;
+        self.entered = Instant::now();
     }
 
     pub fn process_input(&mut self, event: ButtonEvent) {
-        //
+        match event {
+            ButtonEvent::ShortPress { .. } => self.show(self.menu.next()),
+            // Long press on the trip screen starts a new trip
+            ButtonEvent::LongPress => {
+                if let Menu::Trip = self.menu {
+                    trip::reset();
+                }
+            }
+        }
     }
 
     pub async fn run(self) -> ! {
@@ -158,22 +391,30 @@ impl UserInterface {
         // wait
         // start main loop
 
-        loop {}
+        loop {
+            core::future::pending::<()>().await;
+        }
     }
 
     pub fn next_landmark(&mut self) {
-        self.landmark_index = (self.landmark_index + 1) % generated::LANDMARKS.len();
+        critical_section::with(|cs| {
+            let index = SELECTED_LANDMARK.borrow(cs);
+            index.set((index.get() + 1) % generated::LANDMARKS.len());
+        })
     }
 
     pub fn previouse_landmark(&mut self) {
-        self.landmark_index = match self.landmark_index {
-            0 => generated::LANDMARKS.len() - 1,
-            _ => self.landmark_index - 1,
-        }
+        critical_section::with(|cs| {
+            let index = SELECTED_LANDMARK.borrow(cs);
+            index.set(match index.get() {
+                0 => generated::LANDMARKS.len() - 1,
+                i => i - 1,
+            });
+        })
     }
 
     pub fn current_landmark(&mut self) -> &'static Landmark {
-        &generated::LANDMARKS[self.landmark_index]
+        critical_section::with(landmark::selected)
     }
 
     // produce buffer for display
@@ -195,6 +436,7 @@ mod screen {
         }
     }
 
+    #[allow(dead_code)]
     impl ScreenBuffer {
         pub fn set_pixel(&mut self, x: usize, y: usize, value: bool) {
             assert!(x < WIDTH);
@@ -209,6 +451,10 @@ mod screen {
         pub fn clear(&mut self) {
             self.buf = [0u8; BUF_SIZE];
         }
+
+        pub fn as_bytes(&self) -> &[u8; BUF_SIZE] {
+            &self.buf
+        }
         // TODO
         // line
         // triangle
@@ -228,14 +474,14 @@ mod screen {
         pub fn circle(&mut self, centre: (usize, usize), radius: usize, value: bool) {
             let mut x = 0;
             let mut y = radius;
-            let mut d = 3 - 2 * radius;
+            let mut d = 3 - 2 * radius as isize;
             self.draw_circle(centre, x, y, value);
-            while y <= x {
+            while y >= x {
                 d += if d > 0 {
                     y -= 1;
-                    4 * (x - y) + 10
+                    4 * (x as isize - y as isize) + 10
                 } else {
-                    4 * x + 6
+                    4 * x as isize + 6
                 };
                 x += 1;
                 self.draw_circle(centre, x, y, value);
@@ -0,0 +1,280 @@
+//! World Magnetic Model evaluation.
+//!
+//! Computes the main geomagnetic field from the spherical harmonic coefficients published by
+//! NOAA/NCEI and the BGS. Used to convert between true and magnetic north when the GPS receiver
+//! does not report a declination.
+//!
+//! [`WMM2025`] is evaluated (epoch 2025.0). Outside 2025 to 2030 the secular variation terms are
+//! extrapolated and accuracy slowly degrades, replace the table when a new model is released.
+
+use chrono::{Datelike, NaiveDate};
+use geoconv::{Degrees, Lle, Wgs84};
+use libm::{asin, atan2, cos, sin, sqrt};
+
+/// Maximum degree of the model.
+const DEGREE: usize = 12;
+
+/// Geomagnetic reference radius in km.
+const REFERENCE_RADIUS: f64 = 6371.2;
+
+/// WGS84 semi-major axis in km.
+const WGS84_A: f64 = 6378.137;
+/// WGS84 flattening.
+const WGS84_F: f64 = 1. / 298.257223563;
+
+/// Spherical harmonic coefficients of a model, as published in its `WMM.COF`.
+pub struct Model {
+    /// Decimal year the coefficients are given for.
+    pub epoch: f64,
+    /// (n, m, g, h, ġ, ḣ) with g and h in nT and their secular variation in nT/year.
+    pub coefficients: [(usize, usize, f64, f64, f64, f64); 90],
+}
+
+/// WMM2025, the model [`magnetic_field`] evaluates.
+pub static WMM2025: Model = Model {
+    epoch: 2025.0,
+    coefficients: [
+        (1, 0, -29351.8, 0.0, 12.0, 0.0),
+        (1, 1, -1410.8, 4545.4, 9.7, -21.5),
+        (2, 0, -2556.6, 0.0, -11.6, 0.0),
+        (2, 1, 2951.1, -3133.6, -5.2, -27.7),
+        (2, 2, 1649.3, -815.1, -8.0, -12.1),
+        (3, 0, 1361.0, 0.0, -1.3, 0.0),
+        (3, 1, -2404.1, -56.6, -4.2, 4.0),
+        (3, 2, 1243.8, 237.5, 0.4, -0.3),
+        (3, 3, 453.6, -549.5, -15.6, -4.1),
+        (4, 0, 895.0, 0.0, -1.6, 0.0),
+        (4, 1, 799.5, 278.6, -2.4, -1.1),
+        (4, 2, 55.7, -133.9, -6.0, 4.1),
+        (4, 3, -281.1, 212.0, 5.6, 1.6),
+        (4, 4, 12.1, -375.6, -7.0, -4.4),
+        (5, 0, -233.2, 0.0, 0.6, 0.0),
+        (5, 1, 368.9, 45.4, 1.4, -0.5),
+        (5, 2, 187.2, 220.2, 0.0, 2.2),
+        (5, 3, -138.7, -122.9, 0.6, 0.4),
+        (5, 4, -142.0, 43.0, 2.2, 1.7),
+        (5, 5, 20.9, 106.1, 0.9, 1.9),
+        (6, 0, 64.4, 0.0, -0.2, 0.0),
+        (6, 1, 63.8, -18.4, -0.4, 0.3),
+        (6, 2, 76.9, 16.8, 0.9, -1.6),
+        (6, 3, -115.7, 48.8, 1.2, -0.4),
+        (6, 4, -40.9, -59.8, -0.9, 0.9),
+        (6, 5, 14.9, 10.9, 0.3, 0.7),
+        (6, 6, -60.7, 72.7, 0.9, 0.9),
+        (7, 0, 79.5, 0.0, -0.0, 0.0),
+        (7, 1, -77.0, -48.9, -0.1, 0.6),
+        (7, 2, -8.8, -14.4, -0.1, 0.5),
+        (7, 3, 59.3, -1.0, 0.5, -0.8),
+        (7, 4, 15.8, 23.4, -0.1, 0.0),
+        (7, 5, 2.5, -7.4, -0.8, -1.0),
+        (7, 6, -11.1, -25.1, -0.8, 0.6),
+        (7, 7, 14.2, -2.3, 0.8, -0.2),
+        (8, 0, 23.2, 0.0, -0.1, 0.0),
+        (8, 1, 10.8, 7.1, 0.2, -0.2),
+        (8, 2, -17.5, -12.6, 0.0, 0.5),
+        (8, 3, 2.0, 11.4, 0.5, -0.4),
+        (8, 4, -21.7, -9.7, -0.1, 0.4),
+        (8, 5, 16.9, 12.7, 0.3, -0.5),
+        (8, 6, 15.0, 0.7, 0.2, -0.6),
+        (8, 7, -16.8, -5.2, -0.0, 0.3),
+        (8, 8, 0.9, 3.9, 0.2, 0.2),
+        (9, 0, 4.6, 0.0, -0.0, 0.0),
+        (9, 1, 7.8, -24.8, -0.1, -0.3),
+        (9, 2, 3.0, 12.2, 0.1, 0.3),
+        (9, 3, -0.2, 8.3, 0.3, -0.3),
+        (9, 4, -2.5, -3.4, -0.3, 0.3),
+        (9, 5, -13.1, -5.3, 0.0, 0.2),
+        (9, 6, 2.4, 7.2, 0.3, -0.1),
+        (9, 7, 8.6, -0.6, -0.1, -0.2),
+        (9, 8, -8.7, 0.8, 0.1, 0.4),
+        (9, 9, -12.9, 10.0, -0.1, 0.1),
+        (10, 0, -1.3, 0.0, 0.1, 0.0),
+        (10, 1, -6.4, 3.3, 0.0, 0.0),
+        (10, 2, 0.2, 0.0, 0.1, -0.0),
+        (10, 3, 2.0, 2.4, 0.1, -0.2),
+        (10, 4, -1.0, 5.3, -0.0, 0.1),
+        (10, 5, -0.6, -9.1, -0.3, -0.1),
+        (10, 6, -0.9, 0.4, 0.0, 0.1),
+        (10, 7, 1.5, -4.2, -0.1, 0.0),
+        (10, 8, 0.9, -3.8, -0.1, -0.1),
+        (10, 9, -2.7, 0.9, -0.0, 0.2),
+        (10, 10, -3.9, -9.1, -0.0, -0.0),
+        (11, 0, 2.9, 0.0, 0.0, 0.0),
+        (11, 1, -1.5, 0.0, -0.0, -0.0),
+        (11, 2, -2.5, 2.9, 0.0, 0.1),
+        (11, 3, 2.4, -0.6, 0.0, -0.0),
+        (11, 4, -0.6, 0.2, 0.0, 0.1),
+        (11, 5, -0.1, 0.5, -0.1, -0.0),
+        (11, 6, -0.6, -0.3, 0.0, -0.0),
+        (11, 7, -0.1, -1.2, -0.0, 0.1),
+        (11, 8, 1.1, -1.7, -0.1, -0.0),
+        (11, 9, -1.0, -2.9, -0.1, 0.0),
+        (11, 10, -0.2, -1.8, -0.1, 0.0),
+        (11, 11, 2.6, -2.3, -0.1, 0.0),
+        (12, 0, -2.0, 0.0, 0.0, 0.0),
+        (12, 1, -0.2, -1.3, 0.0, -0.0),
+        (12, 2, 0.3, 0.7, -0.0, 0.0),
+        (12, 3, 1.2, 1.0, -0.0, -0.1),
+        (12, 4, -1.3, -1.4, -0.0, 0.1),
+        (12, 5, 0.6, -0.0, -0.0, -0.0),
+        (12, 6, 0.6, 0.6, 0.1, -0.0),
+        (12, 7, 0.5, -0.1, -0.0, -0.0),
+        (12, 8, -0.1, 0.8, 0.0, 0.0),
+        (12, 9, -0.4, 0.1, 0.0, -0.0),
+        (12, 10, -0.2, -1.0, -0.1, -0.0),
+        (12, 11, -1.3, 0.1, -0.0, 0.0),
+        (12, 12, -0.7, 0.2, -0.1, -0.1),
+    ],
+};
+
+/// Position change in degrees of latitude or longitude before [`Declination`] evaluates the
+/// model again. A few km, over which the declination moves by well under a tenth of a degree
+/// away from the magnetic poles.
+const RECOMPUTE_DEGREES: f64 = 0.05;
+
+/// Date change in years before [`Declination`] evaluates the model again, a couple of weeks.
+const RECOMPUTE_YEARS: f64 = 0.05;
+
+/// Declination from the model, evaluated again only once the position or date have moved
+/// enough to change it, since each evaluation runs through all the coefficients.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Declination {
+    /// Latitude, longitude, decimal year and the declination there in degrees.
+    last: Option<(f64, f64, f64, f64)>,
+}
+
+impl Declination {
+    pub const fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// Declination in degrees, positive east, at `lle` and decimal `year`.
+    pub fn at(&mut self, lle: Lle<Wgs84, Degrees>, year: f64) -> f64 {
+        let (lat, lon) = (lle.latitude.as_float(), lle.longitude.as_float());
+        if let Some((last_lat, last_lon, last_year, declination)) = self.last {
+            if (lat - last_lat).abs() < RECOMPUTE_DEGREES
+                && (lon - last_lon).abs() < RECOMPUTE_DEGREES
+                && (year - last_year).abs() < RECOMPUTE_YEARS
+            {
+                return declination;
+            }
+        }
+
+        let declination = magnetic_field(lle, year).declination();
+        self.last = Some((lat, lon, year, declination));
+        declination
+    }
+}
+
+/// Magnetic field vector in nT, in the local north/east/down frame.
+#[derive(Debug, Clone, Copy)]
+pub struct MagneticField {
+    pub north: f64,
+    pub east: f64,
+    pub down: f64,
+}
+
+impl MagneticField {
+    /// Angle between true north and magnetic north in degrees, positive east.
+    pub fn declination(&self) -> f64 {
+        atan2(self.east, self.north).to_degrees()
+    }
+
+    /// Angle of the field below the horizontal in degrees.
+    pub fn inclination(&self) -> f64 {
+        atan2(self.down, self.horizontal_intensity()).to_degrees()
+    }
+
+    pub fn horizontal_intensity(&self) -> f64 {
+        sqrt(self.north * self.north + self.east * self.east)
+    }
+
+    pub fn total_intensity(&self) -> f64 {
+        sqrt(self.north * self.north + self.east * self.east + self.down * self.down)
+    }
+}
+
+/// Converts a date into the decimal year used by the model.
+pub fn decimal_year(date: NaiveDate) -> f64 {
+    let days = if date.leap_year() { 366. } else { 365. };
+    date.year() as f64 + date.ordinal0() as f64 / days
+}
+
+/// Evaluates [`WMM2025`] at a WGS84 position (elevation above the ellipsoid) and decimal year.
+pub fn magnetic_field(lle: Lle<Wgs84, Degrees>, year: f64) -> MagneticField {
+    WMM2025.magnetic_field(lle, year)
+}
+
+impl Model {
+    /// Evaluates the model at a WGS84 position (elevation above the ellipsoid) and decimal year.
+    pub fn magnetic_field(&self, lle: Lle<Wgs84, Degrees>, year: f64) -> MagneticField {
+        let lat = lle.latitude.as_float().to_radians();
+        let lon = lle.longitude.as_float().to_radians();
+        let height = lle.elevation.as_float() / 1000.;
+
+        // Geodetic to geocentric spherical coordinates
+        let e2 = WGS84_F * (2. - WGS84_F);
+        let rc = WGS84_A / sqrt(1. - e2 * sin(lat) * sin(lat));
+        let p = (rc + height) * cos(lat);
+        let z = (rc * (1. - e2) + height) * sin(lat);
+        let r = sqrt(p * p + z * z);
+        let lat_c = asin(z / r);
+
+        // Schmidt semi-normalised associated Legendre functions of cos(colatitude) and their
+        // derivatives with respect to colatitude
+        let cos_theta = sin(lat_c);
+        // Avoid dividing by zero at the geographic poles
+        let sin_theta = cos(lat_c).max(1e-10);
+        let mut pnm = [[0f64; DEGREE + 1]; DEGREE + 1];
+        let mut dpnm = [[0f64; DEGREE + 1]; DEGREE + 1];
+        pnm[0][0] = 1.;
+        for n in 1..=DEGREE {
+            for m in 0..=n {
+                if n == m {
+                    let k = if n == 1 {
+                        1.
+                    } else {
+                        sqrt((2 * n - 1) as f64 / (2 * n) as f64)
+                    };
+                    pnm[n][n] = k * sin_theta * pnm[n - 1][n - 1];
+                    dpnm[n][n] =
+                        k * (cos_theta * pnm[n - 1][n - 1] + sin_theta * dpnm[n - 1][n - 1]);
+                } else {
+                    let (p2, dp2, k2) = if n >= 2 {
+                        let k2 = sqrt(((n - 1) * (n - 1) - m * m) as f64);
+                        (pnm[n - 2][m], dpnm[n - 2][m], k2)
+                    } else {
+                        (0., 0., 0.)
+                    };
+                    let k = sqrt((n * n - m * m) as f64);
+                    let odd = (2 * n - 1) as f64;
+                    pnm[n][m] = (odd * cos_theta * pnm[n - 1][m] - k2 * p2) / k;
+                    dpnm[n][m] = (odd * (cos_theta * dpnm[n - 1][m] - sin_theta * pnm[n - 1][m])
+                        - k2 * dp2)
+                        / k;
+                }
+            }
+        }
+
+        let dt = year - self.epoch;
+        let (mut north, mut east, mut down) = (0., 0., 0.);
+        for &(n, m, g, h, g_dot, h_dot) in &self.coefficients {
+            let g = g + dt * g_dot;
+            let h = h + dt * h_dot;
+            let ratio = libm::pow(REFERENCE_RADIUS / r, (n + 2) as f64);
+            let (sin_m, cos_m) = (sin(m as f64 * lon), cos(m as f64 * lon));
+
+            north += ratio * (g * cos_m + h * sin_m) * dpnm[n][m];
+            east += ratio * m as f64 * (g * sin_m - h * cos_m) * pnm[n][m] / sin_theta;
+            down -= (n + 1) as f64 * ratio * (g * cos_m + h * sin_m) * pnm[n][m];
+        }
+
+        // Rotate from geocentric back to geodetic
+        let psi = lat_c - lat;
+        MagneticField {
+            north: north * cos(psi) - down * sin(psi),
+            east,
+            down: north * sin(psi) + down * cos(psi),
+        }
+    }
+}
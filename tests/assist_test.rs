@@ -0,0 +1,353 @@
+//! Assistance file upload and read back on a RAM flash, message selection, MGA-INI payloads and
+//! time to first fix.
+
+mod tests {
+    use core::fmt::{self, Write};
+
+    use chrono::NaiveDate;
+    use compass::assist::{
+        is_current, mga_ini_pos_llh, mga_ini_time_utc, Aiding, AssistStore, Error, FirstFix,
+        PositionHint, CHUNK_LEN, CLASS_MGA, ID_MGA_ANO, ID_MGA_INI, MAGIC,
+    };
+    use compass::ubx::{encode, Frame, Framer};
+    use embassy_time::{Duration, Instant};
+    use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash};
+
+    const SECTOR: usize = 128;
+    const SECTORS: usize = 8;
+
+    /// NOR flash in RAM: erasing sets bytes to 0xff and writing can only clear bits.
+    struct RamFlash {
+        data: [u8; SECTOR * SECTORS],
+    }
+
+    impl RamFlash {
+        /// Blank, as if never erased.
+        fn new() -> Self {
+            Self {
+                data: [0; SECTOR * SECTORS],
+            }
+        }
+    }
+
+    impl ErrorType for RamFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for RamFlash {
+        const READ_SIZE: usize = 4;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            if !offset.is_multiple_of(Self::READ_SIZE)
+                || !bytes.len().is_multiple_of(Self::READ_SIZE)
+            {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            let data = self
+                .data
+                .get(offset..offset + bytes.len())
+                .ok_or(NorFlashErrorKind::OutOfBounds)?;
+            bytes.copy_from_slice(data);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    impl NorFlash for RamFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = SECTOR;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            let (from, to) = (from as usize, to as usize);
+            if !from.is_multiple_of(SECTOR) || !to.is_multiple_of(SECTOR) {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            self.data
+                .get_mut(from..to)
+                .ok_or(NorFlashErrorKind::OutOfBounds)?
+                .fill(0xff);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            if !offset.is_multiple_of(Self::WRITE_SIZE)
+                || !bytes.len().is_multiple_of(Self::WRITE_SIZE)
+            {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            let data = self
+                .data
+                .get_mut(offset..offset + bytes.len())
+                .ok_or(NorFlashErrorKind::OutOfBounds)?;
+            for (old, new) in data.iter_mut().zip(bytes) {
+                *old &= new;
+            }
+            Ok(())
+        }
+    }
+
+    /// The store in the second half of the flash.
+    fn store() -> AssistStore<RamFlash> {
+        let half = (SECTOR * SECTORS / 2) as u32;
+        AssistStore::new(RamFlash::new(), half, half)
+    }
+
+    /// MGA-ANO frame length: header, 76 byte payload and checksum.
+    const ANO_LEN: usize = 84;
+
+    /// MGA-ANO for GPS satellite `sv` on 2024-06-`day`.
+    fn ano(sv: u8, day: u8, buf: &mut [u8; ANO_LEN]) -> &[u8] {
+        let mut payload = [0u8; 76];
+        payload[2] = sv;
+        payload[4..7].copy_from_slice(&[24, 6, day]);
+        payload[8] = sv.wrapping_mul(31);
+        encode(CLASS_MGA, ID_MGA_ANO, &payload, buf)
+    }
+
+    /// Five MGA-ANO, three for 2024-06-01 and two for the day after.
+    fn file() -> [u8; 5 * ANO_LEN] {
+        let mut file = [0u8; 5 * ANO_LEN];
+        for (i, (sv, day)) in [(1, 1), (2, 1), (3, 1), (1, 2), (2, 2)]
+            .into_iter()
+            .enumerate()
+        {
+            let mut buf = [0u8; ANO_LEN];
+            file[i * ANO_LEN..(i + 1) * ANO_LEN].copy_from_slice(ano(sv, day, &mut buf));
+        }
+        file
+    }
+
+    /// Reads the stored file back in chunks.
+    fn read_back(store: &mut AssistStore<RamFlash>, out: &mut [u8]) -> usize {
+        let len = store.len().unwrap().unwrap();
+        let mut chunk = [0u8; CHUNK_LEN];
+        for start in (0..len).step_by(CHUNK_LEN) {
+            let bytes = store.read(len, start, &mut chunk).unwrap();
+            out[start as usize..start as usize + bytes.len()].copy_from_slice(bytes);
+        }
+        len as usize
+    }
+
+    #[test]
+    fn uploads_and_reads_back() {
+        let file = file();
+        let mut store = store();
+        assert_eq!(store.len(), Ok(None));
+
+        let mut upload = store.begin(file.len() as u32).unwrap();
+        // Console reads of odd sizes
+        for read in file.chunks(61) {
+            assert_eq!(store.write(&mut upload, read), Ok(read.len()));
+        }
+        // Anything after the file is left alone
+        assert_eq!(store.write(&mut upload, b"gpx\n"), Ok(0));
+        assert!(upload.is_complete());
+        assert_eq!(store.finish(upload), Ok(5));
+
+        assert_eq!(store.len(), Ok(Some(file.len() as u32)));
+        let mut read = [0u8; 5 * ANO_LEN];
+        assert_eq!(read_back(&mut store, &mut read), file.len());
+        assert_eq!(read, file);
+
+        // And frames again
+        let mut framer = Framer::new();
+        let frames = read
+            .iter()
+            .filter(|&&byte| framer.push(byte).is_some())
+            .count();
+        assert_eq!(frames, 5);
+
+        store.clear().unwrap();
+        assert_eq!(store.len(), Ok(None));
+    }
+
+    #[test]
+    fn rejects_bad_uploads() {
+        let file = file();
+        let mut store = store();
+
+        assert_eq!(store.begin(0).err(), Some(Error::TooLarge));
+        assert_eq!(store.begin(512 - 8 + 1).err(), Some(Error::TooLarge));
+
+        // Cut short
+        let mut upload = store.begin(file.len() as u32).unwrap();
+        store.write(&mut upload, &file[..100]).unwrap();
+        assert!(!upload.is_complete());
+        assert_eq!(store.finish(upload), Err(Error::NotUbx));
+        assert_eq!(store.len(), Ok(None));
+
+        // A corrupt message
+        let mut corrupt = file;
+        corrupt[ANO_LEN + 20] ^= 0x01;
+        let mut upload = store.begin(corrupt.len() as u32).unwrap();
+        store.write(&mut upload, &corrupt).unwrap();
+        assert_eq!(store.finish(upload), Err(Error::NotUbx));
+        assert_eq!(store.len(), Ok(None));
+
+        // Not UBX at all
+        let text = b"AssistNow offline data, honest";
+        let mut upload = store.begin(text.len() as u32).unwrap();
+        store.write(&mut upload, text).unwrap();
+        assert_eq!(store.finish(upload), Err(Error::NotUbx));
+        assert_eq!(store.len(), Ok(None));
+    }
+
+    #[test]
+    fn an_upload_replaces_the_file() {
+        let file = file();
+        let mut store = store();
+        let mut upload = store.begin(file.len() as u32).unwrap();
+        store.write(&mut upload, &file).unwrap();
+        store.finish(upload).unwrap();
+
+        let shorter = &file[..2 * ANO_LEN];
+        let mut upload = store.begin(shorter.len() as u32).unwrap();
+        // Gone as soon as the upload starts
+        assert_eq!(store.len(), Ok(None));
+        store.write(&mut upload, shorter).unwrap();
+        assert_eq!(store.finish(upload), Ok(2));
+
+        let mut read = [0u8; 5 * ANO_LEN];
+        assert_eq!(read_back(&mut store, &mut read), shorter.len());
+        assert_eq!(&read[..shorter.len()], shorter);
+
+        let mut flash = store.release();
+        let mut header = [0u8; 8];
+        flash.read(512, &mut header).unwrap();
+        assert_eq!(header[..4], MAGIC);
+    }
+
+    /// The frame made of `bytes`.
+    fn framed<'f>(framer: &'f mut Framer, bytes: &[u8]) -> Frame<'f> {
+        let (last, bytes) = bytes.split_last().unwrap();
+        for &byte in bytes {
+            assert!(framer.push(byte).is_none());
+        }
+        framer.push(*last).unwrap()
+    }
+
+    #[test]
+    fn selects_todays_messages() {
+        let today = NaiveDate::from_ymd_opt(2024, 6, 1);
+        let mut framer = Framer::new();
+
+        let mut buf = [0u8; ANO_LEN];
+        let frame = framed(&mut framer, ano(5, 1, &mut buf));
+        assert!(is_current(&frame, today));
+        assert!(!is_current(&frame, NaiveDate::from_ymd_opt(2024, 6, 2)));
+        // Without a date everything goes
+        assert!(is_current(&frame, None));
+
+        let mut buf = [0u8; 28];
+        let hint = PositionHint {
+            latitude: 0,
+            longitude: 0,
+            height: 0,
+        };
+        let ini = encode(CLASS_MGA, ID_MGA_INI, &mga_ini_pos_llh(&hint, 1), &mut buf);
+        let frame = framed(&mut framer, ini);
+        assert!(is_current(&frame, today));
+    }
+
+    #[test]
+    fn mga_ini_payloads() {
+        let hint = PositionHint {
+            latitude: 515_000_000,
+            longitude: -1_200_000,
+            height: 4_567,
+        };
+        assert_eq!(
+            mga_ini_pos_llh(&hint, 10_000_000),
+            [
+                0x01, 0x00, 0x00, 0x00, // type, version, reserved
+                0xc0, 0x46, 0xb2, 0x1e, // latitude
+                0x80, 0xb0, 0xed, 0xff, // longitude
+                0xd7, 0x11, 0x00, 0x00, // height
+                0x80, 0x96, 0x98, 0x00, // accuracy
+            ]
+        );
+
+        let utc = NaiveDate::from_ymd_opt(2024, 6, 1)
+            .unwrap()
+            .and_hms_milli_opt(12, 34, 56, 250)
+            .unwrap();
+        assert_eq!(
+            mga_ini_time_utc(utc, Duration::from_millis(10_500)),
+            [
+                0x10, 0x00, 0x00, 0x80, // type, version, reference, leap seconds unknown
+                0xe8, 0x07, 0x06, 0x01, // 2024-06-01
+                0x0c, 0x22, 0x38, 0x00, // 12:34:56
+                0x80, 0xb2, 0xe6, 0x0e, // 250 000 000 ns
+                0x0a, 0x00, 0x00, 0x00, // 10 s
+                0x00, 0x65, 0xcd, 0x1d, // 500 000 000 ns
+            ]
+        );
+    }
+
+    /// Text written with `Display`.
+    struct Text {
+        buf: [u8; 96],
+        len: usize,
+    }
+
+    impl Text {
+        fn of(value: &impl fmt::Display) -> Self {
+            let mut text = Self {
+                buf: [0; 96],
+                len: 0,
+            };
+            write!(text, "{value}").unwrap();
+            text
+        }
+
+        fn as_str(&self) -> &str {
+            core::str::from_utf8(&self.buf[..self.len]).unwrap()
+        }
+    }
+
+    impl Write for Text {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.buf
+                .get_mut(self.len..end)
+                .ok_or(fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn times_the_first_fix() {
+        let start = Instant::from_secs(2);
+        let mut first_fix = FirstFix::new();
+        // Not started
+        assert_eq!(first_fix.fix(start), None);
+
+        first_fix.start(start);
+        first_fix.aiding = Aiding {
+            time: true,
+            position: false,
+            messages: 32,
+        };
+        assert_eq!(
+            Text::of(&first_fix).as_str(),
+            "No fix yet, aided with time yes position no messages 32"
+        );
+
+        let at = start + Duration::from_millis(12_345);
+        assert_eq!(first_fix.fix(at), Some(Duration::from_millis(12_345)));
+        // Only the first counts
+        assert_eq!(first_fix.fix(at + Duration::from_secs(1)), None);
+        assert_eq!(first_fix.time_to_fix, Some(Duration::from_millis(12_345)));
+        assert_eq!(
+            Text::of(&first_fix).as_str(),
+            "First fix after 12.3 s, aided with time yes position no messages 32"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Magnetometer calibration fitted against synthetic distorted spheres.
+
+mod tests {
+    use compass::calibration::{
+        calibration_samples, Calibration, Calibrator, Error, MIN_DRIFT_SPAN, MIN_SAMPLES,
+    };
+    use core::f64::consts::PI;
+
+    /// Field strength in µT.
+    const RADIUS: f64 = 50.0;
+    const OFFSET: (f64, f64, f64) = (7.0, -20.8, 5.2);
+    /// QMC5883L sensitivity at ± 2 gauss.
+    const LSB_PER_MICRO_TESLA: f64 = 120.0;
+    const SOFT_IRON: [[f64; 3]; 3] = [[1.25, 0.08, -0.05], [0.08, 0.85, 0.12], [-0.05, 0.12, 1.05]];
+
+    /// Evenly spread points on a sphere (Fibonacci lattice), distorted by a soft-iron matrix and
+    /// shifted by a hard-iron offset. Readings are rounded to the 2 gauss resolution.
+    fn distorted_sphere(n: usize) -> impl Iterator<Item = (f32, f32, f32)> {
+        let golden_angle = PI * (3. - libm::sqrt(5.));
+        (0..n).map(move |i| {
+            let z = 1. - 2. * (i as f64 + 0.5) / n as f64;
+            let r = libm::sqrt(1. - z * z);
+            let theta = golden_angle * i as f64;
+            let p = [r * libm::cos(theta), r * libm::sin(theta), z];
+            let d = |row: [f64; 3]| RADIUS * (row[0] * p[0] + row[1] * p[1] + row[2] * p[2]);
+            let quantize =
+                |v: f64| (libm::round(v * LSB_PER_MICRO_TESLA) / LSB_PER_MICRO_TESLA) as f32;
+            (
+                quantize(d(SOFT_IRON[0]) + OFFSET.0),
+                quantize(d(SOFT_IRON[1]) + OFFSET.1),
+                quantize(d(SOFT_IRON[2]) + OFFSET.2),
+            )
+        })
+    }
+
+    fn norm(v: (f32, f32, f32)) -> f32 {
+        libm::sqrtf(v.0 * v.0 + v.1 * v.1 + v.2 * v.2)
+    }
+
+    #[test]
+    fn recovers_hard_iron_offset() {
+        let mut calibrator = Calibrator::new();
+        distorted_sphere(400).for_each(|s| calibrator.add_sample(s, None));
+        let calibration = calibrator.fit().unwrap();
+
+        assert!((calibration.offset.0 - OFFSET.0 as f32).abs() < 0.1);
+        assert!((calibration.offset.1 - OFFSET.1 as f32).abs() < 0.1);
+        assert!((calibration.offset.2 - OFFSET.2 as f32).abs() < 0.1);
+    }
+
+    #[test]
+    fn window_lasts_as_long_at_any_rate() {
+        assert_eq!(calibration_samples(10), 300);
+        assert_eq!(calibration_samples(50), 5 * calibration_samples(10));
+        assert_eq!(calibration_samples(200), 20 * calibration_samples(10));
+    }
+
+    #[test]
+    fn fits_a_window_at_the_highest_rate() {
+        let mut calibrator = Calibrator::new();
+        distorted_sphere(calibration_samples(200)).for_each(|s| calibrator.add_sample(s, None));
+        let calibration = calibrator.fit().unwrap();
+
+        assert!((calibration.offset.0 - OFFSET.0 as f32).abs() < 0.1);
+        assert!((calibration.offset.1 - OFFSET.1 as f32).abs() < 0.1);
+        assert!((calibration.offset.2 - OFFSET.2 as f32).abs() < 0.1);
+    }
+
+    #[test]
+    fn corrected_samples_lie_on_sphere() {
+        let mut calibrator = Calibrator::new();
+        distorted_sphere(400).for_each(|s| calibrator.add_sample(s, None));
+        let calibration = calibrator.fit().unwrap();
+
+        let expected = norm(calibration.apply(distorted_sphere(1).next().unwrap(), None));
+        for sample in distorted_sphere(400) {
+            let radius = norm(calibration.apply(sample, None));
+            assert!((radius - expected).abs() / expected < 0.005);
+        }
+    }
+
+    #[test]
+    fn soft_iron_matrix_is_symmetric() {
+        let mut calibrator = Calibrator::new();
+        distorted_sphere(400).for_each(|s| calibrator.add_sample(s, None));
+        let m = calibrator.fit().unwrap().soft_iron;
+
+        assert!((m[0][1] - m[1][0]).abs() < 1e-4);
+        assert!((m[0][2] - m[2][0]).abs() < 1e-4);
+        assert!((m[1][2] - m[2][1]).abs() < 1e-4);
+    }
+
+    #[test]
+    fn too_few_samples() {
+        let mut calibrator = Calibrator::new();
+        distorted_sphere(MIN_SAMPLES - 1).for_each(|s| calibrator.add_sample(s, None));
+        assert_eq!(
+            calibrator.fit(),
+            Err(Error::NotEnoughSamples(MIN_SAMPLES - 1))
+        );
+    }
+
+    #[test]
+    fn flat_rotation_is_degenerate() {
+        // Only spinning the device on a table does not reveal the z axis.
+        let mut calibrator = Calibrator::new();
+        for i in 0..200 {
+            let theta = 2. * PI * i as f64 / 200.;
+            calibrator.add_sample(
+                (
+                    (RADIUS * libm::cos(theta)) as f32,
+                    (RADIUS * libm::sin(theta)) as f32,
+                    8.,
+                ),
+                None,
+            );
+        }
+        assert_eq!(calibrator.fit(), Err(Error::Degenerate));
+    }
+
+    #[test]
+    fn fit_records_mean_temperature() {
+        let mut calibrator = Calibrator::new();
+        distorted_sphere(400)
+            .enumerate()
+            .for_each(|(i, s)| calibrator.add_sample(s, Some(20. + (i % 2) as f32)));
+        assert_eq!(calibrator.fit().unwrap().temp, Some(20.5));
+    }
+
+    /// Calibration fitted at `temp` °C with the offset shifted by `shift` µT.
+    fn calibration_at(temp: f32, shift: f32) -> Calibration {
+        Calibration {
+            offset: (7. + shift, -20.8 - shift, 5.2),
+            temp: Some(temp),
+            ..Calibration::new()
+        }
+    }
+
+    #[test]
+    fn drift_learned_from_successive_calibrations() {
+        let calibration = calibration_at(35., 3.).with_drift_from(&calibration_at(20., 0.));
+        assert!((calibration.drift.0 - 0.2).abs() < 1e-6);
+        assert!((calibration.drift.1 + 0.2).abs() < 1e-6);
+        assert_eq!(calibration.drift.2, 0.);
+
+        // Back at the first temperature the first offset applies
+        let offset = calibration.offset_at(Some(20.));
+        assert!((offset.0 - 7.).abs() < 1e-4);
+        assert!((offset.1 + 20.8).abs() < 1e-4);
+        assert_eq!(calibration.offset_at(None), calibration.offset);
+    }
+
+    #[test]
+    fn drift_needs_a_temperature_span() {
+        let previous = Calibration {
+            drift: (0.1, 0., 0.),
+            ..calibration_at(20., 0.)
+        };
+        let calibration = calibration_at(20. + MIN_DRIFT_SPAN / 2., 3.).with_drift_from(&previous);
+        assert_eq!(calibration.drift, (0.1, 0., 0.));
+
+        let calibration = Calibration {
+            temp: None,
+            ..calibration_at(35., 3.)
+        }
+        .with_drift_from(&previous);
+        assert_eq!(calibration.drift, (0.1, 0., 0.));
+    }
+
+    #[test]
+    fn apply_corrects_drift() {
+        let calibration = Calibration {
+            drift: (0.5, 0., 0.),
+            ..calibration_at(20., 0.)
+        };
+        // 10 °C warmer the offset has moved 5 µT along x
+        let corrected = calibration.apply((37., -20.8, 5.2), Some(30.));
+        assert!((corrected.0 - 25.).abs() < 1e-4);
+        assert!(corrected.1.abs() < 1e-4);
+        assert!(corrected.2.abs() < 1e-4);
+    }
+}
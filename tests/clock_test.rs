@@ -0,0 +1,144 @@
+//! Latching and free running of the GNSS-disciplined clock.
+
+mod tests {
+    use chrono::{NaiveDate, NaiveDateTime};
+    use compass::clock::{Clock, Saved, Source, PULSE_WINDOW};
+    use embassy_time::{Duration, Instant};
+
+    const RECEIVED: Instant = Instant::from_secs(100);
+
+    fn utc(sec: u32, milli: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 12, 31)
+            .unwrap()
+            .and_hms_milli_opt(23, 59, sec, milli)
+            .unwrap()
+    }
+
+    #[test]
+    fn unset_until_latched() {
+        assert_eq!(Clock::new().now_at(RECEIVED), None);
+        assert_eq!(Clock::new().last_latch(), None);
+    }
+
+    #[test]
+    fn free_runs_from_the_message() {
+        let mut clock = Clock::new();
+        clock.latch(utc(58, 0), RECEIVED);
+        assert_eq!(clock.last_latch().unwrap().source, Source::Message);
+        assert_eq!(clock.now_at(RECEIVED), Some(utc(58, 0)));
+        assert_eq!(
+            clock.now_at(RECEIVED + Duration::from_millis(1500)),
+            Some(utc(59, 500))
+        );
+
+        // Long after the fix was lost, into the new year
+        let later = clock.now_at(RECEIVED + Duration::from_secs(3600)).unwrap();
+        assert_eq!(
+            later,
+            NaiveDate::from_ymd_opt(2025, 1, 1)
+                .unwrap()
+                .and_hms_opt(0, 59, 58)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn anchors_to_the_timepulse() {
+        let pulse = RECEIVED - Duration::from_millis(120);
+        let mut clock = Clock::new();
+        clock.pulse(pulse);
+        // The epoch time is a few ns off the second
+        clock.latch(utc(57, 0) + chrono::TimeDelta::nanoseconds(-30), RECEIVED);
+
+        let latch = clock.last_latch().unwrap();
+        assert_eq!(latch.source, Source::Pulse);
+        assert_eq!(latch.at, pulse);
+        assert_eq!(latch.utc, utc(57, 0));
+        assert_eq!(clock.now_at(RECEIVED), Some(utc(57, 120)));
+    }
+
+    #[test]
+    fn ignores_old_and_used_pulses() {
+        let mut clock = Clock::new();
+        clock.pulse(RECEIVED - PULSE_WINDOW - Duration::from_millis(1));
+        clock.latch(utc(57, 0), RECEIVED);
+        assert_eq!(clock.last_latch().unwrap().source, Source::Message);
+
+        // Each edge is matched to one epoch only
+        clock.pulse(RECEIVED);
+        clock.latch(utc(57, 0), RECEIVED);
+        assert_eq!(clock.last_latch().unwrap().source, Source::Pulse);
+        clock.latch(utc(58, 0), RECEIVED + Duration::from_secs(1));
+        assert_eq!(clock.last_latch().unwrap().source, Source::Message);
+
+        // An edge after the message belongs to the next epoch
+        clock.pulse(RECEIVED + Duration::from_secs(3));
+        clock.latch(utc(59, 0), RECEIVED + Duration::from_secs(2));
+        assert_eq!(clock.last_latch().unwrap().source, Source::Message);
+    }
+
+    #[test]
+    fn clock_behind_the_latch() {
+        let mut clock = Clock::new();
+        clock.latch(utc(58, 0), RECEIVED);
+        assert_eq!(
+            clock.now_at(RECEIVED - Duration::from_millis(250)),
+            Some(utc(57, 750))
+        );
+    }
+
+    #[test]
+    fn restores_through_the_rtc() {
+        let mut clock = Clock::new();
+        clock.latch(utc(58, 0), RECEIVED);
+
+        // Saved before deep sleep, half a second after the fix
+        let words = Saved {
+            utc: clock.now_at(RECEIVED + Duration::from_millis(500)).unwrap(),
+            rtc_us: 7_000_000,
+        }
+        .to_words();
+
+        // Woken 90 s later by the RTC, with the system timer started over
+        let saved = Saved::from_words(words).unwrap();
+        let woken = saved.utc_at(97_000_000).unwrap();
+        let mut clock = Clock::new();
+        let booted = Instant::from_millis(20);
+        clock.restore(woken, booted);
+
+        let latch = clock.last_latch().unwrap();
+        assert_eq!(latch.source, Source::Rtc);
+        assert_eq!(
+            clock.now_at(booted + Duration::from_secs(2)),
+            Some(
+                NaiveDate::from_ymd_opt(2025, 1, 1)
+                    .unwrap()
+                    .and_hms_milli_opt(0, 1, 30, 500)
+                    .unwrap()
+            )
+        );
+
+        // A fix that came first wins
+        let mut clock = Clock::new();
+        clock.latch(utc(59, 0), booted);
+        clock.restore(woken, booted);
+        assert_eq!(clock.last_latch().unwrap().source, Source::Message);
+    }
+
+    #[test]
+    fn rejects_a_reset_rtc() {
+        let saved = Saved {
+            utc: utc(58, 0),
+            rtc_us: 7_000_000,
+        };
+        // The RTC timer starts over at power on
+        assert_eq!(saved.utc_at(6_999_999), None);
+
+        // RTC memory after power on
+        assert_eq!(Saved::from_words([0; 3]), None);
+        assert_eq!(Saved::from_words([u64::MAX; 3]), None);
+        let mut words = saved.to_words();
+        words[0] ^= 1;
+        assert_eq!(Saved::from_words(words), None);
+    }
+}
@@ -0,0 +1,82 @@
+//! Mock I2C bus shared by the driver tests.
+
+// Each test binary only uses part of it
+#![allow(dead_code)]
+
+use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+
+/// Bus transaction the mock expects next, starting with the address.
+#[derive(Clone, Copy)]
+pub enum Transaction {
+    Write(u8, &'static [u8]),
+    WriteRead(u8, &'static [u8], &'static [u8]),
+    /// Fail the next transaction with a bus error.
+    Fail(u8),
+    /// Nothing answers at the address.
+    Nack(u8),
+}
+
+pub struct MockBus {
+    expected: &'static [Transaction],
+    index: usize,
+}
+
+impl MockBus {
+    pub fn new(expected: &'static [Transaction]) -> Self {
+        Self { expected, index: 0 }
+    }
+
+    pub fn done(&self) {
+        assert_eq!(self.index, self.expected.len(), "missing transactions");
+    }
+}
+
+impl ErrorType for MockBus {
+    type Error = ErrorKind;
+}
+
+impl I2c for MockBus {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let expected = self.expected[self.index];
+        self.index += 1;
+
+        match (expected, operations) {
+            (Transaction::Write(a, expected), [Operation::Write(write)]) => {
+                assert_eq!(a, address);
+                assert_eq!(expected, *write);
+            }
+            (
+                Transaction::WriteRead(a, expected, read),
+                [Operation::Write(write), Operation::Read(buf)],
+            ) => {
+                assert_eq!(a, address);
+                assert_eq!(expected, *write);
+                buf.copy_from_slice(read);
+            }
+            (Transaction::Fail(a), _) => {
+                assert_eq!(a, address);
+                return Err(ErrorKind::Other);
+            }
+            (Transaction::Nack(a), _) => {
+                assert_eq!(a, address);
+                return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+            }
+            _ => panic!("unexpected transaction"),
+        }
+        Ok(())
+    }
+}
+
+impl embedded_hal_async::i2c::I2c for MockBus {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        I2c::transaction(self, address, operations)
+    }
+}
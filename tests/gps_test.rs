@@ -0,0 +1,218 @@
+//! Fix quality of the navigation state, and its date and time from NAV-PVT payloads with each
+//! combination of validity flags.
+
+mod tests {
+    use chrono::{NaiveDate, NaiveDateTime};
+    use compass::receiver::{
+        FixQuality, NavPvtState, FULLY_RESOLVED, GOOD_HORIZONTAL_ACCURACY, STALE_AFTER, VALID_DATE,
+        VALID_TIME,
+    };
+    use compass::ubx::checksum;
+    use embassy_time::{Duration, Instant};
+    use geoconv::{Degrees, Lle, Meters};
+    use ublox::{FixedLinearBuffer, GnssFixType, NavPvtFlags, PacketRef, Parser};
+
+    const RECEIVED: Instant = Instant::from_secs(100);
+
+    /// A 3D fix received at [`RECEIVED`] with `horizontal_accuracy` in metres.
+    fn fix(horizontal_accuracy: f64) -> NavPvtState {
+        NavPvtState {
+            lle: Some(Lle::new(
+                Degrees::new(51.5),
+                Degrees::new(-0.12),
+                Meters::new(45.),
+            )),
+            position_fix_type: GnssFixType::Fix3D,
+            flags: NavPvtFlags::GPS_FIX_OK.bits(),
+            horizontal_accuracy,
+            vertical_accuracy: 2. * horizontal_accuracy,
+            received: Some(RECEIVED),
+            ..NavPvtState::new()
+        }
+    }
+
+    #[test]
+    fn nothing_received() {
+        assert_eq!(
+            NavPvtState::new().fix_quality_at(RECEIVED),
+            FixQuality::None
+        );
+    }
+
+    #[test]
+    fn grades_fixes() {
+        assert_eq!(fix(3.).fix_quality_at(RECEIVED), FixQuality::Good);
+        assert_eq!(
+            fix(GOOD_HORIZONTAL_ACCURACY).fix_quality_at(RECEIVED),
+            FixQuality::Good
+        );
+        assert_eq!(fix(25.).fix_quality_at(RECEIVED), FixQuality::Fix3D);
+        // NMEA receivers give no accuracy estimate
+        assert_eq!(fix(f64::NAN).fix_quality_at(RECEIVED), FixQuality::Fix3D);
+
+        let state = NavPvtState {
+            position_fix_type: GnssFixType::Fix2D,
+            ..fix(3.)
+        };
+        assert_eq!(state.fix_quality_at(RECEIVED), FixQuality::Fix2D);
+    }
+
+    #[test]
+    fn refuses_untrusted_fixes() {
+        let state = NavPvtState {
+            flags: 0,
+            ..fix(3.)
+        };
+        assert_eq!(state.fix_quality_at(RECEIVED), FixQuality::None);
+
+        let state = NavPvtState {
+            position_fix_type: GnssFixType::TimeOnlyFix,
+            ..fix(3.)
+        };
+        assert_eq!(state.fix_quality_at(RECEIVED), FixQuality::None);
+
+        let state = NavPvtState {
+            lle: None,
+            ..fix(3.)
+        };
+        assert_eq!(state.fix_quality_at(RECEIVED), FixQuality::None);
+    }
+
+    #[test]
+    fn goes_stale() {
+        let state = fix(3.);
+        assert_eq!(
+            state.fix_quality_at(RECEIVED + STALE_AFTER),
+            FixQuality::Good
+        );
+        assert_eq!(
+            state.fix_quality_at(RECEIVED + STALE_AFTER + Duration::from_millis(1)),
+            FixQuality::Stale
+        );
+        // A clock behind the receive time is not stale
+        assert_eq!(
+            state.fix_quality_at(Instant::from_secs(50)),
+            FixQuality::Good
+        );
+    }
+
+    #[test]
+    fn only_2d_and_better_navigate() {
+        assert!(!FixQuality::None.can_navigate());
+        assert!(!FixQuality::Stale.can_navigate());
+        assert!(FixQuality::Fix2D.can_navigate());
+        assert!(FixQuality::Fix3D.can_navigate());
+        assert!(FixQuality::Good.can_navigate());
+    }
+
+    const NAV_PVT_LEN: usize = 92;
+
+    /// NAV-PVT for 2024-03-09 23:59:58 plus `nanosecond`, 3D fix at 51.5° N 0.12° W.
+    fn nav_pvt(itow: u32, valid: u8, nanosecond: i32) -> [u8; NAV_PVT_LEN] {
+        let mut payload = [0u8; NAV_PVT_LEN];
+        payload[0..4].copy_from_slice(&itow.to_le_bytes());
+        payload[4..6].copy_from_slice(&2024u16.to_le_bytes());
+        payload[6..11].copy_from_slice(&[3, 9, 23, 59, 58]);
+        payload[11] = valid;
+        // tAcc in ns
+        payload[12..16].copy_from_slice(&25u32.to_le_bytes());
+        payload[16..20].copy_from_slice(&nanosecond.to_le_bytes());
+        // 3D fix, gnssFixOK
+        payload[20] = 3;
+        payload[21] = 0x01;
+        payload[23] = 9;
+        payload[24..28].copy_from_slice(&(-1_200_000i32).to_le_bytes());
+        payload[28..32].copy_from_slice(&515_000_000i32.to_le_bytes());
+        // hAcc and vAcc in mm
+        payload[40..44].copy_from_slice(&3_000u32.to_le_bytes());
+        payload[44..48].copy_from_slice(&5_000u32.to_le_bytes());
+        payload
+    }
+
+    /// Frames `payload` as NAV-PVT and decodes it.
+    fn decode(payload: &[u8; NAV_PVT_LEN]) -> NavPvtState {
+        let mut frame = [0u8; NAV_PVT_LEN + 8];
+        frame[..6].copy_from_slice(&[0xb5, 0x62, 0x01, 0x07, NAV_PVT_LEN as u8, 0]);
+        frame[6..6 + NAV_PVT_LEN].copy_from_slice(payload);
+        let (ck_a, ck_b) = checksum(&frame[2..6 + NAV_PVT_LEN]);
+        frame[6 + NAV_PVT_LEN] = ck_a;
+        frame[7 + NAV_PVT_LEN] = ck_b;
+
+        let mut buf = [0u8; NAV_PVT_LEN + 8];
+        let mut parser = Parser::new(FixedLinearBuffer::new(&mut buf));
+        let mut packets = parser.consume_ubx(&frame);
+        match packets.next() {
+            Some(Ok(PacketRef::NavPvt(pkg))) => NavPvtState::from_nav_pvt(&pkg),
+            _ => panic!("not a NAV-PVT"),
+        }
+    }
+
+    fn expected(nanosecond: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, 9)
+            .unwrap()
+            .and_hms_nano_opt(23, 59, 58, nanosecond)
+            .unwrap()
+    }
+
+    #[test]
+    fn date_time_needs_every_validity_flag() {
+        let all = VALID_DATE | VALID_TIME | FULLY_RESOLVED;
+        for valid in 0..=all {
+            let state = decode(&nav_pvt(0, valid, 0));
+            let date_time = state.date_time();
+            if valid == all {
+                assert_eq!(date_time, Some(expected(0)));
+            } else {
+                assert_eq!(date_time, None);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_only_valid_fields() {
+        let state = decode(&nav_pvt(0, VALID_DATE, 0));
+        assert_eq!((state.year, state.month, state.day), (2024, 3, 9));
+        assert_eq!((state.hour, state.min, state.sec), (0, 0, 0));
+        assert_eq!(state.time_accuracy, 0);
+
+        let state = decode(&nav_pvt(0, VALID_TIME, 500));
+        assert_eq!((state.year, state.month, state.day), (0, 0, 0));
+        assert_eq!((state.hour, state.min, state.sec), (23, 59, 58));
+        assert_eq!(state.nanosecond, 500);
+        assert_eq!(state.time_accuracy, 25);
+
+        // The position does not depend on the time
+        assert!(state.lle.is_some());
+        assert_eq!(state.fix_quality_at(RECEIVED), FixQuality::None);
+        let state = NavPvtState {
+            received: Some(RECEIVED),
+            ..state
+        };
+        assert_eq!(state.fix_quality_at(RECEIVED), FixQuality::Good);
+    }
+
+    #[test]
+    fn adds_the_fraction_of_a_second() {
+        let all = VALID_DATE | VALID_TIME | FULLY_RESOLVED;
+        let state = decode(&nav_pvt(0, all, 250_000_000));
+        assert_eq!(state.date_time(), Some(expected(250_000_000)));
+
+        // The receiver rounds to the nearest second, so the fraction can be negative
+        let state = decode(&nav_pvt(0, all, -200_000_000));
+        assert_eq!(
+            state.date_time(),
+            Some(
+                NaiveDate::from_ymd_opt(2024, 3, 9)
+                    .unwrap()
+                    .and_hms_milli_opt(23, 59, 57, 800)
+                    .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn keeps_the_milliseconds_of_itow() {
+        let state = decode(&nav_pvt(345_600_250, 0, 0));
+        assert_eq!(state.time_tag, 345_600.25);
+    }
+}
@@ -0,0 +1,143 @@
+//! Heading and navigation directions for known coordinates and field vectors.
+
+mod tests {
+    use compass::heading::{
+        heading, magnetic_to_true, nav_compass_state, pitch_roll, tilt_compensated_heading,
+        true_to_magnetic,
+    };
+    use compass::landmark::Landmark;
+    use core::f32::consts::{FRAC_PI_2, PI};
+    use geoconv::{Degrees, Lle, Meters};
+
+    /// Allow for f32 rounding.
+    const TOLERANCE: f32 = 1e-4;
+
+    fn assert_angle(actual: f32, expected: f32) {
+        let diff = (actual - expected).rem_euclid(2. * PI);
+        assert!(diff.min(2. * PI - diff) < TOLERANCE);
+    }
+
+    fn landmark(lat: f64, lon: f64) -> Landmark {
+        Landmark {
+            name: "Target",
+            lle: Lle::new(Degrees::new(lat), Degrees::new(lon), Meters::new(0.)),
+        }
+    }
+
+    #[test]
+    fn heading_cardinal_points() {
+        assert_angle(heading((30., 0., 40.)), 0.);
+        assert_angle(heading((0., -30., 40.)), FRAC_PI_2);
+        assert_angle(heading((-30., 0., 40.)), PI);
+        assert_angle(heading((0., 30., 40.)), 3. * FRAC_PI_2);
+    }
+
+    #[test]
+    fn heading_ignores_vertical_component() {
+        assert_angle(heading((20., -20., -50.)), heading((20., -20., 50.)));
+    }
+
+    #[test]
+    fn target_due_north_facing_north() {
+        let position = (Degrees::new(-41.28664), Degrees::new(174.77557));
+        let target = landmark(-40., 174.77557);
+        let state = nav_compass_state(heading((25., 0., -40.)), None, 0., position, &target);
+
+        assert_angle(state.north_dir, 0.);
+        assert_angle(state.target_dir, 0.);
+        assert_angle(state.screen_offset, 0.);
+    }
+
+    #[test]
+    fn target_due_north_facing_east() {
+        let position = (Degrees::new(-41.28664), Degrees::new(174.77557));
+        let target = landmark(-40., 174.77557);
+        let state = nav_compass_state(heading((0., -25., -40.)), None, 0., position, &target);
+
+        assert_angle(state.north_dir, 3. * FRAC_PI_2);
+        assert_angle(state.target_dir, 3. * FRAC_PI_2);
+        assert_angle(state.screen_offset, FRAC_PI_2);
+    }
+
+    #[test]
+    fn target_due_east_facing_south() {
+        let position = (Degrees::new(0.), Degrees::new(0.));
+        let state = nav_compass_state(
+            heading((-25., 0., 0.)),
+            Some(21.),
+            0.,
+            position,
+            &landmark(0., 1.),
+        );
+
+        assert_eq!(state.temp, Some(21.));
+        assert_angle(state.north_dir, PI);
+        assert_angle(state.target_dir, 3. * FRAC_PI_2);
+    }
+
+    #[test]
+    fn declination_conversion() {
+        let declination = 22f32.to_radians();
+        assert_angle(magnetic_to_true(0., declination), declination);
+        assert_angle(true_to_magnetic(0., declination), 2. * PI - declination);
+        assert_angle(
+            true_to_magnetic(magnetic_to_true(1., declination), declination),
+            1.,
+        );
+    }
+
+    #[test]
+    fn east_declination_shifts_target() {
+        // Facing magnetic north with 22° east declination means facing 22° true, so a target due
+        // north is 22° to the left.
+        let declination = 22f32.to_radians();
+        let position = (Degrees::new(-41.28664), Degrees::new(174.77557));
+        let target = landmark(-40., 174.77557);
+        let state = nav_compass_state(0., None, declination, position, &target);
+
+        assert_angle(state.north_dir, 0.);
+        assert_angle(state.target_dir, 2. * PI - declination);
+        assert_angle(state.screen_offset, declination);
+    }
+
+    /// Rotates a north/east/down vector into device axes for a given heading, pitch and roll.
+    fn to_device(v: (f32, f32, f32), heading: f32, pitch: f32, roll: f32) -> (f32, f32, f32) {
+        let (sh, ch) = (libm::sinf(heading), libm::cosf(heading));
+        let (sp, cp) = (libm::sinf(pitch), libm::cosf(pitch));
+        let (sr, cr) = (libm::sinf(roll), libm::cosf(roll));
+        let v = (ch * v.0 + sh * v.1, -sh * v.0 + ch * v.1, v.2);
+        let v = (cp * v.0 - sp * v.2, v.1, sp * v.0 + cp * v.2);
+        (v.0, cr * v.1 + sr * v.2, -sr * v.1 + cr * v.2)
+    }
+
+    /// Field with a strong vertical component, as in New Zealand.
+    const FIELD: (f32, f32, f32) = (22., 5., -50.);
+    /// Accelerometer reading of a device at rest.
+    const GRAVITY: (f32, f32, f32) = (0., 0., -1.);
+
+    #[test]
+    fn pitch_and_roll() {
+        let (pitch, roll) = pitch_roll(to_device(GRAVITY, 1., 0.3, -0.5));
+        assert!((pitch - 0.3).abs() < TOLERANCE);
+        assert!((roll + 0.5).abs() < TOLERANCE);
+    }
+
+    #[test]
+    fn level_matches_uncompensated() {
+        let mag = to_device(FIELD, 2., 0., 0.);
+        let accel = to_device(GRAVITY, 2., 0., 0.);
+        assert_angle(tilt_compensated_heading(mag, accel), heading(mag));
+    }
+
+    #[test]
+    fn tilted_heading() {
+        let north = heading(FIELD);
+        for (pitch, roll) in [(0.4, 0.), (0., -0.6), (-0.3, 0.5), (1.2, 0.9)] {
+            for true_heading in [0., 1., 2.5, 4., 5.5] {
+                let mag = to_device(FIELD, true_heading, pitch, roll);
+                let accel = to_device(GRAVITY, true_heading, pitch, roll);
+                assert_angle(tilt_compensated_heading(mag, accel), true_heading + north);
+            }
+        }
+    }
+}
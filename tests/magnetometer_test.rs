@@ -0,0 +1,233 @@
+//! Magnetometer detection, the HMC5883L and QMC5883P backends and automatic ranging against a
+//! mock I2C bus.
+
+mod common;
+
+mod tests {
+    use compass::magnetometer::{
+        detect, AnyMagnetometer, AutoRange, Error, Magnetometer, Sample, FITS_TO_RANGE_DOWN,
+        OVERFLOWS_TO_RANGE_UP,
+    };
+    use embassy_futures::block_on;
+    use embedded_hal::i2c::ErrorKind;
+
+    use crate::common::{MockBus, Transaction};
+
+    const HMC5883L: u8 = 0x1e;
+    const QMC5883P: u8 = 0x2c;
+    const QMC5883L: u8 = 0x0d;
+
+    const HMC5883L_INIT: [Transaction; 5] = [
+        Transaction::WriteRead(HMC5883L, &[0x0a], b"H43"),
+        Transaction::WriteRead(HMC5883L, &[0x0a], b"H43"),
+        Transaction::Write(HMC5883L, &[0x00, 0x10]),
+        Transaction::Write(HMC5883L, &[0x01, 0x20]),
+        Transaction::Write(HMC5883L, &[0x02, 0x03]),
+    ];
+
+    const QMC5883P_INIT: [Transaction; 6] = [
+        Transaction::Nack(HMC5883L),
+        Transaction::WriteRead(QMC5883P, &[0x00], &[0x80]),
+        Transaction::WriteRead(QMC5883P, &[0x00], &[0x80]),
+        Transaction::Write(QMC5883P, &[0x0b, 0x80]),
+        Transaction::Write(QMC5883P, &[0x29, 0x06]),
+        Transaction::Write(QMC5883P, &[0x0b, 0x08]),
+    ];
+
+    fn assert_close(a: (f32, f32, f32), b: (f32, f32, f32)) {
+        assert!((a.0 - b.0).abs() < 1e-3, "{:?} != {:?}", a, b);
+        assert!((a.1 - b.1).abs() < 1e-3, "{:?} != {:?}", a, b);
+        assert!((a.2 - b.2).abs() < 1e-3, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn detects_hmc5883l() {
+        block_on(async {
+            let dev = detect(MockBus::new(&HMC5883L_INIT)).await.unwrap();
+            assert!(matches!(dev, AnyMagnetometer::HMC5883L(_)));
+            assert_eq!(dev.name(), "HMC5883L");
+            assert_eq!(dev.ranges()[dev.range()], 1.3);
+        })
+    }
+
+    #[test]
+    fn detects_qmc5883p() {
+        block_on(async {
+            let dev = detect(MockBus::new(&QMC5883P_INIT)).await.unwrap();
+            assert!(matches!(dev, AnyMagnetometer::QMC5883P(_)));
+            assert_eq!(dev.ranges()[dev.range()], 8.);
+        })
+    }
+
+    #[test]
+    fn detects_qmc5883l_clone_with_wrong_chip_id() {
+        block_on(async {
+            const EXPECTED: &[Transaction] = &[
+                Transaction::Nack(HMC5883L),
+                Transaction::Nack(QMC5883P),
+                Transaction::WriteRead(QMC5883L, &[0x0d], &[0x00]),
+                Transaction::Write(QMC5883L, &[0x0a, 0x80]),
+                Transaction::Write(QMC5883L, &[0x0a, 0x01]),
+                Transaction::Write(QMC5883L, &[0x0b, 0x01]),
+            ];
+            let dev = detect(MockBus::new(EXPECTED)).await.unwrap();
+            assert!(matches!(dev, AnyMagnetometer::QMC5883L(_)));
+            assert_eq!(dev.ranges()[dev.range()], 2.);
+        })
+    }
+
+    #[test]
+    fn detects_nothing() {
+        block_on(async {
+            const EXPECTED: &[Transaction] = &[
+                Transaction::Nack(HMC5883L),
+                Transaction::Nack(QMC5883P),
+                Transaction::Nack(QMC5883L),
+            ];
+            assert!(matches!(
+                detect(MockBus::new(EXPECTED)).await,
+                Err(Error::NoDevice)
+            ));
+        })
+    }
+
+    #[test]
+    fn hmc5883l_sample_reorders_axes() {
+        block_on(async {
+            const EXPECTED: &[Transaction] = &[
+                HMC5883L_INIT[0],
+                HMC5883L_INIT[1],
+                HMC5883L_INIT[2],
+                HMC5883L_INIT[3],
+                HMC5883L_INIT[4],
+                Transaction::WriteRead(HMC5883L, &[0x09], &[0x01]),
+                // x, z, y big endian
+                Transaction::WriteRead(HMC5883L, &[0x03], &[0x01, 0xb4, 0xff, 0x26, 0xff, 0x93]),
+                Transaction::WriteRead(HMC5883L, &[0x09], &[0x00]),
+                Transaction::WriteRead(HMC5883L, &[0x09], &[0x01]),
+                Transaction::WriteRead(HMC5883L, &[0x03], &[0xf0, 0x00, 0, 0, 0, 0]),
+            ];
+            let AnyMagnetometer::HMC5883L(mut dev) = detect(MockBus::new(EXPECTED)).await.unwrap()
+            else {
+                panic!("wrong magnetometer")
+            };
+
+            let sample = dev.sample().await.unwrap();
+            assert_eq!(sample.raw, (436, -109, -218));
+            // 1090 LSB/G at ± 1.3 gauss
+            assert_close(sample.mag, (40., -10., -20.));
+            assert_eq!(sample.temp, None);
+
+            assert_eq!(dev.sample().await, Err(Error::NotReady));
+            assert_eq!(dev.sample().await, Err(Error::Overflow));
+            dev.release().done();
+        })
+    }
+
+    #[test]
+    fn qmc5883p_sample_and_range() {
+        block_on(async {
+            const EXPECTED: &[Transaction] = &[
+                QMC5883P_INIT[0],
+                QMC5883P_INIT[1],
+                QMC5883P_INIT[2],
+                QMC5883P_INIT[3],
+                QMC5883P_INIT[4],
+                QMC5883P_INIT[5],
+                Transaction::WriteRead(
+                    QMC5883P,
+                    &[0x01],
+                    &[0x77, 0x01, 0x89, 0xfe, 0x00, 0x00, 0, 0, 0b01],
+                ),
+                Transaction::WriteRead(QMC5883P, &[0x0b], &[0x08]),
+                Transaction::Write(QMC5883P, &[0x0b, 0x0c]),
+                Transaction::WriteRead(
+                    QMC5883P,
+                    &[0x01],
+                    &[0x77, 0x01, 0x89, 0xfe, 0x00, 0x00, 0, 0, 0b11],
+                ),
+            ];
+            let AnyMagnetometer::QMC5883P(mut dev) = detect(MockBus::new(EXPECTED)).await.unwrap()
+            else {
+                panic!("wrong magnetometer")
+            };
+
+            let sample = dev.sample().await.unwrap();
+            assert_eq!(sample.raw, (375, -375, 0));
+            // 3750 LSB/G at ± 8 gauss
+            assert_close(sample.mag, (10., -10., 0.));
+
+            dev.set_range(0).await.unwrap();
+            assert_eq!(dev.ranges()[dev.range()], 2.);
+            assert_eq!(dev.sample().await, Err(Error::Overflow));
+            dev.release().done();
+        })
+    }
+
+    const RANGES: &[f32] = &[2., 8., 12.];
+
+    /// 20 µT on each axis.
+    const SMALL: Result<Sample, Error<ErrorKind>> = Ok(Sample {
+        raw: (0, 0, 0),
+        mag: (20., 20., 20.),
+        temp: None,
+    });
+    const OVERFLOW: Result<Sample, Error<ErrorKind>> = Err(Error::Overflow);
+    const NOT_READY: Result<Sample, Error<ErrorKind>> = Err(Error::NotReady);
+
+    #[test]
+    fn auto_range_switches_up_after_repeated_overflow() {
+        let mut auto_range = AutoRange::new();
+        for _ in 1..OVERFLOWS_TO_RANGE_UP {
+            assert_eq!(auto_range.update(RANGES, 0, &OVERFLOW), None);
+        }
+        assert_eq!(auto_range.update(RANGES, 0, &OVERFLOW), Some(1));
+    }
+
+    #[test]
+    fn auto_range_ignores_isolated_overflow() {
+        let mut auto_range = AutoRange::new();
+        for _ in 0..10 {
+            for _ in 1..OVERFLOWS_TO_RANGE_UP {
+                auto_range.update(RANGES, 0, &OVERFLOW);
+            }
+            // Not ready readings neither count nor reset
+            assert_eq!(auto_range.update(RANGES, 0, &NOT_READY), None);
+            assert_eq!(auto_range.update(RANGES, 0, &SMALL), None);
+        }
+    }
+
+    #[test]
+    fn auto_range_stops_at_largest_range() {
+        let mut auto_range = AutoRange::new();
+        for _ in 0..10 {
+            assert_eq!(auto_range.update(RANGES, 2, &OVERFLOW), None);
+        }
+    }
+
+    #[test]
+    fn auto_range_switches_down_when_readings_fit() {
+        let mut auto_range = AutoRange::new();
+        for _ in 1..FITS_TO_RANGE_DOWN {
+            assert_eq!(auto_range.update(RANGES, 2, &SMALL), None);
+        }
+        assert_eq!(auto_range.update(RANGES, 2, &SMALL), Some(1));
+    }
+
+    #[test]
+    fn auto_range_stays_up_near_the_limit() {
+        // 180 µT would fit in ± 2 gauss but leaves no headroom
+        const LARGE: Result<Sample, Error<ErrorKind>> = Ok(Sample {
+            raw: (0, 0, 0),
+            mag: (180., 0., 0.),
+            temp: None,
+        });
+        let mut auto_range = AutoRange::new();
+        for _ in 0..FITS_TO_RANGE_DOWN {
+            for _ in 1..FITS_TO_RANGE_DOWN {
+                auto_range.update(RANGES, 1, &SMALL);
+            }
+            assert_eq!(auto_range.update(RANGES, 1, &LARGE), None);
+        }
+    }
+}
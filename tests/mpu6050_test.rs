@@ -0,0 +1,70 @@
+//! MPU6050 driver against a mock I2C bus.
+
+mod common;
+
+mod tests {
+    use compass::mpu6050::{Error, MPU6050};
+    use embassy_futures::block_on;
+    use embedded_hal::delay::DelayNs;
+
+    use crate::common::{MockBus, Transaction};
+
+    const ADDRESS: u8 = 0x68;
+
+    struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    impl embedded_hal_async::delay::DelayNs for NoDelay {
+        async fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    /// WHO_AM_I answering `$id`, the reset sequence, then one accelerometer read of 1 g on z.
+    macro_rules! init_with_id {
+        ($id:expr) => {{
+            const EXPECTED: &[Transaction] = &[
+                Transaction::WriteRead(ADDRESS, &[0x75], &[$id]),
+                Transaction::Write(ADDRESS, &[0x6b, 0x80]),
+                Transaction::Write(ADDRESS, &[0x6b, 0x01]),
+                Transaction::Write(ADDRESS, &[0x6c, 0x07]),
+                Transaction::Write(ADDRESS, &[0x1c, 0x00]),
+                Transaction::WriteRead(ADDRESS, &[0x3b], &[0, 0, 0, 0, 0x40, 0]),
+            ];
+            MockBus::new(EXPECTED)
+        }};
+    }
+
+    #[test]
+    fn accepts_mpu6050_mpu6500_and_mpu9250() {
+        for bus in [
+            init_with_id!(0x68),
+            init_with_id!(0x70),
+            init_with_id!(0x71),
+        ] {
+            let mut dev = MPU6050::new(bus, &mut NoDelay).unwrap();
+            assert_eq!(dev.accel().unwrap(), (0., 0., 1.));
+        }
+    }
+
+    #[test]
+    fn accepts_mpu6500_async() {
+        block_on(async {
+            let mut dev = MPU6050::new_async(init_with_id!(0x70), &mut NoDelay)
+                .await
+                .unwrap();
+            assert_eq!(dev.accel_async().await.unwrap(), (0., 0., 1.));
+        });
+    }
+
+    #[test]
+    fn rejects_other_devices() {
+        // The ICM-20948 answers at the same address
+        const EXPECTED: &[Transaction] = &[Transaction::WriteRead(ADDRESS, &[0x75], &[0xea])];
+        assert!(matches!(
+            MPU6050::new(MockBus::new(EXPECTED), &mut NoDelay),
+            Err(Error::InvalidDevice(0xea))
+        ));
+    }
+}
@@ -0,0 +1,310 @@
+//! NMEA sentence framing and parsing, including a log captured from a receiver starting up.
+
+mod tests {
+    use chrono::{NaiveDate, NaiveTime};
+    use compass::nmea::{checksum, parse, Framer, Gga, Gsa, Rmc, SatelliteInView, Sentence, Vtg};
+
+    const GGA: &[u8] =
+        b"$GNGGA,103015.00,5130.07407,N,00007.40741,W,1,09,1.0,45.2,M,45.9,M,,*57\r\n";
+
+    /// Feeds `bytes`, returning how many sentences were completed.
+    fn feed(framer: &mut Framer, bytes: &[u8]) -> usize {
+        bytes
+            .iter()
+            .filter(|&&byte| framer.push(byte).is_some())
+            .count()
+    }
+
+    #[test]
+    fn checksum_is_xor_of_data() {
+        assert_eq!(checksum(&GGA[1..GGA.len() - 5]), 0x57);
+    }
+
+    #[test]
+    fn returns_sentence_data() {
+        let mut framer = Framer::new();
+        let mut found = 0;
+        for &byte in GGA {
+            if let Some(sentence) = framer.push(byte) {
+                assert_eq!(sentence, &GGA[1..GGA.len() - 5]);
+                found += 1;
+            }
+        }
+        assert_eq!(found, 1);
+        assert_eq!(framer.sentences(), 1);
+    }
+
+    #[test]
+    fn accepts_lowercase_checksum() {
+        let mut framer = Framer::new();
+        assert_eq!(feed(&mut framer, b"$GPTXT,01,01,02,ANTSTATUS=OK*3b\r\n"), 1);
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let mut framer = Framer::new();
+        assert_eq!(feed(&mut framer, &GGA[..GGA.len() - 4]), 0);
+        assert_eq!(feed(&mut framer, b"58\r\n"), 0);
+        assert_eq!(framer.sentences(), 0);
+    }
+
+    #[test]
+    fn restarts_on_dollar_and_binary() {
+        let mut framer = Framer::new();
+        // Truncated sentence, then UBX bytes in the middle of another
+        assert_eq!(feed(&mut framer, b"$GNRMC,1030"), 0);
+        assert_eq!(feed(&mut framer, &GGA[..20]), 0);
+        assert_eq!(feed(&mut framer, &[0xb5, 0x62, 0x01, 0x07]), 0);
+        assert_eq!(feed(&mut framer, &GGA[20..]), 0);
+
+        assert_eq!(feed(&mut framer, GGA), 1);
+    }
+
+    #[test]
+    fn rejects_overlong_sentences() {
+        let mut framer = Framer::new();
+        assert_eq!(feed(&mut framer, b"$GPTXT,"), 0);
+        assert_eq!(feed(&mut framer, &[b'A'; 90]), 0);
+        assert_eq!(feed(&mut framer, b"*00\r\n"), 0);
+    }
+
+    /// Startup without a fix, then a fix. Holds a GSA with a bad checksum and a truncated GSV.
+    const LOG: &[u8] = include_bytes!("data/nmea_log.txt");
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn parses_log() {
+        const EXPECTED: &[&str] = &[
+            "RMC", "VTG", "GGA", "GSA", "GSV", "RMC", "VTG", "GGA", "GSA", "GSV", "GSV", "GSV",
+            "RMC",
+        ];
+
+        let mut framer = Framer::new();
+        let mut parsed = 0;
+        for &byte in LOG {
+            let Some(sentence) = framer.push(byte).and_then(parse) else {
+                continue;
+            };
+            let kind = match sentence {
+                Sentence::Gga(_) => "GGA",
+                Sentence::Rmc(_) => "RMC",
+                Sentence::Gsa(_) => "GSA",
+                Sentence::Gsv(_) => "GSV",
+                Sentence::Vtg(_) => "VTG",
+            };
+            assert_eq!(kind, EXPECTED[parsed]);
+            parsed += 1;
+        }
+        assert_eq!(parsed, EXPECTED.len());
+        // TXT and GLL are framed but not parsed
+        assert_eq!(framer.sentences(), EXPECTED.len() as u32 + 2);
+    }
+
+    #[test]
+    fn parses_gga() {
+        let Some(Sentence::Gga(gga)) = parse(&GGA[1..GGA.len() - 5]) else {
+            panic!("not a GGA");
+        };
+        let Gga {
+            time,
+            position,
+            quality,
+            satellites_used,
+            hdop,
+            altitude_msl,
+            geoid_separation,
+        } = gga;
+        assert_eq!(time, NaiveTime::from_hms_opt(10, 30, 15));
+        let (latitude, longitude) = position.unwrap();
+        assert_close(latitude, 51. + 30.07407 / 60.);
+        assert_close(longitude, -(7.40741 / 60.));
+        assert_eq!(quality, 1);
+        assert_eq!(satellites_used, 9);
+        assert_eq!(hdop, Some(1.0));
+        assert_eq!(altitude_msl, Some(45.2));
+        assert_eq!(geoid_separation, Some(45.9));
+    }
+
+    #[test]
+    fn parses_gga_without_fix() {
+        assert_eq!(
+            parse(b"GNGGA,103015.00,,,,,0,00,99.99,,,,,,"),
+            Some(Sentence::Gga(Gga {
+                time: NaiveTime::from_hms_opt(10, 30, 15),
+                position: None,
+                quality: 0,
+                satellites_used: 0,
+                hdop: Some(99.99),
+                altitude_msl: None,
+                geoid_separation: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn parses_rmc() {
+        let Some(Sentence::Rmc(rmc)) =
+            parse(b"GNRMC,103016.25,A,5130.07407,S,00007.40741,E,1.944,77.52,170524,1.2,W,A")
+        else {
+            panic!("not an RMC");
+        };
+        let Rmc {
+            time,
+            valid,
+            position,
+            speed,
+            course,
+            date,
+            magnetic_variation,
+        } = rmc;
+        assert_eq!(time, NaiveTime::from_hms_milli_opt(10, 30, 16, 250));
+        assert!(valid);
+        let (latitude, longitude) = position.unwrap();
+        assert_close(latitude, -(51. + 30.07407 / 60.));
+        assert_close(longitude, 7.40741 / 60.);
+        assert_close(speed.unwrap(), 1.944 * 1852. / 3600.);
+        assert_eq!(course, Some(77.52));
+        assert_eq!(date, NaiveDate::from_ymd_opt(2024, 5, 17));
+        assert_eq!(magnetic_variation, Some(-1.2));
+    }
+
+    #[test]
+    fn parses_rmc_without_fix() {
+        let Some(Sentence::Rmc(rmc)) = parse(b"GNRMC,103015.00,V,,,,,,,170524,,,N") else {
+            panic!("not an RMC");
+        };
+        assert!(!rmc.valid);
+        assert_eq!(rmc.position, None);
+        assert_eq!(rmc.speed, None);
+        assert_eq!(rmc.magnetic_variation, None);
+        assert_eq!(rmc.date, NaiveDate::from_ymd_opt(2024, 5, 17));
+    }
+
+    #[test]
+    fn parses_gsa() {
+        assert_eq!(
+            parse(b"GNGSA,A,3,05,13,15,18,23,24,,,,,,,1.87,1.01,1.57"),
+            Some(Sentence::Gsa(Gsa {
+                talker: *b"GN",
+                system: None,
+                fix: 3,
+                satellites: [
+                    Some(5),
+                    Some(13),
+                    Some(15),
+                    Some(18),
+                    Some(23),
+                    Some(24),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                ],
+                pdop: Some(1.87),
+                hdop: Some(1.01),
+                vdop: Some(1.57),
+            }))
+        );
+        // NMEA 4.10 system id
+        assert!(matches!(
+            parse(b"GNGSA,A,2,05,13,15,,,,,,,,,,2.50,2.30,0.99,1"),
+            Some(Sentence::Gsa(Gsa {
+                fix: 2,
+                system: Some(1),
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn parses_gsv() {
+        let Some(Sentence::Gsv(gsv)) =
+            parse(b"GPGSV,3,2,11,20,04,026,,23,17,310,31,24,74,080,42,26,05,176,")
+        else {
+            panic!("not a GSV");
+        };
+        assert_eq!(&gsv.talker, b"GP");
+        assert_eq!((gsv.sentences, gsv.sentence, gsv.in_view), (3, 2, 11));
+        assert_eq!(
+            gsv.satellites[0],
+            Some(SatelliteInView {
+                id: 20,
+                elevation: Some(4),
+                azimuth: Some(26),
+                cno: None,
+            })
+        );
+        assert_eq!(gsv.satellites[2].unwrap().cno, Some(42));
+
+        // Last sentence of a group, with a signal id
+        let Some(Sentence::Gsv(gsv)) = parse(b"GPGSV,3,3,11,29,20,130,35,30,02,197,,32,11,023,,1")
+        else {
+            panic!("not a GSV");
+        };
+        assert_eq!(gsv.satellites[2].unwrap().id, 32);
+        assert_eq!(gsv.satellites[3], None);
+    }
+
+    #[test]
+    fn parses_padded_gsv() {
+        // Four slots in every sentence, the last two empty, and a satellite without an orbit
+        const PADDED: &[u8] = b"$GPGSV,3,3,10,26,,,27,29,01,198,,,,,,,,,*43\r\n";
+        let mut framer = Framer::new();
+        let mut parsed = None;
+        for &byte in PADDED {
+            if let Some(data) = framer.push(byte) {
+                parsed = parse(data);
+            }
+        }
+        let Some(Sentence::Gsv(gsv)) = parsed else {
+            panic!("not a GSV");
+        };
+        assert_eq!((gsv.sentences, gsv.sentence, gsv.in_view), (3, 3, 10));
+        assert_eq!(
+            gsv.satellites,
+            [
+                Some(SatelliteInView {
+                    id: 26,
+                    elevation: None,
+                    azimuth: None,
+                    cno: Some(27),
+                }),
+                Some(SatelliteInView {
+                    id: 29,
+                    elevation: Some(1),
+                    azimuth: Some(198),
+                    cno: None,
+                }),
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_vtg() {
+        let Some(Sentence::Vtg(Vtg { course, speed })) =
+            parse(b"GNVTG,77.52,T,,M,0.194,N,0.360,K,A")
+        else {
+            panic!("not a VTG");
+        };
+        assert_eq!(course, Some(77.52));
+        assert_close(speed.unwrap(), 0.1);
+    }
+
+    #[test]
+    fn ignores_other_and_short_sentences() {
+        assert_eq!(
+            parse(b"GNGLL,5130.07407,N,00007.40741,W,103016.00,A,A"),
+            None
+        );
+        assert_eq!(parse(b"GNGGA,103015.00,5130.07407,N"), None);
+        assert_eq!(parse(b"GGA,103015.00"), None);
+        assert_eq!(parse(b""), None);
+    }
+}
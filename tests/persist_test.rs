@@ -0,0 +1,92 @@
+//! Snapshots of the state kept through deep sleep, and rejecting what RTC memory holds otherwise.
+
+mod tests {
+    use chrono::NaiveDate;
+    use compass::calibration::Calibration;
+    use compass::persist::{LastFix, Snapshot, WORDS};
+
+    fn fix() -> LastFix {
+        LastFix {
+            latitude: -41.2865,
+            longitude: 174.7762,
+            height: 31.5,
+            utc: NaiveDate::from_ymd_opt(2024, 6, 1)
+                .unwrap()
+                .and_hms_micro_opt(12, 34, 56, 789_000),
+            declination: 22.9,
+        }
+    }
+
+    fn calibration() -> Calibration {
+        Calibration {
+            offset: (12.5, -3.25, 40.),
+            soft_iron: [[1.02, 0.01, -0.03], [0.01, 0.97, 0.02], [-0.03, 0.02, 1.01]],
+            temp: Some(21.5),
+            drift: (0.1, -0.05, 0.),
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let snapshot = Snapshot {
+            last_fix: Some(fix()),
+            landmark: 3,
+            calibration: calibration(),
+        };
+        assert_eq!(Snapshot::from_words(&snapshot.to_words()), Some(snapshot));
+
+        // Nothing known yet
+        let snapshot = Snapshot {
+            last_fix: None,
+            landmark: 0,
+            calibration: Calibration::new(),
+        };
+        assert_eq!(Snapshot::from_words(&snapshot.to_words()), Some(snapshot));
+
+        // A fix from NMEA without a date, and a magnetometer without a temperature sensor
+        let snapshot = Snapshot {
+            last_fix: Some(LastFix { utc: None, ..fix() }),
+            landmark: 1,
+            calibration: Calibration {
+                temp: None,
+                ..calibration()
+            },
+        };
+        assert_eq!(Snapshot::from_words(&snapshot.to_words()), Some(snapshot));
+    }
+
+    #[test]
+    fn keeps_an_unknown_declination() {
+        let snapshot = Snapshot {
+            last_fix: Some(LastFix {
+                declination: f64::NAN,
+                ..fix()
+            }),
+            landmark: 0,
+            calibration: Calibration::new(),
+        };
+        let restored = Snapshot::from_words(&snapshot.to_words()).unwrap();
+        assert!(restored.last_fix.unwrap().declination.is_nan());
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        // RTC memory after power on
+        assert_eq!(Snapshot::from_words(&[0; WORDS]), None);
+        assert_eq!(Snapshot::from_words(&[0xffff_ffff; WORDS]), None);
+
+        let words = Snapshot {
+            last_fix: Some(fix()),
+            landmark: 2,
+            calibration: calibration(),
+        }
+        .to_words();
+        for i in 0..WORDS {
+            for bit in [0, 7, 31] {
+                let mut corrupt = words;
+                corrupt[i] ^= 1 << bit;
+                assert_eq!(Snapshot::from_words(&corrupt), None, "word {i} bit {bit}");
+            }
+        }
+    }
+}
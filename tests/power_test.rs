@@ -0,0 +1,103 @@
+//! Receiver power mode policy and the power management payloads.
+
+mod tests {
+    use compass::power::PowerMode::{Backup, Continuous, PowerSave};
+    use compass::power::{cfg_pm2, cfg_rxm, rxm_pmreq_backup, Inputs, Policy, STATIONARY_AFTER};
+    use embassy_time::{Duration, Instant};
+
+    const START: Instant = Instant::from_secs(100);
+
+    fn at(seconds: u64) -> Instant {
+        START + Duration::from_secs(seconds)
+    }
+
+    /// Screen on and no sleep requested, at `speed`.
+    fn moving(speed: Option<f64>) -> Inputs {
+        Inputs {
+            screen_on: true,
+            sleep_requested: false,
+            speed,
+        }
+    }
+
+    #[test]
+    fn continuous_while_in_use() {
+        let mut policy = Policy::new();
+        for second in 0..120 {
+            assert_eq!(policy.mode(moving(Some(1.4)), at(second)), Continuous);
+        }
+        // Searching for satellites
+        for second in 120..240 {
+            assert_eq!(policy.mode(moving(None), at(second)), Continuous);
+        }
+    }
+
+    #[test]
+    fn power_save_once_stationary() {
+        let mut policy = Policy::new();
+        let after = STATIONARY_AFTER.as_secs();
+        for second in 0..after {
+            assert_eq!(policy.mode(moving(Some(0.1)), at(second)), Continuous);
+        }
+        assert_eq!(policy.mode(moving(Some(0.1)), at(after)), PowerSave);
+
+        // Speeds between stationary and moving keep the mode
+        assert_eq!(policy.mode(moving(Some(0.4)), at(after + 1)), PowerSave);
+        assert_eq!(policy.mode(moving(Some(1.)), at(after + 2)), Continuous);
+        assert_eq!(policy.mode(moving(Some(0.4)), at(after + 3)), Continuous);
+
+        // The wait starts again
+        assert_eq!(policy.mode(moving(Some(0.)), at(after + 4)), Continuous);
+        assert_eq!(policy.mode(moving(Some(0.)), at(2 * after + 4)), PowerSave);
+
+        // Losing the fix means searching
+        assert_eq!(policy.mode(moving(None), at(2 * after + 5)), Continuous);
+        assert_eq!(policy.mode(moving(Some(0.)), at(2 * after + 6)), Continuous);
+    }
+
+    #[test]
+    fn power_save_with_the_screen_off() {
+        let mut policy = Policy::new();
+        let screen_off = Inputs {
+            screen_on: false,
+            ..moving(Some(1.4))
+        };
+        assert_eq!(policy.mode(screen_off, at(0)), PowerSave);
+        assert_eq!(policy.mode(moving(Some(1.4)), at(1)), Continuous);
+    }
+
+    #[test]
+    fn backup_before_sleep() {
+        let mut policy = Policy::new();
+        let sleep = Inputs {
+            screen_on: false,
+            sleep_requested: true,
+            speed: None,
+        };
+        assert_eq!(policy.mode(sleep, at(0)), Backup);
+        let sleep = Inputs {
+            sleep_requested: true,
+            ..moving(Some(1.4))
+        };
+        assert_eq!(policy.mode(sleep, at(1)), Backup);
+    }
+
+    #[test]
+    fn payloads() {
+        assert_eq!(cfg_rxm(false), [0x08, 0x00]);
+        assert_eq!(cfg_rxm(true), [0x08, 0x01]);
+
+        let pm2 = cfg_pm2();
+        assert_eq!(pm2[0], 0x01);
+        // Cyclic tracking, do not enter off and update ephemeris
+        assert_eq!(pm2[4..8], [0x00, 0x10, 0x03, 0x00]);
+        // 1000 ms update and 10000 ms search periods
+        assert_eq!(pm2[8..16], [0xe8, 0x03, 0x00, 0x00, 0x10, 0x27, 0x00, 0x00]);
+        assert!(pm2[16..].iter().all(|&byte| byte == 0));
+
+        assert_eq!(
+            rxm_pmreq_backup(),
+            [0, 0, 0, 0, 0, 0, 0, 0, 0x02, 0, 0, 0, 0x08, 0, 0, 0]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! QMC5883L driver against a mock I2C bus.
+
+mod common;
+
+mod tests {
+    use compass::qmc5883l::{
+        Error, FieldRange, Measurement, OutputDataRate, OversampleRate, QMC5883L,
+    };
+    use embassy_futures::block_on;
+    use embedded_hal::i2c::ErrorKind;
+
+    use crate::common::{MockBus, Transaction};
+
+    const ADDRESS: u8 = 0x0d;
+
+    /// Chip ID check followed by the soft reset sequence.
+    const INIT: [Transaction; 4] = [
+        Transaction::WriteRead(ADDRESS, &[0x0d], &[0xff]),
+        Transaction::Write(ADDRESS, &[0x0a, 0x80]),
+        Transaction::Write(ADDRESS, &[0x0a, 0x01]),
+        Transaction::Write(ADDRESS, &[0x0b, 0x01]),
+    ];
+
+    /// Expectations after [`INIT`].
+    macro_rules! init_then {
+        ($($transaction:expr),* $(,)?) => {{
+            const EXPECTED: &[Transaction] = &[INIT[0], INIT[1], INIT[2], INIT[3], $($transaction),*];
+            MockBus::new(EXPECTED)
+        }};
+    }
+
+    #[test]
+    fn new_checks_chip_id_and_resets() {
+        let dev = QMC5883L::new(MockBus::new(&INIT)).unwrap();
+        dev.release().done();
+    }
+
+    #[test]
+    fn new_rejects_invalid_chip_id() {
+        const EXPECTED: &[Transaction] = &[Transaction::WriteRead(ADDRESS, &[0x0d], &[0x00])];
+        assert!(matches!(
+            QMC5883L::new(MockBus::new(EXPECTED)),
+            Err(Error::InvalidDevice(0x00))
+        ));
+    }
+
+    #[test]
+    fn new_bus_error() {
+        const EXPECTED: &[Transaction] = &[Transaction::Fail(ADDRESS)];
+        assert!(matches!(
+            QMC5883L::new(MockBus::new(EXPECTED)),
+            Err(Error::BusError(ErrorKind::Other))
+        ));
+    }
+
+    #[test]
+    fn set_field_range_keeps_other_bits() {
+        let mut dev = QMC5883L::new(init_then![
+            Transaction::WriteRead(ADDRESS, &[0x09], &[0b1100_0101]),
+            Transaction::Write(ADDRESS, &[0x09, 0b1101_0101]),
+            Transaction::WriteRead(ADDRESS, &[0x09], &[0xff]),
+            Transaction::Write(ADDRESS, &[0x09, 0b1100_1111]),
+        ])
+        .unwrap();
+        assert_eq!(dev.field_range(), FieldRange::Range2Gauss);
+        dev.set_field_range(FieldRange::Range8Gauss).unwrap();
+        assert_eq!(dev.field_range(), FieldRange::Range8Gauss);
+        dev.set_field_range(FieldRange::Range2Gauss).unwrap();
+        assert_eq!(dev.field_range(), FieldRange::Range2Gauss);
+        dev.release().done();
+    }
+
+    #[test]
+    fn set_oversample_keeps_other_bits() {
+        let mut dev = QMC5883L::new(init_then![
+            Transaction::WriteRead(ADDRESS, &[0x09], &[0b0000_1101]),
+            Transaction::Write(ADDRESS, &[0x09, 0b1100_1101]),
+            Transaction::WriteRead(ADDRESS, &[0x09], &[0xff]),
+            Transaction::Write(ADDRESS, &[0x09, 0b0011_1111]),
+        ])
+        .unwrap();
+        dev.set_oversample(OversampleRate::Rate64).unwrap();
+        dev.set_oversample(OversampleRate::Rate512).unwrap();
+        dev.release().done();
+    }
+
+    #[test]
+    fn set_output_data_rate_keeps_other_bits() {
+        let mut dev = QMC5883L::new(init_then![
+            Transaction::WriteRead(ADDRESS, &[0x09], &[0b0000_0001]),
+            Transaction::Write(ADDRESS, &[0x09, 0b0000_1101]),
+            Transaction::WriteRead(ADDRESS, &[0x09], &[0xff]),
+            Transaction::Write(ADDRESS, &[0x09, 0b1111_0011]),
+        ])
+        .unwrap();
+        dev.set_output_data_rate(OutputDataRate::Rate200Hz).unwrap();
+        dev.set_output_data_rate(OutputDataRate::Rate10Hz).unwrap();
+        dev.release().done();
+    }
+
+    #[test]
+    fn mag_reads_little_endian_axes() {
+        let mut dev = QMC5883L::new(init_then![Transaction::WriteRead(
+            ADDRESS,
+            &[0x00],
+            &[0x34, 0x12, 0xcc, 0xfe, 0x00, 0x80, 0b001]
+        )])
+        .unwrap();
+        assert_eq!(dev.mag(), Ok((0x1234, -0x134, -0x8000)));
+        dev.release().done();
+    }
+
+    #[test]
+    fn mag_not_ready() {
+        let mut dev = QMC5883L::new(init_then![Transaction::WriteRead(
+            ADDRESS,
+            &[0x00],
+            &[0, 0, 0, 0, 0, 0, 0b000]
+        )])
+        .unwrap();
+        assert_eq!(dev.mag(), Err(Error::NotReady));
+        dev.release().done();
+    }
+
+    #[test]
+    fn mag_overflow() {
+        let mut dev = QMC5883L::new(init_then![Transaction::WriteRead(
+            ADDRESS,
+            &[0x00],
+            &[0, 0, 0, 0, 0, 0, 0b011]
+        )])
+        .unwrap();
+        assert_eq!(dev.mag(), Err(Error::Overflow));
+        dev.release().done();
+    }
+
+    #[test]
+    fn mag_bus_error() {
+        let mut dev = QMC5883L::new(init_then![Transaction::Fail(ADDRESS)]).unwrap();
+        assert_eq!(dev.mag(), Err(Error::BusError(ErrorKind::Other)));
+        dev.release().done();
+    }
+
+    #[test]
+    fn temp_reads_both_bytes_at_once() {
+        let mut dev = QMC5883L::new(init_then![Transaction::WriteRead(
+            ADDRESS,
+            &[0x07],
+            &[0x10, 0x0a]
+        )])
+        .unwrap();
+        assert_eq!(dev.temp(), Ok(0x0a10));
+        dev.release().done();
+    }
+
+    #[test]
+    fn measure_is_a_single_burst() {
+        let mut dev = QMC5883L::new(init_then![Transaction::WriteRead(
+            ADDRESS,
+            &[0x00],
+            &[0x34, 0x12, 0xcc, 0xfe, 0x00, 0x80, 0b001, 0x10, 0x0a]
+        )])
+        .unwrap();
+        assert_eq!(
+            dev.measure(),
+            Ok(Measurement {
+                mag: (0x1234, -0x134, -0x8000),
+                temp: 0x0a10
+            })
+        );
+        dev.release().done();
+    }
+
+    #[test]
+    fn measure_not_ready() {
+        let mut dev = QMC5883L::new(init_then![Transaction::WriteRead(
+            ADDRESS,
+            &[0x00],
+            &[0, 0, 0, 0, 0, 0, 0b000, 0x10, 0x0a]
+        )])
+        .unwrap();
+        assert_eq!(dev.measure(), Err(Error::NotReady));
+        dev.release().done();
+    }
+
+    #[test]
+    fn async_init_matches_blocking() {
+        block_on(async {
+            let dev = QMC5883L::new_async(MockBus::new(&INIT)).await.unwrap();
+            dev.release().done();
+        })
+    }
+
+    #[test]
+    fn async_configuration() {
+        block_on(async {
+            let mut dev = QMC5883L::new_async(init_then![
+                Transaction::WriteRead(ADDRESS, &[0x09], &[0b0000_0000]),
+                Transaction::Write(ADDRESS, &[0x09, 0b0001_0000]),
+                Transaction::WriteRead(ADDRESS, &[0x09], &[0b0001_0000]),
+                Transaction::Write(ADDRESS, &[0x09, 0b0101_0000]),
+                Transaction::WriteRead(ADDRESS, &[0x09], &[0b0101_0000]),
+                Transaction::Write(ADDRESS, &[0x09, 0b0101_1000]),
+                Transaction::WriteRead(ADDRESS, &[0x09], &[0b0101_1000]),
+                Transaction::Write(ADDRESS, &[0x09, 0b0101_1001]),
+                Transaction::Write(ADDRESS, &[0x0a, 0x00]),
+            ])
+            .await
+            .unwrap();
+            dev.set_field_range_async(FieldRange::Range8Gauss)
+                .await
+                .unwrap();
+            dev.set_oversample_async(OversampleRate::Rate256)
+                .await
+                .unwrap();
+            dev.set_output_data_rate_async(OutputDataRate::Rate100Hz)
+                .await
+                .unwrap();
+            dev.continuous_async().await.unwrap();
+            dev.enable_interrupt_async().await.unwrap();
+            dev.release().done();
+        })
+    }
+
+    #[test]
+    fn async_measure() {
+        block_on(async {
+            let mut dev = QMC5883L::new_async(init_then![
+                Transaction::WriteRead(
+                    ADDRESS,
+                    &[0x00],
+                    &[0x34, 0x12, 0xcc, 0xfe, 0x00, 0x80, 0b011, 0x10, 0x0a]
+                ),
+                Transaction::WriteRead(
+                    ADDRESS,
+                    &[0x00],
+                    &[0x34, 0x12, 0xcc, 0xfe, 0x00, 0x80, 0b001, 0x10, 0x0a]
+                ),
+            ])
+            .await
+            .unwrap();
+            assert_eq!(dev.measure_async().await, Err(Error::Overflow));
+            assert_eq!(
+                dev.measure_async().await,
+                Ok(Measurement {
+                    mag: (0x1234, -0x134, -0x8000),
+                    temp: 0x0a10
+                })
+            );
+            dev.release().done();
+        })
+    }
+
+    #[test]
+    fn micro_tesla_scales_with_range() {
+        assert_eq!(
+            FieldRange::Range2Gauss.micro_tesla((6000, -12000, 0)),
+            (50., -100., 0.)
+        );
+        assert_eq!(
+            FieldRange::Range8Gauss.micro_tesla((1500, -3000, 0)),
+            (50., -100., 0.)
+        );
+    }
+
+    #[test]
+    fn temperature_in_celsius() {
+        let measurement = Measurement {
+            mag: (0, 0, 0),
+            temp: 2150,
+        };
+        assert_eq!(measurement.celsius(), 21.5);
+    }
+}
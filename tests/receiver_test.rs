@@ -0,0 +1,244 @@
+//! Receiver captures replayed through [`Receiver`] against golden transcripts of its events.
+//!
+//! The captures are of a receiver sending NAV-PVT and NAV-SAT at 1 Hz from 2024-06-01 12:00:00
+//! UTC, near 51.5° N 0.12° W:
+//!
+//! - `cold_start.ubx`: the boot banner and two epochs of default NMEA output without a fix, the
+//!   ACK-ACKs for the configuration, then a time not yet resolved, a fix building up from none
+//!   through 2D to a good 3D one, and NAV-SAT before and after the satellites are acquired.
+//! - `no_fix.ubx`: four epochs searching for satellites, the time resolved from the third.
+//! - `fix_3d.ubx`: five epochs of a good 3D fix walking north at 1.4 m/s, each with NAV-SAT. The
+//!   third NAV-PVT lost a byte on the way.
+//! - `fix_loss.ubx`: a good fix, an epoch without gnssFixOK, two without a fix and the fix back
+//!   30 steps further north.
+
+mod tests {
+    use core::fmt::{self, Write};
+
+    use compass::nmea::Sentence;
+    use compass::receiver::{Event, FixQuality, NavPvtState, Receiver};
+    use compass::ubx::{self, Stats};
+    use embassy_time::{Duration, Instant};
+
+    const START: Instant = Instant::from_secs(100);
+
+    /// Bytes per UART read, which splits frames and sentences.
+    const READ_LEN: usize = 64;
+
+    /// Time between UART reads.
+    const READ_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Events as lines of text.
+    struct Transcript {
+        buf: [u8; 2048],
+        len: usize,
+    }
+
+    impl Transcript {
+        fn as_str(&self) -> &str {
+            core::str::from_utf8(&self.buf[..self.len]).unwrap()
+        }
+
+        fn record(&mut self, event: Event) -> fmt::Result {
+            match event {
+                Event::NavPvt(state) => self.state("NAV-PVT", &state),
+                Event::Sentence(sentence, state) => {
+                    let name = match sentence {
+                        Sentence::Gga(_) => "GGA",
+                        Sentence::Rmc(_) => "RMC",
+                        Sentence::Gsa(_) => "GSA",
+                        Sentence::Gsv(_) => "GSV",
+                        Sentence::Vtg(_) => "VTG",
+                    };
+                    self.state(name, &state)
+                }
+                Event::SkyView(view) => {
+                    let satellites = view.satellites();
+                    let used = satellites.iter().filter(|satellite| satellite.used).count();
+                    writeln!(self, "SKY {} seen {} used", satellites.len(), used)
+                }
+            }
+        }
+
+        /// Fix quality, date and time, position and satellites used.
+        fn state(&mut self, name: &str, state: &NavPvtState) -> fmt::Result {
+            let quality = match state.received {
+                Some(received) => state.fix_quality_at(received),
+                None => FixQuality::None,
+            };
+            write!(self, "{name} {quality:?}")?;
+            match state.date_time() {
+                Some(date_time) => write!(self, " {date_time}")?,
+                None => write!(self, " -")?,
+            }
+            match state.lle {
+                Some(lle) => write!(
+                    self,
+                    " {:.6},{:.6}",
+                    lle.latitude.as_float(),
+                    lle.longitude.as_float()
+                )?,
+                None => write!(self, " -")?,
+            }
+            writeln!(self, " {} sv", state.satellites_used)
+        }
+    }
+
+    impl Write for Transcript {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.buf
+                .get_mut(self.len..end)
+                .ok_or(fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    /// Feeds `capture` to a new [`Receiver`] in [`READ_LEN`] reads, [`READ_INTERVAL`] apart.
+    fn replay(capture: &[u8]) -> (Transcript, Stats) {
+        let mut parser_buf = [0u8; ubx::MAX_FRAME_LEN];
+        let mut receiver = Receiver::new(&mut parser_buf);
+        let mut transcript = Transcript {
+            buf: [0; 2048],
+            len: 0,
+        };
+
+        let mut now = START;
+        for read in capture.chunks(READ_LEN) {
+            receiver.consume(read, now, None, |event| {
+                transcript.record(event).unwrap();
+            });
+            now += READ_INTERVAL;
+        }
+        (transcript, receiver.stats())
+    }
+
+    #[test]
+    fn cold_start() {
+        let (transcript, stats) = replay(include_bytes!("data/cold_start.ubx"));
+        assert_eq!(
+            transcript.as_str(),
+            "RMC None - - 0 sv\n\
+            VTG None - - 0 sv\n\
+            GGA None - - 0 sv\n\
+            GSA None - - 0 sv\n\
+            RMC None - - 0 sv\n\
+            VTG None - - 0 sv\n\
+            GGA None - - 0 sv\n\
+            GSA None - - 0 sv\n\
+            NAV-PVT None - - 0 sv\n\
+            SKY 3 seen 0 used\n\
+            NAV-PVT None 2024-06-01 12:00:03 - 2 sv\n\
+            NAV-PVT Fix2D 2024-06-01 12:00:04 51.500000,-0.120000 3 sv\n\
+            NAV-PVT Fix3D 2024-06-01 12:00:05 51.500000,-0.120000 5 sv\n\
+            NAV-PVT Good 2024-06-01 12:00:06 51.500000,-0.120000 5 sv\n\
+            SKY 6 seen 5 used\n"
+        );
+        assert_eq!(
+            stats,
+            Stats {
+                // Five ACK-ACK, five NAV-PVT and two NAV-SAT
+                packets: 12,
+                checksum_failures: 0,
+                // The NMEA sentences
+                dropped_bytes: 343,
+            }
+        );
+    }
+
+    #[test]
+    fn no_fix() {
+        let (transcript, stats) = replay(include_bytes!("data/no_fix.ubx"));
+        assert_eq!(
+            transcript.as_str(),
+            "NAV-PVT None - - 0 sv\n\
+            SKY 3 seen 0 used\n\
+            NAV-PVT None - - 0 sv\n\
+            SKY 3 seen 0 used\n\
+            NAV-PVT None 2024-06-01 12:00:02 - 0 sv\n\
+            SKY 3 seen 0 used\n\
+            NAV-PVT None 2024-06-01 12:00:03 - 0 sv\n\
+            SKY 3 seen 0 used\n"
+        );
+        assert_eq!(
+            stats,
+            Stats {
+                packets: 8,
+                checksum_failures: 0,
+                dropped_bytes: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn fix_3d() {
+        let (transcript, stats) = replay(include_bytes!("data/fix_3d.ubx"));
+        assert_eq!(
+            transcript.as_str(),
+            "NAV-PVT Good 2024-06-01 12:00:00 51.500000,-0.120000 9 sv\n\
+            SKY 6 seen 5 used\n\
+            NAV-PVT Good 2024-06-01 12:00:01 51.500013,-0.120000 9 sv\n\
+            SKY 6 seen 5 used\n\
+            SKY 6 seen 5 used\n\
+            NAV-PVT Good 2024-06-01 12:00:03 51.500038,-0.120000 9 sv\n\
+            SKY 6 seen 5 used\n\
+            NAV-PVT Good 2024-06-01 12:00:04 51.500050,-0.120000 9 sv\n\
+            SKY 6 seen 5 used\n"
+        );
+        assert_eq!(
+            stats,
+            Stats {
+                packets: 9,
+                checksum_failures: 1,
+                // Only the NAV-PVT missing a byte, the NAV-SAT it ran into is found again
+                dropped_bytes: 99,
+            }
+        );
+    }
+
+    #[test]
+    fn fix_loss() {
+        let (transcript, stats) = replay(include_bytes!("data/fix_loss.ubx"));
+        assert_eq!(
+            transcript.as_str(),
+            "NAV-PVT Good 2024-06-01 12:00:00 51.500000,-0.120000 9 sv\n\
+            NAV-PVT Good 2024-06-01 12:00:01 51.500013,-0.120000 9 sv\n\
+            NAV-PVT None 2024-06-01 12:00:02 - 2 sv\n\
+            NAV-PVT None 2024-06-01 12:00:03 - 0 sv\n\
+            NAV-PVT None 2024-06-01 12:00:04 - 0 sv\n\
+            NAV-PVT Fix3D 2024-06-01 12:00:05 51.500378,-0.120000 4 sv\n"
+        );
+        assert_eq!(
+            stats,
+            Stats {
+                packets: 6,
+                checksum_failures: 0,
+                dropped_bytes: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn model_declination_without_a_fix() {
+        let mut parser_buf = [0u8; ubx::MAX_FRAME_LEN];
+        let mut receiver = Receiver::new(&mut parser_buf);
+        let mut declinations = [None; 6];
+        let mut epoch = 0;
+        receiver.consume(include_bytes!("data/fix_loss.ubx"), START, None, |event| {
+            if let Event::NavPvt(state) = event {
+                declinations[epoch] = state.declination();
+                epoch += 1;
+            }
+        });
+
+        // The captures carry no declination, so it comes from the model near London
+        let london = declinations[0].unwrap();
+        assert!((0. ..2.).contains(&london), "{london}");
+        assert_eq!(
+            declinations,
+            [Some(london), Some(london), None, None, None, Some(london)]
+        );
+    }
+}
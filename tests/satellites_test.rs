@@ -0,0 +1,145 @@
+//! Sky view from NAV-SAT payloads and from GSV and GSA sentences.
+
+mod tests {
+    use compass::nmea::{parse, Sentence};
+    use compass::satellites::{Constellation, GsvCollector, Satellite, SkyView};
+
+    /// NAV-SAT with GPS 5 used, Galileo 11 tracked but not used and GLONASS 3 without an orbit.
+    #[rustfmt::skip]
+    const NAV_SAT: &[u8] = &[
+        // iTOW, version, numSvs, reserved
+        0x00, 0x00, 0x00, 0x00, 0x01, 0x03, 0x00, 0x00,
+        // gnssId, svId, cno, elev, azim, prRes, flags
+        0x00, 0x05, 0x2a, 0x2f, 0x24, 0x01, 0x00, 0x00, 0x1f, 0x19, 0x00, 0x00,
+        0x02, 0x0b, 0x14, 0x05, 0x59, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00,
+        0x06, 0x03, 0x00, 0xa5, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn decodes_nav_sat() {
+        let view = SkyView::from_nav_sat(NAV_SAT).unwrap();
+        assert_eq!(
+            view.satellites(),
+            &[
+                Satellite {
+                    constellation: Constellation::Gps,
+                    id: 5,
+                    elevation: Some(47),
+                    azimuth: Some(292),
+                    cno: 42,
+                    used: true,
+                },
+                Satellite {
+                    constellation: Constellation::Galileo,
+                    id: 11,
+                    elevation: Some(5),
+                    azimuth: Some(89),
+                    cno: 20,
+                    used: false,
+                },
+                Satellite {
+                    constellation: Constellation::Glonass,
+                    id: 3,
+                    elevation: None,
+                    azimuth: Some(0),
+                    cno: 0,
+                    used: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_nav_sat() {
+        assert_eq!(SkyView::from_nav_sat(&NAV_SAT[..NAV_SAT.len() - 1]), None);
+        assert_eq!(SkyView::from_nav_sat(&NAV_SAT[..4]), None);
+        assert_eq!(SkyView::from_nav_sat(&[]), None);
+    }
+
+    fn add(collector: &mut GsvCollector, sentence: &[u8]) {
+        match parse(sentence) {
+            Some(Sentence::Gsa(gsa)) => collector.add_gsa(&gsa),
+            Some(Sentence::Gsv(gsv)) => collector.add_gsv(&gsv),
+            _ => panic!("not a GSA or GSV"),
+        }
+    }
+
+    fn ids(view: &SkyView) -> ([u16; 8], usize) {
+        let mut ids = [0; 8];
+        for (id, satellite) in ids.iter_mut().zip(view.satellites()) {
+            *id = satellite.id;
+        }
+        (ids, view.satellites().len())
+    }
+
+    #[test]
+    fn collects_gsv_groups() {
+        let mut collector = GsvCollector::new();
+        add(&mut collector, b"GNGSA,A,3,05,13,,,,,,,,,,,1.87,1.01,1.57");
+        add(&mut collector, b"GNGSA,A,3,77,,,,,,,,,,,,1.87,1.01,1.57");
+        add(
+            &mut collector,
+            b"GPGSV,2,1,05,05,47,292,38,13,30,249,33,15,62,157,,18,25,085,29",
+        );
+        add(&mut collector, b"GPGSV,2,2,05,20,04,026,");
+        add(&mut collector, b"GLGSV,1,1,01,77,12,045,31");
+
+        let view = collector.view();
+        assert_eq!(ids(view), ([5, 13, 15, 18, 20, 77, 0, 0], 6));
+
+        let used = view.satellites().iter().filter(|satellite| satellite.used);
+        assert!(used.map(|satellite| satellite.id).eq([5, 13, 77]));
+
+        let satellite = view.satellites()[2];
+        assert_eq!(satellite.constellation, Constellation::Gps);
+        assert_eq!(satellite.elevation, Some(62));
+        assert_eq!(satellite.azimuth, Some(157));
+        assert_eq!(satellite.cno, 0);
+        assert_eq!(view.satellites()[5].constellation, Constellation::Glonass);
+    }
+
+    #[test]
+    fn replaces_constellation_on_new_group() {
+        let mut collector = GsvCollector::new();
+        add(&mut collector, b"GNGSA,A,3,05,,,,,,,,,,,,1.87,1.01,1.57");
+        add(&mut collector, b"GPGSV,1,1,02,05,47,292,38,13,30,249,33");
+        add(&mut collector, b"GLGSV,1,1,01,77,12,045,31");
+
+        // Next epoch: GPS 13 is now used and GPS 5 has set
+        add(&mut collector, b"GNGSA,A,3,13,,,,,,,,,,,,1.87,1.01,1.57");
+        add(&mut collector, b"GPGSV,1,1,01,13,31,250,35");
+
+        let view = collector.view();
+        assert_eq!(ids(view), ([77, 13, 0, 0, 0, 0, 0, 0], 2));
+        assert!(!view.satellites()[0].used);
+        assert!(view.satellites()[1].used);
+    }
+
+    #[test]
+    fn matches_used_by_constellation() {
+        let mut collector = GsvCollector::new();
+        // NMEA 4.10: GPS 5 and Galileo 13 used, ids repeat across constellations
+        add(&mut collector, b"GNGSA,A,3,05,,,,,,,,,,,,1.87,1.01,1.57,1");
+        add(&mut collector, b"GNGSA,A,3,13,,,,,,,,,,,,1.87,1.01,1.57,3");
+        add(&mut collector, b"GPGSV,1,1,02,05,47,292,38,13,30,249,33");
+        add(&mut collector, b"GAGSV,1,1,02,05,12,045,31,13,55,120,40");
+
+        let used = collector
+            .view()
+            .satellites()
+            .iter()
+            .filter(|satellite| satellite.used);
+        assert!(used
+            .map(|satellite| (satellite.constellation, satellite.id))
+            .eq([(Constellation::Gps, 5), (Constellation::Galileo, 13)]));
+
+        // A single constellation talker names it too
+        let mut collector = GsvCollector::new();
+        add(&mut collector, b"GLGSA,A,3,05,,,,,,,,,,,,1.87,1.01,1.57");
+        add(&mut collector, b"GPGSV,1,1,01,05,47,292,38");
+        add(&mut collector, b"GLGSV,1,1,01,05,12,045,31");
+        let satellites = collector.view().satellites();
+        assert!(!satellites[0].used);
+        assert!(satellites[1].used);
+    }
+}
@@ -0,0 +1,201 @@
+//! POSIX TZ parsing and local time around DST transitions.
+
+mod tests {
+    use chrono::{NaiveDate, NaiveDateTime};
+    use compass::timezone::{Dst, HourFormat, ParseError, Rule, TimeZone, Transition, UTC};
+
+    const NEW_ZEALAND: &str = "NZST-12NZDT,M9.5.0,M4.1.0/3";
+    const US_EASTERN: &str = "EST5EDT,M3.2.0,M11.1.0";
+    const EUROPE_LONDON: &str = "GMT0BST,M3.5.0/1,M10.5.0";
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(hour, min, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_zones() {
+        assert_eq!(
+            TimeZone::parse(NEW_ZEALAND),
+            Ok(TimeZone {
+                name: "NZST",
+                offset: 12 * 3600,
+                dst: Some(Dst {
+                    name: "NZDT",
+                    offset: 13 * 3600,
+                    start: Transition {
+                        rule: Rule::MonthWeekDay {
+                            month: 9,
+                            week: 5,
+                            weekday: 0
+                        },
+                        time: 2 * 3600,
+                    },
+                    end: Transition {
+                        rule: Rule::MonthWeekDay {
+                            month: 4,
+                            week: 1,
+                            weekday: 0
+                        },
+                        time: 3 * 3600,
+                    },
+                }),
+            })
+        );
+
+        assert_eq!(
+            TimeZone::parse("UTC0"),
+            Ok(TimeZone {
+                name: "UTC",
+                offset: 0,
+                dst: None
+            })
+        );
+
+        // Quoted names, minutes and an explicit DST offset
+        let zone = TimeZone::parse("<+0530>-5:30").unwrap();
+        assert_eq!((zone.name, zone.offset), ("+0530", 5 * 3600 + 30 * 60));
+        let zone = TimeZone::parse("LHST-10:30LHDT-11,M10.1.0,M4.1.0").unwrap();
+        assert_eq!(zone.dst.unwrap().offset, 11 * 3600);
+
+        // Julian days, day numbers and transition times beyond a day
+        let zone = TimeZone::parse("AAA3BBB,J60/-1,300/26:30:15").unwrap();
+        let dst = zone.dst.unwrap();
+        assert_eq!(dst.offset, -2 * 3600);
+        assert_eq!(dst.start.rule, Rule::Julian(60));
+        assert_eq!(dst.start.time, -3600);
+        assert_eq!(dst.end.rule, Rule::Day(300));
+        assert_eq!(dst.end.time, 26 * 3600 + 30 * 60 + 15);
+    }
+
+    #[test]
+    fn rejects_malformed_zones() {
+        assert_eq!(TimeZone::parse(""), Err(ParseError::Name));
+        assert_eq!(TimeZone::parse("NZ-12"), Err(ParseError::Name));
+        assert_eq!(TimeZone::parse("<+05-5"), Err(ParseError::Name));
+        assert_eq!(TimeZone::parse("NZST"), Err(ParseError::Offset));
+        assert_eq!(TimeZone::parse("NZST-25"), Err(ParseError::Offset));
+        assert_eq!(TimeZone::parse("NZST-12:60"), Err(ParseError::Offset));
+        assert_eq!(
+            TimeZone::parse("NZST-12NZDT,M13.1.0,M4.1.0"),
+            Err(ParseError::Rule)
+        );
+        assert_eq!(
+            TimeZone::parse("NZST-12NZDT,M9.6.0,M4.1.0"),
+            Err(ParseError::Rule)
+        );
+        assert_eq!(TimeZone::parse("NZST-12NZDT,M9.5.0"), Err(ParseError::Rule));
+        assert_eq!(
+            TimeZone::parse("NZST-12NZDT,J0,J365"),
+            Err(ParseError::Rule)
+        );
+        assert_eq!(
+            TimeZone::parse("NZST-12NZDT,M9.5.0,M4.1.0/168"),
+            Err(ParseError::Rule)
+        );
+        assert_eq!(
+            TimeZone::parse("NZST-12NZDT,M9.5.0,M4.1.0 "),
+            Err(ParseError::Trailing)
+        );
+    }
+
+    #[test]
+    fn southern_hemisphere_transitions() {
+        let zone = TimeZone::parse(NEW_ZEALAND).unwrap();
+
+        // DST starts 2024-09-29 at 02:00 NZST, 14:00 UTC the day before
+        let before = zone.local(utc(2024, 9, 28, 13, 59));
+        assert_eq!(before.time, utc(2024, 9, 29, 1, 59));
+        assert_eq!((before.name, before.dst), ("NZST", false));
+        let after = zone.local(utc(2024, 9, 28, 14, 0));
+        assert_eq!(after.time, utc(2024, 9, 29, 3, 0));
+        assert_eq!((after.name, after.dst), ("NZDT", true));
+
+        // Across the new year
+        assert!(zone.is_dst(utc(2024, 12, 31, 12, 0)));
+        assert!(zone.is_dst(utc(2025, 1, 1, 12, 0)));
+
+        // Ends 2025-04-06 at 03:00 NZDT, 14:00 UTC the day before, so 02:xx repeats
+        let before = zone.local(utc(2025, 4, 5, 13, 59));
+        assert_eq!(before.time, utc(2025, 4, 6, 2, 59));
+        assert!(before.dst);
+        let after = zone.local(utc(2025, 4, 5, 14, 0));
+        assert_eq!(after.time, utc(2025, 4, 6, 2, 0));
+        assert!(!after.dst);
+
+        assert!(!zone.is_dst(utc(2025, 7, 1, 0, 0)));
+    }
+
+    #[test]
+    fn northern_hemisphere_transitions() {
+        let zone = TimeZone::parse(US_EASTERN).unwrap();
+        // Second Sunday of March 2024 is the 10th, 02:00 EST is 07:00 UTC
+        assert!(!zone.is_dst(utc(2024, 3, 10, 6, 59)));
+        assert!(zone.is_dst(utc(2024, 3, 10, 7, 0)));
+        assert_eq!(
+            zone.local(utc(2024, 3, 10, 7, 0)).time,
+            utc(2024, 3, 10, 3, 0)
+        );
+        // First Sunday of November 2024 is the 3rd, 02:00 EDT is 06:00 UTC
+        assert!(zone.is_dst(utc(2024, 11, 3, 5, 59)));
+        assert!(!zone.is_dst(utc(2024, 11, 3, 6, 0)));
+        assert!(!zone.is_dst(utc(2024, 12, 31, 23, 0)));
+
+        // Without rules DST follows the US ones
+        assert_eq!(TimeZone::parse("EST5EDT").unwrap(), zone);
+
+        // Last Sunday of March 2024 is the 31st, of October the 27th
+        let zone = TimeZone::parse(EUROPE_LONDON).unwrap();
+        assert!(!zone.is_dst(utc(2024, 3, 31, 0, 59)));
+        assert_eq!(zone.local(utc(2024, 3, 31, 1, 0)).name, "BST");
+        assert!(zone.is_dst(utc(2024, 10, 27, 0, 59)));
+        assert!(!zone.is_dst(utc(2024, 10, 27, 1, 0)));
+    }
+
+    #[test]
+    fn julian_days_skip_leap_day() {
+        let zone = TimeZone::parse("AAA0BBB,J60/0,J61/0").unwrap();
+        // 1 March in both years
+        assert!(zone.is_dst(utc(2024, 3, 1, 12, 0)));
+        assert!(!zone.is_dst(utc(2024, 2, 29, 12, 0)));
+        assert!(zone.is_dst(utc(2023, 3, 1, 12, 0)));
+
+        // Day 59 counts 29 February
+        let zone = TimeZone::parse("AAA0BBB,59/0,60/0").unwrap();
+        assert!(zone.is_dst(utc(2024, 2, 29, 12, 0)));
+        assert!(zone.is_dst(utc(2023, 3, 1, 12, 0)));
+    }
+
+    #[test]
+    fn zone_from_longitude() {
+        assert_eq!(TimeZone::from_longitude(0.), UTC);
+        let zone = TimeZone::from_longitude(174.78);
+        assert_eq!((zone.name, zone.offset), ("UTC+12", 12 * 3600));
+        let zone = TimeZone::from_longitude(-7.6);
+        assert_eq!((zone.name, zone.offset), ("UTC-1", -3600));
+        assert_eq!(TimeZone::from_longitude(-180.).offset, -12 * 3600);
+    }
+
+    #[test]
+    fn selects_a_zone() {
+        let zone = TimeZone::select(Some(NEW_ZEALAND), true, Some(-7.6));
+        assert_eq!(zone.name, "NZST");
+        // A bad zone falls back like none at all
+        let zone = TimeZone::select(Some("NZST"), true, Some(-7.6));
+        assert_eq!(zone.name, "UTC-1");
+        assert_eq!(TimeZone::select(None, true, None), UTC);
+        assert_eq!(TimeZone::select(None, false, Some(-7.6)), UTC);
+    }
+
+    #[test]
+    fn formats_hours() {
+        assert_eq!(HourFormat::H24.hour(0), (0, None));
+        assert_eq!(HourFormat::H24.hour(23), (23, None));
+        assert_eq!(HourFormat::H12.hour(0), (12, Some("AM")));
+        assert_eq!(HourFormat::H12.hour(11), (11, Some("AM")));
+        assert_eq!(HourFormat::H12.hour(12), (12, Some("PM")));
+        assert_eq!(HourFormat::H12.hour(23), (11, Some("PM")));
+    }
+}
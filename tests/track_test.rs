@@ -0,0 +1,400 @@
+//! Track point encoding, the flash ring buffer on a RAM flash, sampling and GPX output.
+
+mod tests {
+    use core::fmt::{self, Write};
+
+    use chrono::NaiveDate;
+    use compass::track::{
+        encode, Decoder, Gpx, Record, Sampler, TrackLog, TrackPoint, MAGIC, MAX_RECORD_LEN,
+    };
+    use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash};
+
+    const SECTOR: usize = 128;
+    const SECTORS: usize = 4;
+
+    /// NOR flash in RAM: erasing sets bytes to 0xff and writing can only clear bits.
+    struct RamFlash {
+        data: [u8; SECTOR * SECTORS],
+    }
+
+    impl RamFlash {
+        /// Blank, as if never erased.
+        fn new() -> Self {
+            Self {
+                data: [0; SECTOR * SECTORS],
+            }
+        }
+    }
+
+    impl ErrorType for RamFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for RamFlash {
+        const READ_SIZE: usize = 4;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            if !offset.is_multiple_of(Self::READ_SIZE)
+                || !bytes.len().is_multiple_of(Self::READ_SIZE)
+            {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            let data = self
+                .data
+                .get(offset..offset + bytes.len())
+                .ok_or(NorFlashErrorKind::OutOfBounds)?;
+            bytes.copy_from_slice(data);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    impl NorFlash for RamFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = SECTOR;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            let (from, to) = (from as usize, to as usize);
+            if !from.is_multiple_of(SECTOR) || !to.is_multiple_of(SECTOR) {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            self.data
+                .get_mut(from..to)
+                .ok_or(NorFlashErrorKind::OutOfBounds)?
+                .fill(0xff);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            if !offset.is_multiple_of(Self::WRITE_SIZE)
+                || !bytes.len().is_multiple_of(Self::WRITE_SIZE)
+            {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            let data = self
+                .data
+                .get_mut(offset..offset + bytes.len())
+                .ok_or(NorFlashErrorKind::OutOfBounds)?;
+            for (old, new) in data.iter_mut().zip(bytes) {
+                *old &= new;
+            }
+            Ok(())
+        }
+    }
+
+    /// Walking north east from Wellington, a point every 10 s.
+    fn point(i: i32) -> TrackPoint {
+        TrackPoint {
+            latitude: -412_866_400 + 90 * i,
+            longitude: 1_747_755_700 + 120 * i,
+            elevation: 100 + i % 7,
+            time: 1_710_028_798 + 10 * i as i64,
+        }
+    }
+
+    /// Checks the points in `log` against `expected`, returning how many there were.
+    fn log_points(log: &mut TrackLog<RamFlash>, expected: &[(TrackPoint, bool)]) -> usize {
+        let mut count = 0;
+        for (found, expected) in log.points().zip(expected.iter()) {
+            assert_eq!(found.unwrap(), *expected);
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn round_trips_points() {
+        let points = [
+            point(0),
+            point(1),
+            // Extremes
+            TrackPoint {
+                latitude: i32::MIN,
+                longitude: i32::MAX,
+                elevation: -4_000,
+                time: i64::MIN,
+            },
+            TrackPoint {
+                latitude: i32::MAX,
+                longitude: i32::MIN,
+                elevation: 88_490,
+                time: i64::MAX,
+            },
+            point(2),
+        ];
+
+        let mut stream = [0xffu8; 5 * MAX_RECORD_LEN];
+        let mut len = 0;
+        let mut previous = None;
+        for (i, point) in points.iter().enumerate() {
+            let mut record = [0u8; MAX_RECORD_LEN];
+            let record_len = encode(point, previous.as_ref(), i == 4, &mut record);
+            assert!(record_len.is_multiple_of(4));
+            stream[len..len + record_len].copy_from_slice(&record[..record_len]);
+            len += record_len;
+            previous = Some(*point);
+        }
+
+        let mut decoder = Decoder::new();
+        let mut position = 0;
+        for (i, point) in points.iter().enumerate() {
+            let (record, record_len) = decoder.decode(&stream[position..]).unwrap();
+            assert_eq!(record, Record::Point(*point, i == 4));
+            position += record_len;
+        }
+        assert_eq!(position, len);
+        // Erased flash ends the data
+        assert_eq!(decoder.decode(&stream[position..]), None);
+    }
+
+    #[test]
+    fn compresses_nearby_points() {
+        let mut record = [0u8; MAX_RECORD_LEN];
+        // Tag, 2 byte latitude and longitude deltas, 1 byte elevation and time
+        assert_eq!(encode(&point(1), Some(&point(0)), false, &mut record), 8);
+        // Absolute, 5 bytes each but the elevation
+        assert_eq!(encode(&point(1), None, false, &mut record), 20);
+        assert_eq!(encode(&point(1), Some(&point(0)), true, &mut record), 20);
+    }
+
+    #[test]
+    fn rejects_cut_short_records() {
+        let mut record = [0u8; MAX_RECORD_LEN];
+        let len = encode(&point(0), None, false, &mut record);
+        let mut data = [0xffu8; MAX_RECORD_LEN];
+        data[..len - 6].copy_from_slice(&record[..len - 6]);
+        assert_eq!(Decoder::new().decode(&data), None);
+        assert_eq!(Decoder::new().decode(&[]), None);
+        assert_eq!(Decoder::new().decode(&[0x7f]), None);
+    }
+
+    #[test]
+    fn appends_and_reopens() {
+        let mut log = TrackLog::open(RamFlash::new(), 0, (SECTOR * SECTORS) as u32).unwrap();
+        assert_eq!(log.points().count(), 0);
+        assert_eq!(log.last(), None);
+
+        for i in 0..5 {
+            log.append(&point(i)).unwrap();
+        }
+        log.break_segment();
+        log.append(&point(10)).unwrap();
+
+        let expected = [
+            (point(0), true),
+            (point(1), false),
+            (point(2), false),
+            (point(3), false),
+            (point(4), false),
+            (point(10), true),
+        ];
+        assert_eq!(log_points(&mut log, &expected), expected.len());
+
+        // Carries on after a reset with a new segment
+        let flash = log.release();
+        let mut log = TrackLog::open(flash, 0, (SECTOR * SECTORS) as u32).unwrap();
+        assert_eq!(log.last(), Some(point(10)));
+        log.append(&point(11)).unwrap();
+        let last = log.points().last().unwrap().unwrap();
+        assert_eq!(last, (point(11), true));
+        assert_eq!(log.points().count(), 7);
+    }
+
+    #[test]
+    fn overwrites_the_oldest_sector() {
+        let mut log = TrackLog::open(RamFlash::new(), 0, (SECTOR * SECTORS) as u32).unwrap();
+        // 8 byte records after the first of each sector, enough to go round the ring twice
+        let total = 100;
+        for i in 0..total {
+            log.append(&point(i)).unwrap();
+        }
+
+        let mut previous: Option<TrackPoint> = None;
+        let mut count = 0;
+        for found in log.points() {
+            let (found, new_segment) = found.unwrap();
+            // Oldest first with none missing after the first
+            match previous {
+                Some(previous) => assert_eq!(found, point(index_of(&previous) + 1)),
+                None => assert!(!new_segment),
+            }
+            previous = Some(found);
+            count += 1;
+        }
+        assert_eq!(previous, Some(point(total - 1)));
+        assert!(count < total && count > total / 3);
+    }
+
+    /// Inverse of [`point`].
+    fn index_of(point: &TrackPoint) -> i32 {
+        ((point.time - 1_710_028_798) / 10) as i32
+    }
+
+    #[test]
+    fn starts_over_after_clear() {
+        let mut log = TrackLog::open(RamFlash::new(), 0, (SECTOR * SECTORS) as u32).unwrap();
+        log.append(&point(0)).unwrap();
+        log.clear().unwrap();
+        assert_eq!(log.points().count(), 0);
+        log.append(&point(1)).unwrap();
+        assert_eq!(log.points().next().unwrap().unwrap(), (point(1), true));
+    }
+
+    #[test]
+    fn clears_a_sector_at_a_time() {
+        let mut log = TrackLog::open(RamFlash::new(), 0, (SECTOR * SECTORS) as u32).unwrap();
+        // Enough to wrap around the ring
+        for i in 0..40 {
+            log.append(&point(i)).unwrap();
+        }
+        assert_eq!(log.sectors(), SECTORS as u32);
+        for step in 0..log.sectors() {
+            log.clear_step(step).unwrap();
+        }
+        assert_eq!(log.points().count(), 0);
+
+        // Only the header of the sector it starts over in is left
+        let flash = log.release();
+        assert_eq!(flash.data[..4], MAGIC);
+        assert!(flash.data[8..].iter().all(|&byte| byte == 0xff));
+
+        let mut log = TrackLog::open(flash, 0, (SECTOR * SECTORS) as u32).unwrap();
+        assert_eq!(log.points().count(), 0);
+        log.append(&point(1)).unwrap();
+        assert_eq!(log.points().next().unwrap().unwrap(), (point(1), true));
+    }
+
+    #[test]
+    fn skips_a_record_cut_short() {
+        let mut flash = RamFlash::new();
+        flash.erase(0, (SECTOR * SECTORS) as u32).unwrap();
+        let mut header = [0u8; 8];
+        header[..4].copy_from_slice(&MAGIC);
+        flash.write(0, &header).unwrap();
+        // Half a record, as left by a reset while writing
+        let mut record = [0u8; MAX_RECORD_LEN];
+        encode(&point(0), None, true, &mut record);
+        flash.write(8, &record[..8]).unwrap();
+
+        let mut log = TrackLog::open(flash, 0, (SECTOR * SECTORS) as u32).unwrap();
+        assert_eq!(log.points().count(), 0);
+        log.append(&point(1)).unwrap();
+        log.append(&point(2)).unwrap();
+        let expected = [(point(1), true), (point(2), false)];
+        assert_eq!(log_points(&mut log, &expected), 2);
+    }
+
+    #[test]
+    fn samples_by_time_or_distance() {
+        let at = |i: i32, time: i64| TrackPoint { time, ..point(i) };
+
+        // Every 30 s, or 5 m where each step of point() is 1.4 m
+        let mut sampler = Sampler::new(30, 5.);
+        assert!(sampler.sample(&at(0, 0)));
+        assert!(!sampler.sample(&at(0, 29)));
+        assert!(sampler.sample(&at(0, 30)));
+        assert!(!sampler.sample(&at(3, 31)));
+        assert!(sampler.sample(&at(5, 32)));
+
+        // Distance only
+        let mut sampler = Sampler::new(0, 5.);
+        assert!(sampler.sample(&at(0, 0)));
+        assert!(!sampler.sample(&at(0, 1_000)));
+        assert!(sampler.sample(&at(5, 1_001)));
+
+        // Neither, every fix
+        let mut sampler = Sampler::new(0, 0.);
+        assert!(sampler.sample(&at(0, 0)));
+        assert!(sampler.sample(&at(0, 0)));
+    }
+
+    /// Collects the GPX document.
+    struct Document {
+        buf: [u8; 1024],
+        len: usize,
+    }
+
+    impl Write for Document {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_gpx() {
+        let mut document = Document {
+            buf: [0; 1024],
+            len: 0,
+        };
+        let mut gpx = Gpx::start(&mut document).unwrap();
+        gpx.point(&mut document, &point(0), true).unwrap();
+        gpx.point(&mut document, &point(1), false).unwrap();
+        let below_sea_level = TrackPoint {
+            latitude: 5,
+            longitude: -5,
+            elevation: -12,
+            time: NaiveDate::from_ymd_opt(2000, 1, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+                .timestamp(),
+        };
+        gpx.point(&mut document, &below_sea_level, true).unwrap();
+        gpx.finish(&mut document).unwrap();
+
+        assert_eq!(
+            core::str::from_utf8(&document.buf[..document.len]).unwrap(),
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<gpx version=\"1.1\" creator=\"compass\" ",
+                "xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+                "<trk>\n",
+                "<trkseg>\n",
+                "<trkpt lat=\"-41.2866400\" lon=\"174.7755700\"><ele>10.0</ele>",
+                "<time>2024-03-09T23:59:58Z</time></trkpt>\n",
+                "<trkpt lat=\"-41.2866310\" lon=\"174.7755820\"><ele>10.1</ele>",
+                "<time>2024-03-10T00:00:08Z</time></trkpt>\n",
+                "</trkseg>\n",
+                "<trkseg>\n",
+                "<trkpt lat=\"0.0000005\" lon=\"-0.0000005\"><ele>-1.2</ele>",
+                "<time>2000-01-01T00:00:00Z</time></trkpt>\n",
+                "</trkseg>\n",
+                "</trk>\n",
+                "</gpx>\n",
+            )
+        );
+    }
+
+    #[test]
+    fn empty_gpx_has_no_segment() {
+        let mut document = Document {
+            buf: [0; 1024],
+            len: 0,
+        };
+        Gpx::start(&mut document)
+            .unwrap()
+            .finish(&mut document)
+            .unwrap();
+        let document = core::str::from_utf8(&document.buf[..document.len]).unwrap();
+        assert!(document.ends_with("<trk>\n</trk>\n</gpx>\n"));
+    }
+
+    #[test]
+    fn converts_from_degrees() {
+        let time = NaiveDate::from_ymd_opt(2024, 3, 9)
+            .unwrap()
+            .and_hms_opt(23, 59, 58)
+            .unwrap();
+        let point = TrackPoint::new(-41.28664, 174.77557, 10.04, time);
+        assert_eq!(point, self::point(0));
+        assert_eq!(TrackPoint::new(0., 0., f64::NAN, time).elevation, 0);
+    }
+}
@@ -0,0 +1,126 @@
+//! Trip distance, moving time and speeds, and progress toward a landmark.
+
+mod tests {
+    use compass::landmark::Landmark;
+    use compass::trip::{Progress, Trip, MIN_STEP};
+    use embassy_time::{Duration, Instant};
+    use geoconv::{Degrees, Lle, Meters};
+
+    /// Degrees of latitude in a little over a metre.
+    const METRE: f64 = 1. / 111_100.;
+
+    const START: Instant = Instant::from_secs(100);
+
+    /// `north` metres north of the start.
+    fn north(north: f64) -> Option<(Degrees, Degrees)> {
+        Some((Degrees::new(-41.3 + north * METRE), Degrees::new(174.78)))
+    }
+
+    fn at(seconds: u64) -> Instant {
+        START + Duration::from_secs(seconds)
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{actual} is not within {tolerance} of {expected}"
+        );
+    }
+
+    #[test]
+    fn stationary_jitter_is_ignored() {
+        let mut trip = Trip::new();
+        for (i, wander) in [0., 3., -2., 6., -4., 1.].into_iter().enumerate() {
+            trip.update(north(wander), 0.2, at(i as u64));
+        }
+        assert_eq!(trip.distance, 0.);
+        assert_eq!(trip.moving_time, Duration::from_secs(0));
+        assert_eq!(trip.max_speed, 0.);
+        assert_eq!(trip.average_speed(), None);
+
+        // NMEA receivers may give no speed at all
+        trip.update(north(20.), f64::NAN, at(10));
+        assert_eq!(trip.distance, 0.);
+    }
+
+    #[test]
+    fn counts_distance_in_steps() {
+        let mut trip = Trip::new();
+        for second in 0..=20 {
+            let speed = if second == 7 { 1.5 } else { 1. };
+            trip.update(north(second as f64), speed, at(second));
+            // Nothing under a step
+            if second < MIN_STEP as u64 {
+                assert_eq!(trip.distance, 0.);
+            }
+        }
+        assert_close(trip.distance, 20., 0.1);
+        assert_eq!(trip.moving_time, Duration::from_secs(20));
+        assert_eq!(trip.max_speed, 1.5);
+        assert_close(trip.average_speed().unwrap(), 1., 0.01);
+    }
+
+    #[test]
+    fn repeated_fixes_are_ignored() {
+        let mut trip = Trip::new();
+        trip.update(north(0.), 1., at(0));
+        trip.update(north(10.), 1., at(1));
+        trip.update(north(20.), 1., at(1));
+        trip.update(north(30.), 1., at(0));
+        assert_close(trip.distance, 10., 0.1);
+        assert_eq!(trip.moving_time, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn lost_fix() {
+        let mut trip = Trip::new();
+        trip.update(north(0.), 1., at(0));
+        trip.update(north(10.), 1., at(1));
+        for second in 2..30 {
+            trip.update(None, 1., at(second));
+        }
+        // The way across the gap counts but not the time
+        trip.update(north(100.), 1., at(30));
+        assert_close(trip.distance, 100., 0.5);
+        assert_eq!(trip.moving_time, Duration::from_secs(1));
+
+        trip.update(north(110.), 1., at(40));
+        assert_close(trip.distance, 110., 0.5);
+        assert_eq!(trip.moving_time, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn progress_toward_a_landmark() {
+        let landmark = Landmark {
+            name: "Hill",
+            lle: Lle::new(
+                Degrees::new(-41.3 + 1000. * METRE),
+                Degrees::new(174.78),
+                Meters::new(0.),
+            ),
+        };
+        let start = north(0.).unwrap();
+
+        let progress = Progress::toward(&landmark, start, (2., 0.));
+        assert_close(progress.distance, 1000., 1.);
+        assert_close(progress.velocity_made_good, 2., 0.01);
+        let eta = progress.eta.unwrap().as_secs();
+        assert!((499..=501).contains(&eta), "ETA {eta} s");
+
+        // North east at 2 m/s makes good about 1.41 m/s
+        let diagonal = 2. / libm::sqrt(2.);
+        let progress = Progress::toward(&landmark, start, (diagonal, diagonal));
+        assert_close(progress.velocity_made_good, diagonal, 0.01);
+
+        // Across, away and unknown
+        let progress = Progress::toward(&landmark, start, (0., 2.));
+        assert_close(progress.velocity_made_good, 0., 0.01);
+        assert_eq!(progress.eta, None);
+        let progress = Progress::toward(&landmark, start, (-2., 0.));
+        assert_close(progress.velocity_made_good, -2., 0.01);
+        assert_eq!(progress.eta, None);
+        let progress = Progress::toward(&landmark, start, (f64::NAN, f64::NAN));
+        assert!(progress.velocity_made_good.is_nan());
+        assert_eq!(progress.eta, None);
+    }
+}
@@ -0,0 +1,168 @@
+//! UBX framing against a recorded stream, fed in differently sized reads.
+//!
+//! `data/ubx_stream.ubx` holds, in order: an NMEA GGA sentence, NAV-PVT, ACK-ACK, a lone sync
+//! character, NAV-PVT with a corrupted checksum, an NMEA RMC sentence, NAV-SAT with a payload
+//! that contains a UBX header, and ACK-NAK.
+
+mod tests {
+    use compass::ubx::{checksum, encode, Ack, Framer, Stats};
+
+    const STREAM: &[u8] = include_bytes!("data/ubx_stream.ubx");
+
+    /// Class, id and the position of the payload in [`STREAM`] of each good frame.
+    const EXPECTED: &[(u8, u8, usize, usize)] = &[
+        (0x01, 0x07, 79, 92),
+        (0x05, 0x01, 179, 2),
+        (0x01, 0x35, 357, 24),
+        (0x05, 0x00, 389, 2),
+    ];
+
+    const EXPECTED_STATS: Stats = Stats {
+        packets: 4,
+        checksum_failures: 1,
+        // Both NMEA sentences, the lone sync character and the character after it, and the
+        // corrupted frame
+        dropped_bytes: 73 + 2 + 100 + 66,
+    };
+
+    fn feed(framer: &mut Framer, chunk_len: usize) {
+        let mut found = 0;
+        for chunk in STREAM.chunks(chunk_len) {
+            for &byte in chunk {
+                if let Some(frame) = framer.push(byte) {
+                    let (class, id, start, len) = EXPECTED[found];
+                    assert_eq!(frame.class(), class);
+                    assert_eq!(frame.id(), id);
+                    assert_eq!(frame.payload(), &STREAM[start..start + len]);
+                    found += 1;
+                }
+            }
+        }
+        assert_eq!(found, EXPECTED.len());
+    }
+
+    #[test]
+    fn recovers_frames_across_reads() {
+        for chunk_len in [1, 2, 3, 7, 64, STREAM.len()] {
+            let mut framer = Framer::new();
+            feed(&mut framer, chunk_len);
+            assert_eq!(framer.stats(), EXPECTED_STATS);
+        }
+    }
+
+    #[test]
+    fn keeps_counting_across_streams() {
+        let mut framer = Framer::new();
+        feed(&mut framer, 16);
+        feed(&mut framer, 16);
+        assert_eq!(
+            framer.stats(),
+            Stats {
+                packets: 2 * EXPECTED_STATS.packets,
+                checksum_failures: 2 * EXPECTED_STATS.checksum_failures,
+                dropped_bytes: 2 * EXPECTED_STATS.dropped_bytes,
+            }
+        );
+    }
+
+    #[test]
+    fn drops_oversized_frames() {
+        let mut framer = Framer::new();
+        // Claims a 0xffff byte payload
+        for byte in [0xb5, 0x62, 0x01, 0x07, 0xff, 0xff] {
+            assert!(framer.push(byte).is_none());
+        }
+        assert_eq!(framer.stats().dropped_bytes, 6);
+
+        // Back in sync for the next frame
+        let ack = &STREAM[183 - 10..183];
+        let frames = ack.iter().filter(|&&byte| framer.push(byte).is_some());
+        assert_eq!(frames.count(), 1);
+    }
+
+    #[test]
+    fn resyncs_after_a_bad_frame() {
+        let mut stream = [0u8; 64];
+        let mut len = 0;
+        let mut frame = [0u8; 16];
+        let mut append = |bytes: &[u8]| {
+            stream[len..len + bytes.len()].copy_from_slice(bytes);
+            len += bytes.len();
+        };
+
+        // CFG-RATE that lost a byte of its payload, so it takes the start of the ACK-ACK after it
+        let rate = encode(
+            0x06,
+            0x08,
+            &[0xe8, 0x03, 0x01, 0x00, 0x01, 0x00],
+            &mut frame,
+        );
+        append(&rate[..8]);
+        append(&rate[9..]);
+        append(encode(0x05, 0x01, &[0x06, 0x08], &mut frame));
+        // A length that swallows the whole of the ACK-NAK after it
+        append(&[0xb5, 0x62, 0x06, 0x08, 0x0c, 0x00]);
+        append(encode(0x05, 0x00, &[0x06, 0x8a], &mut frame));
+        append(encode(0x05, 0x01, &[0x06, 0x8a], &mut frame));
+
+        let mut framer = Framer::new();
+        let mut found = [None; 3];
+        let mut count = 0;
+        for &byte in &stream[..len] {
+            if let Some(frame) = framer.push(byte) {
+                found[count] = frame.ack_for(frame.payload()[0], frame.payload()[1]);
+                count += 1;
+            }
+        }
+        assert_eq!(found, [Some(Ack::Ack), Some(Ack::Nak), Some(Ack::Ack)]);
+        assert_eq!(
+            framer.stats(),
+            Stats {
+                packets: 3,
+                checksum_failures: 2,
+                dropped_bytes: 13 + 6,
+            }
+        );
+    }
+
+    #[test]
+    fn matches_acks_to_their_message() {
+        let mut acks = [None; 2];
+        let mut framer = Framer::new();
+        for &byte in STREAM {
+            if let Some(frame) = framer.push(byte) {
+                // CFG-MSG is acknowledged, CFG-VALSET rejected and CFG-PRT never answered
+                assert_eq!(frame.ack_for(0x06, 0x00), None);
+                if let Some(ack) = frame.ack_for(0x06, 0x01) {
+                    acks[0] = Some(ack);
+                }
+                if let Some(ack) = frame.ack_for(0x06, 0x8a) {
+                    acks[1] = Some(ack);
+                }
+            }
+        }
+        assert_eq!(acks, [Some(Ack::Ack), Some(Ack::Nak)]);
+    }
+
+    #[test]
+    fn checksum_matches_receiver() {
+        // CFG-RATE poll
+        assert_eq!(checksum(&[0x06, 0x08, 0x00, 0x00]), (0x0e, 0x30));
+        assert_eq!(checksum(&[]), (0, 0));
+    }
+    #[test]
+    fn encodes_frames() {
+        let mut buf = [0u8; 16];
+        // CFG-RATE poll
+        assert_eq!(
+            encode(0x06, 0x08, &[], &mut buf),
+            [0xb5, 0x62, 0x06, 0x08, 0x00, 0x00, 0x0e, 0x30]
+        );
+
+        let mut framer = Framer::new();
+        let mut frames = encode(0x06, 0x11, &[0x08, 0x01], &mut buf)
+            .iter()
+            .filter_map(|&byte| framer.push(byte).map(|frame| (frame.class(), frame.id())));
+        assert_eq!(frames.next(), Some((0x06, 0x11)));
+    }
+}
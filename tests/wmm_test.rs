@@ -0,0 +1,259 @@
+//! World Magnetic Model against NOAA's published WMM2020 test values, WMM2025 declinations and
+//! field strength, and decimal years.
+
+mod tests {
+    use chrono::NaiveDate;
+    use compass::wmm::{decimal_year, magnetic_field, Declination, Model};
+    use geoconv::{Degrees, Lle, Meters, Wgs84};
+
+    fn at(lat: f64, lon: f64, height_km: f64) -> Lle<Wgs84, Degrees> {
+        Lle::new(
+            Degrees::new(lat),
+            Degrees::new(lon),
+            Meters::new(height_km * 1000.),
+        )
+    }
+
+    /// WMM2020 from its `WMM.COF`, to check the evaluation against the model's published test
+    /// values.
+    static WMM2020: Model = Model {
+        epoch: 2020.0,
+        coefficients: [
+            (1, 0, -29404.5, 0.0, 6.7, 0.0),
+            (1, 1, -1450.7, 4652.9, 7.7, -25.1),
+            (2, 0, -2500.0, 0.0, -11.5, 0.0),
+            (2, 1, 2982.0, -2991.6, -7.1, -30.2),
+            (2, 2, 1676.8, -734.8, -2.2, -23.9),
+            (3, 0, 1363.9, 0.0, 2.8, 0.0),
+            (3, 1, -2381.0, -82.2, -6.2, 5.7),
+            (3, 2, 1236.2, 241.8, 3.4, -1.0),
+            (3, 3, 525.7, -542.9, -12.2, 1.1),
+            (4, 0, 903.1, 0.0, -1.1, 0.0),
+            (4, 1, 809.4, 282.0, -1.6, 0.2),
+            (4, 2, 86.2, -158.4, -6.0, 6.9),
+            (4, 3, -309.4, 199.8, 5.4, 3.7),
+            (4, 4, 47.9, -350.1, -5.5, -5.6),
+            (5, 0, -234.4, 0.0, -0.3, 0.0),
+            (5, 1, 363.1, 47.7, 0.6, 0.1),
+            (5, 2, 187.8, 208.4, -0.7, 2.5),
+            (5, 3, -140.7, -121.3, 0.1, -0.9),
+            (5, 4, -151.2, 32.2, 1.2, 3.0),
+            (5, 5, 13.7, 99.1, 1.0, 0.5),
+            (6, 0, 65.9, 0.0, -0.6, 0.0),
+            (6, 1, 65.6, -19.1, -0.4, 0.1),
+            (6, 2, 73.0, 25.0, 0.5, -1.8),
+            (6, 3, -121.5, 52.7, 1.4, -1.4),
+            (6, 4, -36.2, -64.4, -1.4, 0.9),
+            (6, 5, 13.5, 9.0, -0.0, 0.1),
+            (6, 6, -64.7, 68.1, 0.8, 1.0),
+            (7, 0, 80.6, 0.0, -0.1, 0.0),
+            (7, 1, -76.8, -51.4, -0.3, 0.5),
+            (7, 2, -8.3, -16.8, -0.1, 0.6),
+            (7, 3, 56.5, 2.3, 0.7, -0.7),
+            (7, 4, 15.8, 23.5, 0.2, -0.2),
+            (7, 5, 6.4, -2.2, -0.5, -1.2),
+            (7, 6, -7.2, -27.2, -0.8, 0.2),
+            (7, 7, 9.8, -1.9, 1.0, 0.3),
+            (8, 0, 23.6, 0.0, -0.1, 0.0),
+            (8, 1, 9.8, 8.4, 0.1, -0.3),
+            (8, 2, -17.5, -15.3, -0.1, 0.7),
+            (8, 3, -0.4, 12.8, 0.5, -0.2),
+            (8, 4, -21.1, -11.8, -0.1, 0.5),
+            (8, 5, 15.3, 14.9, 0.4, -0.3),
+            (8, 6, 13.7, 3.6, 0.5, -0.5),
+            (8, 7, -16.5, -6.9, 0.0, 0.4),
+            (8, 8, -0.3, 2.8, 0.4, 0.1),
+            (9, 0, 5.0, 0.0, -0.1, 0.0),
+            (9, 1, 8.2, -23.3, -0.2, -0.3),
+            (9, 2, 2.9, 11.1, -0.0, 0.2),
+            (9, 3, -1.4, 9.8, 0.4, -0.4),
+            (9, 4, -1.1, -5.1, -0.3, 0.4),
+            (9, 5, -13.3, -6.2, -0.0, 0.1),
+            (9, 6, 1.1, 7.8, 0.3, -0.0),
+            (9, 7, 8.9, 0.4, -0.0, -0.2),
+            (9, 8, -9.3, -1.5, -0.0, 0.5),
+            (9, 9, -11.9, 9.7, -0.4, 0.2),
+            (10, 0, -1.9, 0.0, 0.0, 0.0),
+            (10, 1, -6.2, 3.4, -0.0, -0.0),
+            (10, 2, -0.1, -0.2, -0.0, 0.1),
+            (10, 3, 1.7, 3.5, 0.2, -0.3),
+            (10, 4, -0.9, 4.8, -0.1, 0.1),
+            (10, 5, 0.6, -8.6, -0.2, -0.2),
+            (10, 6, -0.9, -0.1, -0.0, 0.1),
+            (10, 7, 1.9, -4.2, -0.1, -0.0),
+            (10, 8, 1.4, -3.4, -0.2, -0.1),
+            (10, 9, -2.4, -0.1, -0.1, 0.2),
+            (10, 10, -3.9, -8.8, -0.0, -0.0),
+            (11, 0, 3.0, 0.0, -0.0, 0.0),
+            (11, 1, -1.4, -0.0, -0.1, -0.0),
+            (11, 2, -2.5, 2.6, -0.0, 0.1),
+            (11, 3, 2.4, -0.5, 0.0, 0.0),
+            (11, 4, -0.9, -0.4, -0.0, 0.2),
+            (11, 5, 0.3, 0.6, -0.1, -0.0),
+            (11, 6, -0.7, -0.2, 0.0, 0.0),
+            (11, 7, -0.1, -1.7, -0.0, 0.1),
+            (11, 8, 1.4, -1.6, -0.1, -0.0),
+            (11, 9, -0.6, -3.0, -0.1, -0.1),
+            (11, 10, 0.2, -2.0, -0.1, 0.0),
+            (11, 11, 3.1, -2.6, -0.1, -0.0),
+            (12, 0, -2.0, 0.0, 0.0, 0.0),
+            (12, 1, -0.1, -1.2, -0.0, -0.0),
+            (12, 2, 0.5, 0.5, -0.0, 0.0),
+            (12, 3, 1.3, 1.3, 0.0, -0.1),
+            (12, 4, -1.2, -1.8, -0.0, 0.1),
+            (12, 5, 0.7, 0.1, -0.0, -0.0),
+            (12, 6, 0.3, 0.7, 0.0, 0.0),
+            (12, 7, 0.5, -0.1, -0.0, -0.0),
+            (12, 8, -0.2, 0.6, 0.0, 0.1),
+            (12, 9, -0.5, 0.2, -0.0, -0.0),
+            (12, 10, 0.1, -0.9, -0.0, -0.0),
+            (12, 11, -1.1, -0.0, -0.0, 0.0),
+            (12, 12, -0.3, 0.5, -0.1, -0.1),
+        ],
+    };
+
+    /// WMM2020 test values: (decimal year, height km, lat, lon, D °, I °, H nT, X nT, Y nT, Z nT,
+    /// F nT).
+    const TEST_VALUES: [[f64; 11]; 12] = [
+        [
+            2020.0, 0., 80., 0., -1.28, 83.14, 6572.0, 6570.4, -146.3, 54606.0, 55000.1,
+        ],
+        [
+            2020.0, 0., 0., 120., 0.16, -15.42, 39624.4, 39624.3, 109.9, -10932.5, 41104.9,
+        ],
+        [
+            2020.0, 0., -80., 240., 69.36, -72.20, 16853.8, 5940.6, 15772.1, -52480.8, 55120.6,
+        ],
+        [
+            2020.0, 100., 80., 0., -1.70, 83.19, 6264.5, 6261.8, -185.5, 52429.1, 52802.0,
+        ],
+        [
+            2020.0, 100., 0., 120., 0.16, -15.55, 37636.9, 37636.7, 104.9, -10474.8, 39067.3,
+        ],
+        [
+            2020.0, 100., -80., 240., 68.78, -72.37, 15875.4, 5744.9, 14799.5, -49969.4, 52430.6,
+        ],
+        [
+            2022.5, 0., 80., 0., 0.01, 83.19, 6529.9, 6529.9, 1.1, 54713.4, 55101.7,
+        ],
+        [
+            2022.5, 0., 0., 120., -0.06, -15.24, 39684.7, 39684.7, -42.2, -10809.5, 41130.5,
+        ],
+        [
+            2022.5, 0., -80., 240., 69.13, -72.09, 16885.0, 6016.5, 15776.7, -52251.6, 54912.1,
+        ],
+        [
+            2022.5, 100., 80., 0., -0.41, 83.24, 6224.2, 6224.0, -44.5, 52527.0, 52894.5,
+        ],
+        [
+            2022.5, 100., 0., 120., -0.05, -15.37, 37694.1, 37694.0, -35.3, -10362.0, 39092.4,
+        ],
+        [
+            2022.5, 100., -80., 240., 68.55, -72.27, 15904.1, 5815.0, 14803.0, -49755.3, 52235.4,
+        ],
+    ];
+
+    #[test]
+    fn published_test_values() {
+        for [year, height, lat, lon, d, i, h, x, y, z, f] in TEST_VALUES {
+            let field = WMM2020.magnetic_field(at(lat, lon, height), year);
+            let close = |value: f64, expected: f64, tolerance: f64| {
+                assert!(
+                    (value - expected).abs() < tolerance,
+                    "{year} {height} km {lat},{lon}: {value} != {expected}"
+                )
+            };
+
+            close(field.declination(), d, 0.01);
+            close(field.inclination(), i, 0.01);
+            close(field.horizontal_intensity(), h, 1.);
+            close(field.north, x, 1.);
+            close(field.east, y, 1.);
+            close(field.down, z, 1.);
+            close(field.total_intensity(), f, 1.);
+        }
+    }
+
+    /// (lat, lon, D °) in mid 2025, rounded.
+    const DECLINATIONS: [[f64; 3]; 5] = [
+        // Boulder
+        [40.015, -105.27, 7.7],
+        // Wellington
+        [-41.29, 174.78, 23.2],
+        // London
+        [51.5, -0.13, 1.0],
+        // Sydney
+        [-33.87, 151.2, 12.8],
+        // Tokyo
+        [35.68, 139.7, -7.9],
+    ];
+
+    #[test]
+    fn declination_at_known_places() {
+        for [lat, lon, d] in DECLINATIONS {
+            let field = magnetic_field(at(lat, lon, 0.), 2025.5);
+            assert!(
+                (field.declination() - d).abs() < 0.5,
+                "{lat},{lon}: {}",
+                field.declination()
+            );
+        }
+    }
+
+    #[test]
+    fn field_strength_and_dip() {
+        for lat in (-80..=80).step_by(10) {
+            for lon in (-180..180).step_by(15) {
+                let field = magnetic_field(at(lat as f64, lon as f64, 0.), 2025.0);
+                // The surface field is between about 22000 and 67000 nT everywhere
+                let total = field.total_intensity();
+                assert!((22_000. ..67_000.).contains(&total), "{lat},{lon}: {total}");
+                // and dips into the ground in the north away from the magnetic equator
+                if lat >= 40 {
+                    assert!(field.inclination() > 0., "{lat},{lon}");
+                } else if lat <= -40 {
+                    assert!(field.inclination() < 0., "{lat},{lon}");
+                }
+            }
+        }
+
+        // Weaker with height
+        let ground = magnetic_field(at(0., 120., 0.), 2025.0);
+        let above = magnetic_field(at(0., 120., 100.), 2025.0);
+        assert!(above.total_intensity() < ground.total_intensity());
+    }
+
+    #[test]
+    fn declination_kept_until_moved() {
+        let mut declination = Declination::new();
+        let wellington = declination.at(at(-41.29, 174.78, 0.), 2025.5);
+        assert_eq!(
+            wellington,
+            magnetic_field(at(-41.29, 174.78, 0.), 2025.5).declination()
+        );
+
+        // A short walk later the same value is handed back
+        assert_eq!(declination.at(at(-41.28, 174.79, 0.), 2025.51), wellington);
+
+        // Further away or later it is evaluated again
+        let auckland = declination.at(at(-36.85, 174.76, 0.), 2025.5);
+        assert_eq!(
+            auckland,
+            magnetic_field(at(-36.85, 174.76, 0.), 2025.5).declination()
+        );
+        assert_ne!(auckland, wellington);
+        let later = declination.at(at(-36.85, 174.76, 0.), 2029.5);
+        assert_eq!(
+            later,
+            magnetic_field(at(-36.85, 174.76, 0.), 2029.5).declination()
+        );
+    }
+
+    #[test]
+    fn decimal_years() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(decimal_year(date(2020, 1, 1)), 2020.0);
+        assert_eq!(decimal_year(date(2022, 7, 2)), 2022. + 182. / 365.);
+        assert_eq!(decimal_year(date(2024, 7, 2)), 2024.5);
+    }
+}